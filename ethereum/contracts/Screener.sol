@@ -0,0 +1,71 @@
+//SPDX-License-Identifier: MIT
+pragma solidity ^0.8.0;
+
+import "v2-core-1.0.1/contracts/interfaces/IUniswapV2Pair.sol";
+import "openzeppelin-contracts-5.3.0/contracts/token/ERC20/utils/SafeERC20.sol";
+
+// never deployed. gofi puts the runtime code at an unused address with an eth_call state
+// override, along with a balance of the quote token, and buys the pair's other token and
+// sells it straight back to see what its transfers really deliver.
+contract Screener {
+    using SafeERC20 for IERC20;
+
+    // amountIn of quote in and back out again. amounts are the buy's (expected, received)
+    // followed by the sell's, expected being what the pair's reserves promise after the
+    // feeBps its dex takes.
+    function screen(
+        address pair_addr,
+        address quote,
+        uint256 amountIn,
+        uint16 feeBps
+    ) external returns (uint256[4] memory amounts) {
+        IUniswapV2Pair pair = IUniswapV2Pair(pair_addr);
+        bool quoteIs0 = pair.token0() == quote;
+        address token = quoteIs0 ? pair.token1() : pair.token0();
+        (amounts[0], amounts[1]) = trade(pair, quote, token, quoteIs0, amountIn, feeBps);
+        (amounts[2], amounts[3]) = trade(pair, token, quote, !quoteIs0, amounts[1], feeBps);
+    }
+
+    // amountIn of tokenIn for tokenOut, paying for what reached the pair
+    function trade(
+        IUniswapV2Pair pair,
+        address tokenIn,
+        address tokenOut,
+        bool in0,
+        uint256 amountIn,
+        uint16 feeBps
+    ) internal returns (uint256 expected, uint256 received) {
+        (uint112 r0, uint112 r1, ) = pair.getReserves();
+        (uint256 reserveIn, uint256 reserveOut) = in0
+            ? (uint256(r0), uint256(r1))
+            : (uint256(r1), uint256(r0));
+        expected = getAmountOut(amountIn, reserveIn, reserveOut, feeBps);
+        IERC20(tokenIn).safeTransfer(address(pair), amountIn);
+        uint256 arrived = IERC20(tokenIn).balanceOf(address(pair)) - reserveIn;
+        uint256 amountOut = getAmountOut(arrived, reserveIn, reserveOut, feeBps);
+        uint256 before = IERC20(tokenOut).balanceOf(address(this));
+        if (in0) {
+            pair.swap(0, amountOut, address(this), new bytes(0));
+        } else {
+            pair.swap(amountOut, 0, address(this), new bytes(0));
+        }
+        received = IERC20(tokenOut).balanceOf(address(this)) - before;
+    }
+
+    // UniswapV2Library.getAmountOut, with the fee in basis points
+    function getAmountOut(
+        uint256 amountIn,
+        uint256 reserveIn,
+        uint256 reserveOut,
+        uint16 feeBps
+    ) internal pure returns (uint256 amountOut) {
+        uint256 amountInWithFee = amountIn * (10000 - feeBps);
+        uint256 numerator = amountInWithFee * reserveOut;
+        uint256 denominator = (reserveIn * 10000) + amountInWithFee;
+        if (denominator > 0) {
+            amountOut = numerator / denominator;
+        } else {
+            amountOut = 0;
+        }
+    }
+}
@@ -18,51 +18,81 @@ contract UniSwab {
         _;
     }
 
-    function _swap(
-        uint256 amount0In,
-        uint256 amount1In,
-        IUniswapV2Pair pool
-    ) internal onlyOwner returns (uint256, uint256) {
-        if(amount0In > 0) {
-            IERC20(pool.token0()).safeTransferFrom(msg.sender, address(pool), amount0In);
-        }
-        if(amount1In > 0) {
-            IERC20(pool.token1()).safeTransferFrom(msg.sender, address(pool), amount1In);
-        }
-        (uint112 reserve0, uint112 reserve1, ) = pool.getReserves();
-        uint256 amount0Out = 0;
-        uint256 amount1Out = 0;
-        amount1Out = getAmountOut(amount0In, reserve0, reserve1);
-        amount0Out = getAmountOut(amount1In, reserve1, reserve0);
-        pool.swap(amount0Out, amount1Out, owner, new bytes(0));
-        return (amount0Out, amount1Out);
-    }
-
-
+    // token1 into pool0 for token0, which goes straight to pool1 for token1, or token0 in
+    // and out through token1 when zeroForOne. both pools must share token0 and token1,
+    // pool0 being the cheaper one. the fees are what each pool's dex takes from the
+    // amount in, in basis points, 30 for uniswap v2.
     function swab(
-        uint256 amount0In,
-        uint256 amount1In,
+        uint256 amountIn,
         address pool0_addr,
-        address pool1_addr
+        address pool1_addr,
+        uint16 fee0Bps,
+        uint16 fee1Bps,
+        bool zeroForOne
     ) public onlyOwner {
-        // Step 1
-        (uint256 amountMid0Out, uint256 amountMid1Out) = _swap(amount0In, amount1In, IUniswapV2Pair(pool0_addr));
+        IUniswapV2Pair pool0 = IUniswapV2Pair(pool0_addr);
+        IUniswapV2Pair pool1 = IUniswapV2Pair(pool1_addr);
+        uint256 amountOut;
+
+        if (zeroForOne) {
+            // Step 1
+            IERC20(pool0.token0()).safeTransferFrom(msg.sender, pool0_addr, amountIn);
+            (uint112 a0, uint112 a1, ) = pool0.getReserves();
+            uint256 amountMid = getAmountOut(amountIn, a0, a1, fee0Bps);
+            pool0.swap(0, amountMid, pool1_addr, new bytes(0));
+
+            // Step 2
+            (uint112 b0, uint112 b1, ) = pool1.getReserves();
+            amountOut = getAmountOut(amountMid, b1, b0, fee1Bps);
+            pool1.swap(amountOut, 0, owner, new bytes(0));
+        } else {
+            // Step 1
+            IERC20(pool0.token1()).safeTransferFrom(msg.sender, pool0_addr, amountIn);
+            (uint112 a0, uint112 a1, ) = pool0.getReserves();
+            uint256 amountMid = getAmountOut(amountIn, a1, a0, fee0Bps);
+            pool0.swap(amountMid, 0, pool1_addr, new bytes(0));
+
+            // Step 2
+            (uint112 b0, uint112 b1, ) = pool1.getReserves();
+            amountOut = getAmountOut(amountMid, b0, b1, fee1Bps);
+            pool1.swap(0, amountOut, owner, new bytes(0));
+        }
+        require(amountOut > amountIn, "UniSwab: no profit");
+    }
 
-        // Step 2
-        (uint256 amount0Out, uint256 amount1Out) = _swap(amountMid0Out, amountMid1Out,  IUniswapV2Pair(pool1_addr));
-        require(amount0Out >= amount0In, "UniSwab: no profit (amount 0)");
-        require(amount1Out >= amount1In, "UniSwab: no profit (amount 1)");
+    // one pool, token0 for token1 when zeroForOne, for rebalancing the owner's wallet.
+    function swap(
+        uint256 amountIn,
+        uint256 amountOutMin,
+        address pool_addr,
+        bool zeroForOne,
+        uint16 feeBps
+    ) public onlyOwner {
+        IUniswapV2Pair pool = IUniswapV2Pair(pool_addr);
+        (uint112 r0, uint112 r1, ) = pool.getReserves();
+        if (zeroForOne) {
+            IERC20(pool.token0()).safeTransferFrom(msg.sender, pool_addr, amountIn);
+            uint256 amountOut = getAmountOut(amountIn, r0, r1, feeBps);
+            require(amountOut >= amountOutMin, "UniSwab: slippage");
+            pool.swap(0, amountOut, owner, new bytes(0));
+        } else {
+            IERC20(pool.token1()).safeTransferFrom(msg.sender, pool_addr, amountIn);
+            uint256 amountOut = getAmountOut(amountIn, r1, r0, feeBps);
+            require(amountOut >= amountOutMin, "UniSwab: slippage");
+            pool.swap(amountOut, 0, owner, new bytes(0));
+        }
     }
 
     // given an input amount of an asset and pair reserves, returns the maximum output amount of the other asset
     function getAmountOut(
         uint256 amountIn,
         uint256 reserveIn,
-        uint256 reserveOut
+        uint256 reserveOut,
+        uint16 feeBps
     ) internal pure returns (uint256 amountOut) {
-        uint256 amountInWithFee = amountIn * 997;
+        uint256 amountInWithFee = amountIn * (10000 - feeBps);
         uint256 numerator = amountInWithFee * reserveOut;
-        uint256 denominator = (reserveIn * 1000) + amountInWithFee;
+        uint256 denominator = (reserveIn * 10000) + amountInWithFee;
         if ( denominator > 0 ) {
             amountOut = numerator / denominator;
         } else {
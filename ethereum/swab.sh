@@ -4,14 +4,7 @@ POOL0=$(cat logs/AC1)
 POOL1=$(cat logs/AC2)
 SWAB=$(cat logs/SWAB)
 
-if [[ -z "$1" ]];
-then
-AX_IN=40371
-AY_IN=0
-else
-AX_IN=$1
-AY_IN=$2
-fi
+AY_IN=${1:-40371}
 
 balances() {
 COIN=`eth contract:call erc20@usdona 'balanceOf("'${HAT2}'")'`
@@ -44,8 +37,8 @@ STARTC=`eth contract:call erc20@usdonc 'balanceOf("'${HAT2}'")'`
 balances
 reserves
 # SWAB!
-echo swab\(${AX_IN}, ${AY_IN}, ${POOL0}, ${POOL1}\) $SWAB
-eth contract:send --pk hat2 swab@${SWAB} 'swab('${AX_IN}', '${AY_IN}', "'${POOL0}'", "'${POOL1}'")'
+echo swab\(${AY_IN}, ${POOL0}, ${POOL1}, 30, 30, false\) $SWAB
+eth contract:send --pk hat2 swab@${SWAB} 'swab('${AY_IN}', "'${POOL0}'", "'${POOL1}'", 30, 30, false)'
 balances
 reserves
 ENDC=`eth contract:call erc20@usdonc 'balanceOf("'${HAT2}'")'`
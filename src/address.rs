@@ -0,0 +1,108 @@
+use alloy::primitives::Address;
+use serde::{Deserialize, Deserializer, de};
+
+// addresses come in from config.yaml, the command line and whatever the indexer wrote to
+// the pools and coins tables. they are Address from then on and only turn back into text
+// through to_db.
+
+// hex with or without 0x. all lower or all upper case is taken as is, mixed case has to
+// be a valid eip-55 checksum so a mistyped address is caught.
+pub fn parse(text: &str) -> Result<Address, String> {
+    let text = text.trim();
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    let address = digits
+        .parse::<Address>()
+        .map_err(|err| format!("address {}: {}", text, err))?;
+    let lower = digits.chars().any(|c| c.is_ascii_lowercase());
+    let upper = digits.chars().any(|c| c.is_ascii_uppercase());
+    if lower && upper && address.to_checksum(None)[2..] != *digits {
+        return Err(format!("address {}: bad eip-55 checksum", text));
+    }
+    Ok(address)
+}
+
+// a column written by the indexer, in any case and with or without 0x
+pub fn from_db(text: &str) -> Address {
+    parse(&text.to_lowercase()).unwrap()
+}
+
+// the form gofi writes and binds as a sql parameter: lowercase hex without 0x.
+// columns the indexer wrote are compared as lower(right(column, 40)) to match it.
+pub fn to_db(address: Address) -> String {
+    hex::encode(address)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Address, D::Error> {
+    parse(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+}
+
+pub fn deserialize_option<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Address>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|text| parse(&text).map_err(de::Error::custom))
+        .transpose()
+}
+
+pub fn deserialize_vec<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Address>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|text| parse(text).map_err(de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WETH: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+
+    #[test]
+    fn test_parse() {
+        let weth = parse(WETH).unwrap();
+        assert_eq!(parse(&WETH.to_lowercase()), Ok(weth));
+        assert_eq!(parse(&WETH[2..].to_lowercase()), Ok(weth));
+        assert_eq!(parse(&WETH[2..].to_uppercase()), Ok(weth));
+        assert_eq!(parse(&format!(" {} ", WETH)), Ok(weth));
+        // one letter with the wrong case
+        let typo = WETH.replacen("aaA", "aaa", 1);
+        assert!(parse(&typo).unwrap_err().contains("checksum"));
+        assert!(parse("0x1234").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_db() {
+        let weth = parse(WETH).unwrap();
+        assert_eq!(to_db(weth), "c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2");
+        assert_eq!(from_db(&to_db(weth)), weth);
+        // the indexer may have stored a checksum that got its case mangled
+        assert_eq!(from_db(&WETH.replacen("aaA", "aaa", 1)), weth);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        #[derive(Deserialize)]
+        struct Addresses {
+            #[serde(deserialize_with = "deserialize")]
+            one: Address,
+            #[serde(default, deserialize_with = "deserialize_option")]
+            maybe: Option<Address>,
+            #[serde(deserialize_with = "deserialize_vec")]
+            many: Vec<Address>,
+        }
+        let yaml = format!("one: {}\nmany: [{}, {}]", WETH, WETH, &WETH[2..]);
+        let addresses: Addresses = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(addresses.one, parse(WETH).unwrap());
+        assert_eq!(addresses.maybe, None);
+        assert_eq!(addresses.many, vec![addresses.one, addresses.one]);
+        let typo = WETH.replacen("aaA", "aaa", 1);
+        let yaml = format!("one: {}\nmany: []", typo);
+        assert!(serde_yaml::from_str::<Addresses>(&yaml).is_err());
+    }
+}
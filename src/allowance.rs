@@ -0,0 +1,310 @@
+use alloy::primitives::{Address, U256, utils::format_units};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::chain::Chain;
+use crate::config::Config;
+use crate::rebalance;
+use crate::repository::PairRepository;
+use crate::{Coin, Pair};
+
+// uniswab pulls the coin1 of a swab from our wallet with transferFrom, so every coin1 scan
+// may swab needs an allowance for it, and so does coin0 when the wallet is rebalanced.
+// exact approves the wallet's balance of the coin and no more, unlimited approves once
+// for good. coins in exclude_addresses get theirs revoked.
+
+#[derive(clap::Args)]
+pub struct ApproveArgs {
+    /// overrides the config's approval_policy
+    #[arg(long, value_enum)]
+    policy: Option<Policy>,
+    /// list the approvals without sending them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    Exact,
+    #[default]
+    Unlimited,
+}
+
+// an approve(uniswab, amount) that is due, amount 0 revokes
+pub struct Approval {
+    pub coin: Coin,
+    pub allowance: U256,
+    pub amount: U256,
+}
+
+pub fn run(
+    config: &Config,
+    db: &mut impl PairRepository,
+    chain: &impl Chain,
+    owner: Address,
+    args: &ApproveArgs,
+) -> Result<(), String> {
+    let policy = args.policy.unwrap_or(config.approval_policy);
+    let pairs = db
+        .pairs_with(config.preferred_base_token)
+        .map_err(|err| err.to_string())?;
+    let approvals = plan(config, chain, policy, &pairs, owner)?;
+    if approvals.is_empty() {
+        println!("allowances are up to date");
+    }
+    for approval in approvals.iter() {
+        println!(
+            "{} {} allowance {} -> {}",
+            approval.coin.symbol,
+            approval.coin.contract_address,
+            units(&approval.coin, approval.allowance),
+            units(&approval.coin, approval.amount)
+        );
+    }
+    if !args.dry_run {
+        apply(chain, &approvals)?;
+    }
+    Ok(())
+}
+
+// the approvals policy calls for, reading allowances and balances of owner
+pub fn plan(
+    config: &Config,
+    chain: &impl Chain,
+    policy: Policy,
+    pairs: &[Pair],
+    owner: Address,
+) -> Result<Vec<Approval>, String> {
+    let (wanted, excluded) = coins(config, pairs);
+    let mut approvals = vec![];
+    for coin in wanted {
+        let allowance = chain.allowance(coin.contract_address, owner)?;
+        let balance = chain.token_balance(coin.contract_address, owner)?;
+        if let Some(amount) = target(policy, allowance, balance) {
+            approvals.push(Approval {
+                coin,
+                allowance,
+                amount,
+            });
+        }
+    }
+    for coin in excluded {
+        let allowance = chain.allowance(coin.contract_address, owner)?;
+        if !allowance.is_zero() {
+            approvals.push(Approval {
+                coin,
+                allowance,
+                amount: U256::ZERO,
+            });
+        }
+    }
+    Ok(approvals)
+}
+
+pub fn apply(chain: &impl Chain, approvals: &[Approval]) -> Result<(), String> {
+    for approval in approvals {
+        // usdt and others refuse to change one non-zero allowance into another
+        if !approval.allowance.is_zero() && !approval.amount.is_zero() {
+            approve(chain, &approval.coin, U256::ZERO)?;
+        }
+        approve(chain, &approval.coin, approval.amount)?;
+    }
+    Ok(())
+}
+
+fn approve(chain: &impl Chain, coin: &Coin, amount: U256) -> Result<(), String> {
+    let receipt = chain.approve(coin.contract_address, amount)?;
+    if !receipt.status {
+        return Err(format!(
+            "approve {} {} reverted in {}",
+            coin.symbol, amount, receipt.tx_hash
+        ));
+    }
+    info!(
+        token = %coin.symbol,
+        amount = %units(coin, amount),
+        tx = %receipt.tx_hash,
+        "approved"
+    );
+    Ok(())
+}
+
+// the amount to approve, None when the allowance is fine as it is
+pub fn target(policy: Policy, allowance: U256, balance: U256) -> Option<U256> {
+    match policy {
+        Policy::Exact => (allowance != balance).then_some(balance),
+        // some tokens count an unlimited allowance down too, top it up past half way
+        Policy::Unlimited => (allowance < U256::MAX >> 1).then_some(U256::MAX),
+    }
+}
+
+// (the coins uniswab takes from the wallet for pairs scan would swab, the excluded coins
+// of pairs it skips). rebalancing sells coin0 as well, so either side may hold an allowance.
+fn coins(config: &Config, pairs: &[Pair]) -> (Vec<Coin>, Vec<Coin>) {
+    let mut wanted: Vec<Coin> = vec![];
+    let mut excluded: Vec<Coin> = vec![];
+    let add = |list: &mut Vec<Coin>, coin: &Coin| {
+        if !list
+            .iter()
+            .any(|listed| listed.contract_address == coin.contract_address)
+        {
+            list.push(coin.clone());
+        }
+    };
+    for pair in pairs {
+        let pool = &pair.pool0.pool;
+        if crate::tradable(config, pair) {
+            add(&mut wanted, &pool.coin1);
+            if rebalance::enabled(config) {
+                add(&mut wanted, &pool.coin0);
+            }
+        } else {
+            for coin in [&pool.coin0, &pool.coin1] {
+                if config.exclude_addresses.contains(&coin.contract_address) {
+                    add(&mut excluded, coin);
+                }
+            }
+        }
+    }
+    (wanted, excluded)
+}
+
+fn units(coin: &Coin, amount: U256) -> String {
+    if amount == U256::MAX {
+        return "unlimited".to_owned();
+    }
+    format_units(amount, coin.decimals as u8).unwrap_or_else(|_| amount.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{BalanceSource, FakeChain};
+    use crate::fixture::{COIN0, COIN1, PairBuilder, coin};
+
+    const ME: Address = Address::repeat_byte(0xee);
+    const COIN2: Address = Address::with_last_byte(0xc2);
+
+    fn config(exclude: &[Address]) -> Config {
+        let mut config: Config = serde_yaml::from_str(&format!(
+            "geth_url: http://localhost:8545
+pg_url: postgres://localhost/gofi
+eth_priv_key: 0000000000000000000000000000000000000000000000000000000000000001
+uniswab: 000000000000000000000000000000000000005b
+preferred_base_token: {COIN0}
+preferred_coin_token: {COIN1}
+minimum_out: 0
+tx_gas: 300000"
+        ))
+        .unwrap();
+        config.exclude_addresses = exclude.to_vec();
+        config
+    }
+
+    // USDONA/USDONC, which scan swabs, and USDONA/USDOND
+    fn pairs() -> Vec<Pair> {
+        let usdond = PairBuilder::new()
+            .coins(coin(COIN0, "USDONA", 18), coin(COIN2, "USDOND", 6))
+            .build();
+        vec![
+            PairBuilder::new().build(),
+            PairBuilder::new().build(),
+            usdond,
+        ]
+    }
+
+    #[test]
+    fn test_target() {
+        let (zero, some, max) = (U256::ZERO, U256::from(500), U256::MAX);
+        assert_eq!(target(Policy::Exact, zero, some), Some(some));
+        assert_eq!(target(Policy::Exact, some, some), None);
+        // more than the balance is cut back
+        assert_eq!(target(Policy::Exact, max, some), Some(some));
+        assert_eq!(target(Policy::Exact, zero, zero), None);
+        assert_eq!(target(Policy::Unlimited, zero, some), Some(max));
+        assert_eq!(target(Policy::Unlimited, some, zero), Some(max));
+        assert_eq!(target(Policy::Unlimited, max - some, some), None);
+    }
+
+    #[test]
+    fn test_plan() {
+        let mut chain = FakeChain::default();
+        chain.set_balance(COIN1, ME, U256::from(1000));
+        chain.allowances.get_mut().insert(COIN1, U256::ZERO);
+        chain.allowances.get_mut().insert(COIN2, U256::from(7));
+
+        let approvals = plan(&config(&[]), &chain, Policy::Unlimited, &pairs(), ME).unwrap();
+        // the usdond pairs are not the preferred coin and not excluded, left alone
+        assert_eq!(approvals.len(), 1);
+        assert_eq!(approvals[0].coin.symbol, "USDONC");
+        assert_eq!(approvals[0].amount, U256::MAX);
+
+        let approvals = plan(&config(&[COIN2]), &chain, Policy::Exact, &pairs(), ME).unwrap();
+        let planned = approvals
+            .iter()
+            .map(|approval| (approval.coin.contract_address, approval.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(planned, [(COIN1, U256::from(1000)), (COIN2, U256::ZERO)]);
+
+        // coin0 too when the wallet is rebalanced
+        let mut rebalanced = config(&[]);
+        rebalanced.rebalance_coin_min = Some(1.0);
+        chain.allowances.get_mut().insert(COIN0, U256::ZERO);
+        let approvals = plan(&rebalanced, &chain, Policy::Unlimited, &pairs(), ME).unwrap();
+        let coins = approvals
+            .iter()
+            .map(|approval| approval.coin.contract_address)
+            .collect::<Vec<_>>();
+        assert_eq!(coins, [COIN1, COIN0]);
+
+        // an excluded coin1 is revoked rather than approved
+        let approvals = plan(&config(&[COIN1]), &chain, Policy::Exact, &pairs(), ME).unwrap();
+        assert!(approvals.is_empty());
+        chain.allowances.get_mut().insert(COIN1, U256::from(3));
+        let approvals = plan(&config(&[COIN1]), &chain, Policy::Exact, &pairs(), ME).unwrap();
+        assert_eq!(approvals[0].amount, U256::ZERO);
+    }
+
+    #[test]
+    fn test_plan_revokes_excluded_coin0() {
+        let chain = FakeChain::default();
+        chain.allowances.borrow_mut().insert(COIN0, U256::from(5));
+        chain.allowances.borrow_mut().insert(COIN1, U256::ZERO);
+        let approvals = plan(&config(&[COIN0]), &chain, Policy::Exact, &pairs(), ME).unwrap();
+        let planned = approvals
+            .iter()
+            .map(|approval| (approval.coin.contract_address, approval.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(planned, [(COIN0, U256::ZERO)]);
+    }
+
+    #[test]
+    fn test_apply() {
+        let chain = FakeChain::default();
+        chain.allowances.borrow_mut().insert(COIN1, U256::from(3));
+        let approvals = [
+            Approval {
+                coin: coin(COIN1, "USDONC", 18),
+                allowance: U256::from(3),
+                amount: U256::from(1000),
+            },
+            Approval {
+                coin: coin(COIN2, "USDOND", 6),
+                allowance: U256::from(7),
+                amount: U256::ZERO,
+            },
+        ];
+        apply(&chain, &approvals).unwrap();
+        // through zero first
+        assert_eq!(
+            *chain.approvals.borrow(),
+            [
+                (COIN1, U256::ZERO),
+                (COIN1, U256::from(1000)),
+                (COIN2, U256::ZERO)
+            ]
+        );
+        assert_eq!(chain.allowance(COIN1, ME), Ok(U256::from(1000)));
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use alloy::primitives::Address;
+use redis::{Commands, Connection, RedisResult, Script};
+
+use crate::{Match, Reserve, address};
+
+// long enough to cover sending the swab and waiting for its receipt
+pub const PAIR_LOCK_SECONDS: u64 = 120;
+const PREFIX: &str = "gofi";
+
+// only replace a stored reserve when the incoming block is not older
+const RESERVE_SET: &str = r"
+local stored = redis.call('HGET', KEYS[1], 'block_number')
+if stored and tonumber(stored) > tonumber(ARGV[3]) then
+  return 0
+end
+redis.call('HSET', KEYS[1], 'x', ARGV[1], 'y', ARGV[2], 'block_number', ARGV[3], 'block_timestamp', ARGV[4])
+return 1
+";
+
+// only the holder of the lock token may release it
+const LOCK_RELEASE: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+  return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+// keys are gofi:<chain_id>:.., pool addresses only mean something on their own chain
+pub struct Cache {
+    conn: Connection,
+    chain_id: u64,
+    match_ttl_secs: u64,
+}
+
+pub struct PairLock {
+    key: String,
+    token: String,
+}
+
+impl Cache {
+    // block_seconds is the chain's block time, match_ttl_blocks of them make the match ttl
+    pub fn connect(
+        url: &str,
+        chain_id: u64,
+        match_ttl_blocks: u64,
+        block_seconds: u64,
+    ) -> RedisResult<Cache> {
+        let client = redis::Client::open(url)?;
+        Ok(Cache {
+            conn: client.get_connection()?,
+            chain_id,
+            match_ttl_secs: match_ttl_blocks * block_seconds,
+        })
+    }
+
+    // returns false when a newer reserve is already stored for the pool
+    pub fn publish_reserve(
+        &mut self,
+        pool_address: Address,
+        reserve: &Reserve,
+    ) -> RedisResult<bool> {
+        let stored: i32 = Script::new(RESERVE_SET)
+            .key(reserve_key(self.chain_id, pool_address))
+            .arg(reserve.x.to_string())
+            .arg(reserve.y.to_string())
+            .arg(reserve.block_number)
+            .arg(reserve.block_timestamp)
+            .invoke(&mut self.conn)?;
+        Ok(stored == 1)
+    }
+
+    pub fn reserve(&mut self, pool_address: Address) -> RedisResult<Option<Reserve>> {
+        let fields: HashMap<String, String> = self
+            .conn
+            .hgetall(reserve_key(self.chain_id, pool_address))?;
+        if fields.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Reserve {
+            contract_address: pool_address,
+            x: fields["x"].parse().unwrap(),
+            y: fields["y"].parse().unwrap(),
+            block_number: fields["block_number"].parse().unwrap(),
+            block_timestamp: fields["block_timestamp"].parse().unwrap(),
+        }))
+    }
+
+    pub fn publish_match(&mut self, r#match: &Match) -> RedisResult<()> {
+        let key = match_key(
+            self.chain_id,
+            r#match.pair.pool0.pool.contract_address,
+            r#match.pair.pool1.pool.contract_address,
+        );
+        let block_number = std::cmp::max(
+            r#match.pair.pool0.reserve.block_number,
+            r#match.pair.pool1.reserve.block_number,
+        );
+        redis::pipe()
+            .atomic()
+            .hset_multiple(
+                &key,
+                &[
+                    ("pool0_ay_in", r#match.pool0_ay_in.to_string()),
+                    ("pool0_ax_out", r#match.pool0_ax_out.to_string()),
+                    ("pool1_ay_out", r#match.pool1_ay_out.to_string()),
+                    ("profit", r#match.profit().to_string()),
+                    ("block_number", block_number.to_string()),
+                ],
+            )
+            .ignore()
+            .expire(&key, self.match_ttl_secs as i64)
+            .ignore()
+            .query::<()>(&mut self.conn)
+    }
+
+    pub fn match_fields(
+        &mut self,
+        pool0_address: Address,
+        pool1_address: Address,
+    ) -> RedisResult<HashMap<String, String>> {
+        self.conn
+            .hgetall(match_key(self.chain_id, pool0_address, pool1_address))
+    }
+
+    // None when another worker already holds the lock for this pool pair
+    pub fn lock_pair(
+        &mut self,
+        pool0_address: Address,
+        pool1_address: Address,
+        ttl_secs: u64,
+    ) -> RedisResult<Option<PairLock>> {
+        let key = lock_key(self.chain_id, pool0_address, pool1_address);
+        let token = lock_token();
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(&key)
+            .arg(&token)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl_secs)
+            .query(&mut self.conn)?;
+        Ok(acquired.map(|_| PairLock { key, token }))
+    }
+
+    pub fn unlock_pair(&mut self, lock: PairLock) -> RedisResult<bool> {
+        let released: i32 = Script::new(LOCK_RELEASE)
+            .key(lock.key)
+            .arg(lock.token)
+            .invoke(&mut self.conn)?;
+        Ok(released == 1)
+    }
+}
+
+fn reserve_key(chain_id: u64, pool_address: Address) -> String {
+    format!(
+        "{}:{}:reserve:{}",
+        PREFIX,
+        chain_id,
+        address::to_db(pool_address)
+    )
+}
+
+// the pool pair is unordered so both scan directions share a key
+fn pair_id(pool0_address: Address, pool1_address: Address) -> String {
+    let (a, b) = if pool0_address < pool1_address {
+        (pool0_address, pool1_address)
+    } else {
+        (pool1_address, pool0_address)
+    };
+    format!("{}:{}", address::to_db(a), address::to_db(b))
+}
+
+fn match_key(chain_id: u64, pool0_address: Address, pool1_address: Address) -> String {
+    format!(
+        "{}:{}:match:{}",
+        PREFIX,
+        chain_id,
+        pair_id(pool0_address, pool1_address)
+    )
+}
+
+fn lock_key(chain_id: u64, pool0_address: Address, pool1_address: Address) -> String {
+    format!(
+        "{}:{}:lock:{}",
+        PREFIX,
+        chain_id,
+        pair_id(pool0_address, pool1_address)
+    )
+}
+
+fn lock_token() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    format!("{}:{}", std::process::id(), nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::U256;
+
+    // these tests talk to a redis-server on localhost, e.g.
+    // REDIS_URL=redis://127.0.0.1/15 cargo test -- --ignored
+    fn test_cache() -> Cache {
+        let url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1/15".to_owned());
+        Cache::connect(&url, 1, 2, 12).unwrap()
+    }
+
+    const POOL_A: Address = Address::repeat_byte(0xaa);
+    const POOL_B: Address = Address::repeat_byte(0xbb);
+
+    fn reserve(block_number: u32) -> Reserve {
+        Reserve {
+            contract_address: POOL_A,
+            x: U256::from(310000),
+            y: U256::from(210000),
+            block_number,
+            block_timestamp: 1,
+        }
+    }
+
+    #[test]
+    fn test_pair_id() {
+        assert_eq!(pair_id(POOL_A, POOL_B), pair_id(POOL_B, POOL_A));
+        assert_eq!(
+            pair_id(POOL_B, POOL_A),
+            format!("{}:{}", "aa".repeat(20), "bb".repeat(20))
+        );
+    }
+
+    #[test]
+    #[ignore = "requires a local redis-server"]
+    fn test_publish_reserve() {
+        let mut cache = test_cache();
+        let _: () = cache.conn.del(reserve_key(1, POOL_A)).unwrap();
+        assert!(cache.publish_reserve(POOL_A, &reserve(10)).unwrap());
+        assert!(!cache.publish_reserve(POOL_A, &reserve(9)).unwrap());
+        assert!(cache.publish_reserve(POOL_A, &reserve(11)).unwrap());
+        let stored = cache.reserve(POOL_A).unwrap().unwrap();
+        assert_eq!(stored.block_number, 11);
+        assert_eq!(stored.x, U256::from(310000));
+    }
+
+    #[test]
+    #[ignore = "requires a local redis-server"]
+    fn test_lock_pair() {
+        let mut cache = test_cache();
+        let _: () = cache.conn.del(lock_key(1, POOL_A, POOL_B)).unwrap();
+        let lock = cache.lock_pair(POOL_A, POOL_B, 5).unwrap().unwrap();
+        assert!(cache.lock_pair(POOL_B, POOL_A, 5).unwrap().is_none());
+        assert!(cache.unlock_pair(lock).unwrap());
+        let lock = cache.lock_pair(POOL_B, POOL_A, 5).unwrap();
+        assert!(lock.is_some());
+        assert!(cache.unlock_pair(lock.unwrap()).unwrap());
+    }
+}
@@ -0,0 +1,551 @@
+use alloy::{
+    consensus::Transaction,
+    eips::BlockId,
+    primitives::{Address, Bytes, U256},
+    providers::Provider,
+    rpc::types::TransactionRequest,
+    sol_types::SolCall,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::contracts::{ERC20, UniSwab, UniswapV2Pair};
+use crate::metrics;
+use crate::nonce::{self, NonceManager, Replace};
+use crate::revert::Replay;
+
+// what the pipeline reads from and sends to the node. Rpc implements them over an alloy
+// provider, FakeChain keeps everything in memory for tests.
+pub trait ReserveSource {
+    // (reserve0, reserve1, block timestamp of the last sync)
+    fn reserves(&self, pool: Address) -> Result<(U256, U256, u32), String>;
+    // the same at the end of block
+    fn reserves_at(&self, pool: Address, block: u64) -> Result<(U256, U256, u32), String>;
+}
+
+pub trait BalanceSource {
+    fn eth_balance(&self, owner: Address) -> Result<U256, String>;
+    fn token_balance(&self, token: Address, owner: Address) -> Result<U256, String>;
+    // what uniswab may still transferFrom owner
+    fn allowance(&self, token: Address, owner: Address) -> Result<U256, String>;
+}
+
+pub trait TxSender {
+    fn gas_price(&self) -> Result<u128, String>;
+    // send uniswab.swab and wait for the receipt. fees are pool0's and pool1's in basis
+    // points, zero_for_one puts token0 in rather than token1. still_wanted decides
+    // whether a stuck swab is sped up or cancelled.
+    fn send_swab(
+        &self,
+        amount: U256,
+        pool0: Address,
+        pool1: Address,
+        fees: (u8, u8),
+        zero_for_one: bool,
+        still_wanted: &dyn Fn() -> bool,
+    ) -> Result<Receipt, nonce::SendError>;
+    // eth_call a mined transaction again at its block, for the revert data
+    fn replay(&self, receipt: &Receipt) -> Result<Replay, String>;
+    // token.approve(uniswab, amount) and wait for the receipt
+    fn approve(&self, token: Address, amount: U256) -> Result<Receipt, String>;
+    // uniswab.swap through one pool, token0 for token1 when zero_for_one
+    fn swap(
+        &self,
+        amount_in: U256,
+        amount_out_min: U256,
+        pool: Address,
+        zero_for_one: bool,
+        fee_bps: u8,
+    ) -> Result<Receipt, String>;
+}
+
+// the raw calls nonce::send makes to send and replace a transaction
+pub trait Node {
+    // the account transactions are signed for
+    fn from(&self) -> Address;
+    fn block_number(&self) -> Result<u64, String>;
+    fn pending_nonce(&self, address: Address) -> Result<u64, String>;
+    // (max fee per gas, max priority fee per gas) for the next block
+    fn fees(&self) -> Result<(u128, u128), String>;
+    // the hash
+    fn send(&self, tx: &TxRequest) -> Result<String, String>;
+    fn receipt(&self, tx_hash: &str) -> Result<Option<Receipt>, String>;
+}
+
+pub trait Chain: ReserveSource + BalanceSource + TxSender {}
+
+impl<T: ReserveSource + BalanceSource + TxSender> Chain for T {}
+
+#[derive(Clone, Debug)]
+pub struct Receipt {
+    pub tx_hash: String,
+    pub status: bool,
+    pub block_number: Option<u64>,
+    pub gas_used: u64,
+    pub effective_gas_price: u128,
+    // the stuck swab was replaced by a 0 value transfer to ourselves
+    pub cancelled: bool,
+    // how many times it was replaced before this one got mined
+    pub replacements: u32,
+}
+
+// how a chain's transactions pay for gas
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GasModel {
+    #[default]
+    Eip1559,
+    // a single gas price, max_fee_per_gas is sent as it
+    Legacy,
+}
+
+// a transaction with everything set, so it can be sent again with the same nonce. on a
+// legacy chain both fees are the gas price.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxRequest {
+    pub to: Address,
+    pub data: Bytes,
+    pub value: U256,
+    pub nonce: u64,
+    pub gas: u64,
+    pub max_fee_per_gas: u128,
+    pub max_priority_fee_per_gas: u128,
+}
+
+// approve costs 25k-50k, more for proxied tokens
+const APPROVE_GAS: u64 = 100000;
+
+pub struct Rpc<P> {
+    provider: P,
+    from: Address,
+    uniswab: Address,
+    tx_gas: u64,
+    gas_model: GasModel,
+    nonces: NonceManager,
+    replace: Replace,
+}
+
+impl<P: Provider> Rpc<P> {
+    pub fn new(
+        provider: P,
+        from: Address,
+        uniswab: Address,
+        tx_gas: u64,
+        gas_model: GasModel,
+        replace: Replace,
+    ) -> Rpc<P> {
+        Rpc {
+            provider,
+            from,
+            uniswab,
+            tx_gas,
+            gas_model,
+            nonces: NonceManager::default(),
+            replace,
+        }
+    }
+
+    #[tokio::main]
+    pub async fn chain_id(&self) -> Result<u64, String> {
+        metrics::rpc("eth_chainId", self.provider.get_chain_id())
+            .await
+            .map_err(|err| err.to_string())
+    }
+}
+
+impl<P: Provider> ReserveSource for Rpc<P> {
+    #[tokio::main]
+    async fn reserves(&self, pool: Address) -> Result<(U256, U256, u32), String> {
+        let pool = UniswapV2Pair::new(pool, &self.provider);
+        let (r0, r1, block_timestamp) = metrics::rpc("getReserves", pool.getReserves().call())
+            .await
+            .map_err(|err| err.to_string())?
+            .into();
+        Ok((U256::from(r0), U256::from(r1), block_timestamp))
+    }
+
+    #[tokio::main]
+    async fn reserves_at(&self, pool: Address, block: u64) -> Result<(U256, U256, u32), String> {
+        let pool = UniswapV2Pair::new(pool, &self.provider);
+        let (r0, r1, block_timestamp) = metrics::rpc(
+            "getReserves",
+            pool.getReserves().block(BlockId::number(block)).call(),
+        )
+        .await
+        .map_err(|err| err.to_string())?
+        .into();
+        Ok((U256::from(r0), U256::from(r1), block_timestamp))
+    }
+}
+
+impl<P: Provider> BalanceSource for Rpc<P> {
+    #[tokio::main]
+    async fn eth_balance(&self, owner: Address) -> Result<U256, String> {
+        metrics::rpc("eth_getBalance", self.provider.get_balance(owner))
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    #[tokio::main]
+    async fn token_balance(&self, token: Address, owner: Address) -> Result<U256, String> {
+        let token = ERC20::new(token, &self.provider);
+        metrics::rpc("balanceOf", token.balanceOf(owner).call())
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    #[tokio::main]
+    async fn allowance(&self, token: Address, owner: Address) -> Result<U256, String> {
+        let token = ERC20::new(token, &self.provider);
+        metrics::rpc("allowance", token.allowance(owner, self.uniswab).call())
+            .await
+            .map_err(|err| err.to_string())
+    }
+}
+
+impl<P: Provider> TxSender for Rpc<P> {
+    #[tokio::main]
+    async fn gas_price(&self) -> Result<u128, String> {
+        metrics::rpc("eth_gasPrice", self.provider.get_gas_price())
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    fn send_swab(
+        &self,
+        amount: U256,
+        pool0: Address,
+        pool1: Address,
+        fees: (u8, u8),
+        zero_for_one: bool,
+        still_wanted: &dyn Fn() -> bool,
+    ) -> Result<Receipt, nonce::SendError> {
+        let data = UniSwab::swabCall {
+            amountIn: amount,
+            pool0_addr: pool0,
+            pool1_addr: pool1,
+            fee0Bps: fees.0 as u16,
+            fee1Bps: fees.1 as u16,
+            zeroForOne: zero_for_one,
+        }
+        .abi_encode();
+        nonce::send(
+            self,
+            &self.nonces,
+            &self.replace,
+            self.uniswab,
+            data.into(),
+            self.tx_gas,
+            still_wanted,
+        )
+    }
+
+    #[tokio::main]
+    async fn replay(&self, receipt: &Receipt) -> Result<Replay, String> {
+        let tx_hash = receipt
+            .tx_hash
+            .parse()
+            .map_err(|err| format!("{}: {}", receipt.tx_hash, err))?;
+        let block = receipt.block_number.ok_or("replay: not mined")?;
+        let tx = metrics::rpc(
+            "eth_getTransactionByHash",
+            self.provider.get_transaction_by_hash(tx_hash),
+        )
+        .await
+        .map_err(|err| err.to_string())?
+        .ok_or_else(|| format!("replay: no transaction {}", receipt.tx_hash))?;
+        if receipt.gas_used >= tx.gas_limit() {
+            return Ok(Replay::OutOfGas);
+        }
+        let call = self
+            .provider
+            .call(tx.into_request())
+            .block(BlockId::number(block));
+        match metrics::rpc("eth_call", call).await {
+            Ok(_) => Ok(Replay::Succeeded),
+            Err(err) => Ok(
+                match err.as_error_resp().and_then(|resp| resp.as_revert_data()) {
+                    Some(data) => Replay::Reverted(data),
+                    None => Replay::Error(err.to_string()),
+                },
+            ),
+        }
+    }
+
+    fn approve(&self, token: Address, amount: U256) -> Result<Receipt, String> {
+        let data = ERC20::approveCall {
+            spender: self.uniswab,
+            value: amount,
+        }
+        .abi_encode();
+        nonce::send(
+            self,
+            &self.nonces,
+            &self.replace,
+            token,
+            data.into(),
+            APPROVE_GAS,
+            &|| true,
+        )
+        .map_err(|err| err.to_string())
+    }
+
+    fn swap(
+        &self,
+        amount_in: U256,
+        amount_out_min: U256,
+        pool: Address,
+        zero_for_one: bool,
+        fee_bps: u8,
+    ) -> Result<Receipt, String> {
+        let data = UniSwab::swapCall {
+            amountIn: amount_in,
+            amountOutMin: amount_out_min,
+            pool_addr: pool,
+            zeroForOne: zero_for_one,
+            feeBps: fee_bps as u16,
+        }
+        .abi_encode();
+        nonce::send(
+            self,
+            &self.nonces,
+            &self.replace,
+            self.uniswab,
+            data.into(),
+            self.tx_gas,
+            &|| true,
+        )
+        .map_err(|err| err.to_string())
+    }
+}
+
+impl<P: Provider> Node for Rpc<P> {
+    fn from(&self) -> Address {
+        self.from
+    }
+
+    #[tokio::main]
+    async fn block_number(&self) -> Result<u64, String> {
+        metrics::rpc("eth_blockNumber", self.provider.get_block_number())
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    #[tokio::main]
+    async fn pending_nonce(&self, address: Address) -> Result<u64, String> {
+        metrics::rpc(
+            "eth_getTransactionCount",
+            self.provider.get_transaction_count(address).pending(),
+        )
+        .await
+        .map_err(|err| err.to_string())
+    }
+
+    #[tokio::main]
+    async fn fees(&self) -> Result<(u128, u128), String> {
+        if self.gas_model == GasModel::Legacy {
+            let gas_price = metrics::rpc("eth_gasPrice", self.provider.get_gas_price())
+                .await
+                .map_err(|err| err.to_string())?;
+            return Ok((gas_price, gas_price));
+        }
+        let fees = metrics::rpc("eth_feeHistory", self.provider.estimate_eip1559_fees())
+            .await
+            .map_err(|err| err.to_string())?;
+        Ok((fees.max_fee_per_gas, fees.max_priority_fee_per_gas))
+    }
+
+    #[tokio::main]
+    async fn send(&self, tx: &TxRequest) -> Result<String, String> {
+        let request = TransactionRequest::default()
+            .from(self.from)
+            .to(tx.to)
+            .input(tx.data.clone().into())
+            .value(tx.value)
+            .nonce(tx.nonce)
+            .gas_limit(tx.gas);
+        let request = match self.gas_model {
+            GasModel::Eip1559 => request
+                .max_fee_per_gas(tx.max_fee_per_gas)
+                .max_priority_fee_per_gas(tx.max_priority_fee_per_gas),
+            GasModel::Legacy => request.gas_price(tx.max_fee_per_gas),
+        };
+        let pending = metrics::rpc(
+            "eth_sendTransaction",
+            self.provider.send_transaction(request),
+        )
+        .await
+        .map_err(|err| err.to_string())?;
+        Ok(pending.tx_hash().to_string())
+    }
+
+    #[tokio::main]
+    async fn receipt(&self, tx_hash: &str) -> Result<Option<Receipt>, String> {
+        let tx_hash = tx_hash
+            .parse()
+            .map_err(|err| format!("{}: {}", tx_hash, err))?;
+        let receipt = metrics::rpc(
+            "eth_getTransactionReceipt",
+            self.provider.get_transaction_receipt(tx_hash),
+        )
+        .await
+        .map_err(|err| err.to_string())?;
+        Ok(receipt.map(|receipt| Receipt {
+            tx_hash: receipt.transaction_hash.to_string(),
+            status: receipt.status(),
+            block_number: receipt.block_number,
+            gas_used: receipt.gas_used,
+            effective_gas_price: receipt.effective_gas_price,
+            cancelled: false,
+            replacements: 0,
+        }))
+    }
+}
+
+#[cfg(test)]
+pub use fake::FakeChain;
+
+#[cfg(test)]
+mod fake {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // balances are keyed by (token, owner), eth uses Address::ZERO as the token.
+    // a sent swab overwrites balances with balances_after and returns receipt. wanted
+    // has what still_wanted said for each swab. reserves_before stands in for reserves
+    // at any earlier block. allowances are per token, unlimited when not set, approvals
+    // and swaps have every approve and swap sent.
+    #[derive(Default)]
+    pub struct FakeChain {
+        pub reserves: HashMap<Address, (U256, U256, u32)>,
+        pub reserves_before: HashMap<Address, (U256, U256, u32)>,
+        pub balances: RefCell<HashMap<(Address, Address), U256>>,
+        pub balances_after: HashMap<(Address, Address), U256>,
+        pub gas_price: u128,
+        pub gas_price_error: Option<String>,
+        pub receipt: Option<Receipt>,
+        // what a send_swab without a receipt had broadcast
+        pub sent_hash: Option<String>,
+        pub sent: RefCell<Vec<(U256, Address, Address, bool)>>,
+        pub wanted: RefCell<Vec<bool>>,
+        pub replay: Option<Replay>,
+        pub allowances: RefCell<HashMap<Address, U256>>,
+        pub approvals: RefCell<Vec<(Address, U256)>>,
+        pub swaps: RefCell<Vec<(U256, U256, Address, bool)>>,
+    }
+
+    impl FakeChain {
+        pub fn set_balance(&mut self, token: Address, owner: Address, amount: U256) {
+            self.balances.get_mut().insert((token, owner), amount);
+        }
+    }
+
+    impl ReserveSource for FakeChain {
+        fn reserves(&self, pool: Address) -> Result<(U256, U256, u32), String> {
+            self.reserves
+                .get(&pool)
+                .copied()
+                .ok_or_else(|| format!("no pool {}", pool))
+        }
+
+        fn reserves_at(&self, pool: Address, _block: u64) -> Result<(U256, U256, u32), String> {
+            match self.reserves_before.get(&pool) {
+                Some(reserves) => Ok(*reserves),
+                None => self.reserves(pool),
+            }
+        }
+    }
+
+    impl BalanceSource for FakeChain {
+        fn eth_balance(&self, owner: Address) -> Result<U256, String> {
+            self.token_balance(Address::ZERO, owner)
+        }
+
+        fn token_balance(&self, token: Address, owner: Address) -> Result<U256, String> {
+            Ok(self
+                .balances
+                .borrow()
+                .get(&(token, owner))
+                .copied()
+                .unwrap_or_default())
+        }
+
+        fn allowance(&self, token: Address, _owner: Address) -> Result<U256, String> {
+            Ok(self
+                .allowances
+                .borrow()
+                .get(&token)
+                .copied()
+                .unwrap_or(U256::MAX))
+        }
+    }
+
+    impl TxSender for FakeChain {
+        fn gas_price(&self) -> Result<u128, String> {
+            match &self.gas_price_error {
+                Some(err) => Err(err.clone()),
+                None => Ok(self.gas_price),
+            }
+        }
+
+        fn send_swab(
+            &self,
+            amount: U256,
+            pool0: Address,
+            pool1: Address,
+            _fees: (u8, u8),
+            zero_for_one: bool,
+            still_wanted: &dyn Fn() -> bool,
+        ) -> Result<Receipt, nonce::SendError> {
+            self.sent
+                .borrow_mut()
+                .push((amount, pool0, pool1, zero_for_one));
+            self.wanted.borrow_mut().push(still_wanted());
+            let receipt = self.receipt.clone().ok_or_else(|| nonce::SendError {
+                message: "send failed".to_owned(),
+                tx_hash: self.sent_hash.clone(),
+            })?;
+            self.balances
+                .borrow_mut()
+                .extend(self.balances_after.clone());
+            Ok(receipt)
+        }
+
+        fn replay(&self, _receipt: &Receipt) -> Result<Replay, String> {
+            self.replay.clone().ok_or("no replay".to_owned())
+        }
+
+        fn approve(&self, token: Address, amount: U256) -> Result<Receipt, String> {
+            self.approvals.borrow_mut().push((token, amount));
+            self.allowances.borrow_mut().insert(token, amount);
+            Ok(mined(self.approvals.borrow().len()))
+        }
+
+        fn swap(
+            &self,
+            amount_in: U256,
+            amount_out_min: U256,
+            pool: Address,
+            zero_for_one: bool,
+            _fee_bps: u8,
+        ) -> Result<Receipt, String> {
+            self.swaps
+                .borrow_mut()
+                .push((amount_in, amount_out_min, pool, zero_for_one));
+            Ok(mined(self.swaps.borrow().len()))
+        }
+    }
+
+    fn mined(n: usize) -> Receipt {
+        Receipt {
+            tx_hash: format!("0x{}", n),
+            status: true,
+            block_number: Some(1),
+            gas_used: 46000,
+            effective_gas_price: 1,
+            cancelled: false,
+            replacements: 0,
+        }
+    }
+}
@@ -1,17 +1,305 @@
-use std::env;
+#![allow(dead_code)]
 
+use std::process::ExitCode;
+
+use alloy::{
+    primitives::{Address, I256, U256, U512},
+    providers::ProviderBuilder,
+    transports::http::reqwest::Url,
+};
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+
+mod address;
+mod contracts;
 mod decimal;
 mod unipool;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let ax = u128::from_str_radix(&args[1], 10).unwrap();
-    let ay = u128::from_str_radix(&args[2], 10).unwrap();
-    let bx = u128::from_str_radix(&args[3], 10).unwrap();
-    let by = u128::from_str_radix(&args[4], 10).unwrap();
-    println!("ax {} ay {} bx {} by {}", ax, ay, bx, by);
-    let a_price = decimal::scale(ay, ax);
-    let b_price = decimal::scale(by, bx);
+use contracts::UniswapV2Pair;
+use decimal::Decimal;
+
+#[derive(Parser)]
+#[command(name = "u2arb", about = "uniswap v2 two pool arbitrage calculator")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// pool fee in basis points, applied to both pools
+    #[arg(long, global = true, default_value_t = unipool::POOL_FEE_BASIS_POINTS)]
+    fee: u8,
+    /// print results as json
+    #[arg(long, global = true)]
+    json: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// simulate the arb from pool reserves. x is the product, y is the money.
+    Simulate {
+        #[command(flatten)]
+        reserves: Reserves,
+        /// trade this much y instead of the optimal amount
+        #[arg(long)]
+        ay_in: Option<U256>,
+    },
+    /// read reserves of two pools with getReserves, then simulate
+    Fetch {
+        #[arg(value_parser = address::parse)]
+        pool0: Address,
+        #[arg(value_parser = address::parse)]
+        pool1: Address,
+        #[arg(long, env = "ETH_RPC_URL", default_value = "http://127.0.0.1:8545")]
+        rpc: Url,
+        /// trade this much y instead of the optimal amount
+        #[arg(long)]
+        ay_in: Option<U256>,
+    },
+    /// print profit against input size, from zero to twice the optimal ay_in
+    Curve {
+        #[command(flatten)]
+        reserves: Reserves,
+        /// number of samples
+        #[arg(long, default_value_t = 20)]
+        steps: u32,
+        /// reserve move, in basis points, for the sensitivity report
+        #[arg(long, default_value_t = 10)]
+        bps: u32,
+        /// print the curve as csv instead of a chart
+        #[arg(long)]
+        csv: bool,
+    },
+    /// show the quadratic coefficients behind the optimal ay_in
+    Explain {
+        #[command(flatten)]
+        reserves: Reserves,
+    },
+}
+
+#[derive(clap::Args, Clone, Copy, Serialize)]
+struct Reserves {
+    /// pool 0 product reserve
+    #[serde(serialize_with = "as_decimal")]
+    ax: U256,
+    /// pool 0 money reserve
+    #[serde(serialize_with = "as_decimal")]
+    ay: U256,
+    /// pool 1 product reserve
+    #[serde(serialize_with = "as_decimal")]
+    bx: U256,
+    /// pool 1 money reserve
+    #[serde(serialize_with = "as_decimal")]
+    by: U256,
+}
+
+#[derive(Serialize)]
+struct Simulation {
+    reserves: Reserves,
+    fee_bps: u8,
+    pool0_price: Decimal,
+    pool1_price: Decimal,
+    mid_price: Decimal,
+    price_diff_pct: Decimal,
+    #[serde(serialize_with = "as_decimal")]
+    ay_in: U256,
+    #[serde(serialize_with = "as_decimal")]
+    s1_adx: U256,
+    #[serde(serialize_with = "as_decimal")]
+    s2_ady: U256,
+    #[serde(serialize_with = "as_decimal")]
+    profit: I256,
+}
+
+#[derive(Serialize)]
+struct CurvePoint {
+    #[serde(serialize_with = "as_decimal")]
+    ay_in: U256,
+    #[serde(serialize_with = "as_decimal")]
+    ay_out: U256,
+    #[serde(serialize_with = "as_decimal")]
+    profit: I256,
+}
+
+// profit of the optimal ay_in, sized before the reserves moved
+#[derive(Serialize)]
+struct Sensitivity {
+    #[serde(serialize_with = "as_decimal")]
+    ay_in: U256,
+    #[serde(serialize_with = "as_decimal")]
+    profit: I256,
+    moves: Vec<ReserveMove>,
+    // smallest move of both pools that leaves no profit
+    breakeven_bps: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct ReserveMove {
+    bps: u32,
+    #[serde(serialize_with = "as_decimal")]
+    pool0_moved: I256,
+    #[serde(serialize_with = "as_decimal")]
+    pool1_moved: I256,
+    #[serde(serialize_with = "as_decimal")]
+    both_moved: I256,
+}
+
+#[derive(Serialize)]
+struct Explanation {
+    reserves: Reserves,
+    fee_bps: u8,
+    a: String,
+    b: String,
+    neg_c: String,
+    delta: String,
+    #[serde(serialize_with = "as_decimal")]
+    root: U256,
+}
+
+// json numbers lose precision past 2^53, so 256 bit amounts are written as decimal strings
+fn as_decimal<T: std::fmt::Display, S: serde::Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("u2arb: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), String> {
+    match &cli.command {
+        Command::Simulate { reserves, ay_in } => {
+            let simulation = simulate(*reserves, *ay_in, cli.fee)?;
+            print_simulation(&simulation, cli.json)?;
+        }
+        Command::Fetch {
+            pool0,
+            pool1,
+            rpc,
+            ay_in,
+        } => {
+            let (reserves, swapped) = fetch(*pool0, *pool1, rpc.clone())?;
+            if swapped && !cli.json {
+                println!("pool {} is cheaper. simulating it as pool 0", pool1);
+            }
+            let simulation = simulate(reserves, *ay_in, cli.fee)?;
+            print_simulation(&simulation, cli.json)?;
+        }
+        Command::Curve {
+            reserves,
+            steps,
+            bps,
+            csv,
+        } => {
+            let points = curve(*reserves, *steps, cli.fee)?;
+            let sensitivity = sensitivity(*reserves, *bps, cli.fee)?;
+            if cli.json {
+                let curve = serde_json::json!({"points": points, "sensitivity": sensitivity});
+                println!("{}", serde_json::to_string_pretty(&curve).unwrap());
+            } else if *csv {
+                println!("ay_in,ay_out,profit");
+                for point in points {
+                    println!("{},{},{}", point.ay_in, point.ay_out, point.profit);
+                }
+            } else {
+                print!("{}", chart(&points, 12));
+                print_sensitivity(&sensitivity);
+            }
+        }
+        Command::Explain { reserves } => {
+            let explanation = explain(*reserves, cli.fee)?;
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(&explanation).unwrap());
+            } else {
+                println!("all terms are multiplied by m^4, m = 10000 basis points");
+                println!(
+                    "a = k^2 where k = (1-f)*bx + (1-f)^2*ax\n  {}",
+                    explanation.a
+                );
+                println!("b = 2k*ay*bx\n  {}", explanation.b);
+                println!(
+                    "c = (ay*bx)^2 - (1-f)^2*ax*ay*bx*by (negative, shown as -c)\n  {}",
+                    explanation.neg_c
+                );
+                println!("delta = b^2 + 4a(-c)\n  {}", explanation.delta);
+                println!("ay_in = (sqrt(delta) - b) / 2a\n  {}", explanation.root);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn simulate(reserves: Reserves, ay_in: Option<U256>, fee: u8) -> Result<Simulation, String> {
+    let Reserves { ax, ay, bx, by } = reserves;
+    check_reserves(reserves)?;
+    let pool0_price = Decimal::ratio(ay, 0, ax, 0).ok_or("pool 0 price out of range")?;
+    let pool1_price = Decimal::ratio(by, 0, bx, 0).ok_or("pool 1 price out of range")?;
+    let ay_in = match ay_in {
+        Some(ay_in) => ay_in,
+        None => unipool::optimal_ay_in_with_fee(ax, ay, bx, by, fee)?,
+    };
+    let (s1_adx, s2_ady) = unipool::swab_out(ay_in, ax, ay, bx, by, fee)?;
+    Ok(Simulation {
+        reserves,
+        fee_bps: fee,
+        pool0_price,
+        pool1_price,
+        mid_price: pool0_price
+            .checked_add(pool1_price)
+            .and_then(|sum| sum.checked_div(Decimal::from_int(2)))
+            .ok_or("mid price out of range")?,
+        price_diff_pct: pool1_price
+            .checked_div(pool0_price)
+            .and_then(|ratio| ratio.checked_sub(Decimal::ONE))
+            .and_then(|diff| diff.checked_mul(Decimal::from_int(100)))
+            .ok_or("price difference out of range")?,
+        ay_in,
+        s1_adx,
+        s2_ady,
+        profit: profit(ay_in, s2_ady)?,
+    })
+}
+
+// ay_out - ay_in, negative for a losing trade
+fn profit(ay_in: U256, ay_out: U256) -> Result<I256, String> {
+    let signed = |amount: U256| {
+        I256::try_from(amount)
+            .map_err(|_| format!("{} does not fit a signed 256 bit profit", amount))
+    };
+    signed(ay_out)?
+        .checked_sub(signed(ay_in)?)
+        .ok_or_else(|| format!("profit overflow for ay_in {} ay_out {}", ay_in, ay_out))
+}
+
+// y per x, or n/a when nothing came out
+fn price(y: U256, x: U256) -> String {
+    Decimal::ratio(y, 0, x, 0).map_or("n/a".to_owned(), |price| price.to_string())
+}
+
+// constant product after a trade, wide enough for any two reserves
+fn k(x: U256, y: U256) -> U512 {
+    U512::from(x) * U512::from(y)
+}
+
+fn print_simulation(simulation: &Simulation, json: bool) -> Result<(), String> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(simulation).unwrap());
+        return Ok(());
+    }
+    let Reserves { ax, ay, bx, by } = simulation.reserves;
+    let (a_price, b_price) = (simulation.pool0_price, simulation.pool1_price);
+    let (ay_in, s1_adx, s2_ady) = (simulation.ay_in, simulation.s1_adx, simulation.s2_ady);
+    let overflow = |step: &str| format!("{} reserves out of range", step);
+    println!(
+        "ax {} ay {} bx {} by {} fee {}bps",
+        ax, ay, bx, by, simulation.fee_bps
+    );
     println!(
         "pool 0 price y/x {} {}",
         a_price,
@@ -22,47 +310,384 @@ fn main() {
         b_price,
         if a_price > b_price { "CHEAP" } else { "" },
     );
-    let mid_price = ((b_price - a_price) / 2.0) + a_price;
-    let price_diff = ((b_price / a_price) - 1.0) * 100.0;
-    println!("mid_price {mid_price} price_diff {price_diff:.2}%");
-    let ay_in = if args.len() < 6 {
-        unipool::optimal_ay_in(ax, ay, bx, by).unwrap()
-    } else {
-        u128::from_str_radix(&args[5], 10).unwrap()
-    };
-    let s1_adx = unipool::get_y_out(ay_in, ay, ax);
+    println!(
+        "mid_price {} price_diff {:.2}%",
+        simulation.mid_price, simulation.price_diff_pct
+    );
     println!(
         "step 1 ay_in {} -> s1_adx {} price {}",
         ay_in,
         s1_adx,
-        decimal::scale(ay_in, s1_adx)
+        price(ay_in, s1_adx)
     );
-    let s1_ax = ax - s1_adx;
-    let s1_ay = ay + ay_in;
+    let s1_ax = ax.checked_sub(s1_adx).ok_or_else(|| overflow("step 1"))?;
+    let s1_ay = ay.checked_add(ay_in).ok_or_else(|| overflow("step 1"))?;
     println!(
         "p0 price@s1 {} ax {} ay {} k {}",
-        decimal::scale(s1_ay, s1_ax),
+        price(s1_ay, s1_ax),
         s1_ax,
         s1_ay,
-        s1_ax * s1_ay
+        k(s1_ax, s1_ay)
     );
-    let s2_ady = unipool::get_y_out(s1_adx, bx, by);
     println!(
         "step 2 s1_adx {} -> s2_ady {} price {}",
         s1_adx,
         s2_ady,
-        decimal::scale(s2_ady, s1_adx)
+        price(s2_ady, s1_adx)
     );
-    let s2_bx = bx + s1_adx;
-    let s2_by = by - s2_ady;
+    let s2_bx = bx.checked_add(s1_adx).ok_or_else(|| overflow("step 2"))?;
+    let s2_by = by.checked_sub(s2_ady).ok_or_else(|| overflow("step 2"))?;
     println!(
         "p1 price@s2 {} ax {} ay {} k {}",
-        decimal::scale(s2_by, s2_bx),
+        price(s2_by, s2_bx),
         s2_bx,
         s2_by,
-        s2_bx * s2_by
+        k(s2_bx, s2_by)
+    );
+    println!(
+        "ay_in {} ay_out {} -> profit {}",
+        ay_in, s2_ady, simulation.profit
+    );
+    Ok(())
+}
+
+fn curve(reserves: Reserves, steps: u32, fee: u8) -> Result<Vec<CurvePoint>, String> {
+    let Reserves { ax, ay, bx, by } = reserves;
+    check_reserves(reserves)?;
+    if steps == 0 {
+        return Err("--steps must be at least 1".to_owned());
+    }
+    let optimal = unipool::optimal_ay_in_with_fee(ax, ay, bx, by, fee)?;
+    (0..=steps)
+        .map(|step| {
+            let ay_in = U512::from(optimal) * U512::from(2 * step) / U512::from(steps);
+            let ay_in = U256::checked_from_limbs_slice(ay_in.as_limbs())
+                .ok_or_else(|| format!("ay_in {} does not fit 256 bits", ay_in))?;
+            let (_, ay_out) = unipool::swab_out(ay_in, ax, ay, bx, by, fee)?;
+            Ok(CurvePoint {
+                ay_in,
+                ay_out,
+                profit: profit(ay_in, ay_out)?,
+            })
+        })
+        .collect()
+}
+
+// one column per point, profit on the vertical axis. the zero line is drawn with dashes.
+fn chart(points: &[CurvePoint], height: usize) -> String {
+    let profits = points
+        .iter()
+        .map(|point| point.profit)
+        .collect::<Vec<I256>>();
+    let max = profits
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or_default()
+        .max(I256::ZERO);
+    let min = profits
+        .iter()
+        .copied()
+        .min()
+        .unwrap_or_default()
+        .min(I256::ZERO);
+    let span = U512::from(max.saturating_sub(min).unsigned_abs()).max(U512::from(1));
+    let row_of = |profit: I256| {
+        let offset = U512::from(profit.saturating_sub(min).unsigned_abs());
+        (offset * U512::from(height - 1) / span).to::<usize>()
+    };
+    let zero_row = row_of(I256::ZERO);
+    let label_width = max.to_string().len().max(min.to_string().len());
+    let mut lines = vec![];
+    for row in (0..height).rev() {
+        let label = if row == height - 1 {
+            max.to_string()
+        } else if row == 0 {
+            min.to_string()
+        } else if row == zero_row {
+            "0".to_owned()
+        } else {
+            String::new()
+        };
+        let cells = profits
+            .iter()
+            .map(|profit| {
+                if row_of(*profit) == row {
+                    '*'
+                } else if row == zero_row {
+                    '-'
+                } else {
+                    ' '
+                }
+            })
+            .collect::<String>();
+        lines.push(format!("{:>width$} |{}", label, cells, width = label_width));
+    }
+    let last = points.last().map(|point| point.ay_in).unwrap_or_default();
+    lines.push(format!(
+        "{:>width$}  ay_in 0..{}",
+        "",
+        last,
+        width = label_width
+    ));
+    lines.join("\n") + "\n"
+}
+
+// the money reserve moves against the trade: pool 0 gets pricier and pool 1 cheaper
+fn sensitivity(reserves: Reserves, bps: u32, fee: u8) -> Result<Sensitivity, String> {
+    let Reserves { ax, ay, bx, by } = reserves;
+    check_reserves(reserves)?;
+    let ay_in = unipool::optimal_ay_in_with_fee(ax, ay, bx, by, fee)?;
+    let profit_at = |pool0_bps: u32, pool1_bps: u32| {
+        let moved = |reserve: U256, bps: u32| {
+            reserve
+                .checked_mul(U256::from(bps))
+                .map(|moved| moved / U256::from(10000))
+                .ok_or_else(|| format!("reserve {} out of range", reserve))
+        };
+        let ay = moved(ay, 10000 + pool0_bps)?;
+        let by = moved(by, 10000_u32.saturating_sub(pool1_bps))?;
+        let (_, ay_out) = unipool::swab_out(ay_in, ax, ay, bx, by, fee)?;
+        profit(ay_in, ay_out)
+    };
+    let moves = (1..=5)
+        .map(|multiple| {
+            let bps = bps * multiple;
+            Ok(ReserveMove {
+                bps,
+                pool0_moved: profit_at(bps, 0)?,
+                pool1_moved: profit_at(0, bps)?,
+                both_moved: profit_at(bps, bps)?,
+            })
+        })
+        .collect::<Result<Vec<ReserveMove>, String>>()?;
+    let mut breakeven_bps = None;
+    for bps in 1..=10000 {
+        if profit_at(bps, bps)? <= I256::ZERO {
+            breakeven_bps = Some(bps);
+            break;
+        }
+    }
+    Ok(Sensitivity {
+        ay_in,
+        profit: profit_at(0, 0)?,
+        moves,
+        breakeven_bps,
+    })
+}
+
+fn print_sensitivity(sensitivity: &Sensitivity) {
+    let decay = |profit: I256| {
+        let pct = sensitivity
+            .profit
+            .checked_sub(profit)
+            .and_then(|lost| {
+                Decimal::from_signed_units(lost, 0).checked_mul(Decimal::from_int(100))
+            })
+            .and_then(|lost| lost.checked_div(Decimal::from_signed_units(sensitivity.profit, 0)));
+        match pct {
+            Some(pct) => format!("{} (-{:.1}%)", profit, pct),
+            None => profit.to_string(),
+        }
+    };
+    println!(
+        "ay_in {} profit {} when reserves move against the trade by",
+        sensitivity.ay_in, sensitivity.profit
     );
+    for reserve_move in sensitivity.moves.iter() {
+        println!(
+            "  {}bps pool0 {} pool1 {} both {}",
+            reserve_move.bps,
+            decay(reserve_move.pool0_moved),
+            decay(reserve_move.pool1_moved),
+            decay(reserve_move.both_moved)
+        );
+    }
+    match sensitivity.breakeven_bps {
+        Some(bps) => println!("no profit once both pools move {}bps", bps),
+        None => println!("still profitable when both pools move 10000bps"),
+    }
+}
+
+fn explain(reserves: Reserves, fee: u8) -> Result<Explanation, String> {
+    let Reserves { ax, ay, bx, by } = reserves;
+    check_reserves(reserves)?;
+    let (a, b, c) = unipool::reserves_to_coefficients(ax, ay, bx, by, fee)?;
+    Ok(Explanation {
+        reserves,
+        fee_bps: fee,
+        a: a.to_string(),
+        b: b.to_string(),
+        neg_c: c.to_string(),
+        delta: unipool::discriminant(a, b, c).to_string(),
+        root: unipool::quadratic_root(a, b, c)?,
+    })
+}
+
+fn check_reserves(reserves: Reserves) -> Result<(), String> {
+    let Reserves { ax, ay, bx, by } = reserves;
+    if ax.is_zero() || ay.is_zero() || bx.is_zero() || by.is_zero() {
+        return Err("reserves must all be greater than zero".to_owned());
+    }
+    // pool 0 has to be the cheaper one: ay/ax < by/bx
+    if k(ay, bx) >= k(by, ax) {
+        return Err(format!(
+            "no arb: pool 0 price {} is not below pool 1 price {}. list the cheaper pool first",
+            price(ay, ax),
+            price(by, bx)
+        ));
+    }
+    Ok(())
+}
+
+// reserves of both pools, with the cheaper pool first. true when the pools were swapped.
+#[tokio::main]
+async fn fetch(pool0: Address, pool1: Address, rpc: Url) -> Result<(Reserves, bool), String> {
+    let provider = ProviderBuilder::new().connect_http(rpc.clone());
+    let pair0 = UniswapV2Pair::new(pool0, &provider);
+    let pair1 = UniswapV2Pair::new(pool1, &provider);
+    let rpc = &rpc;
+    let rpc_err = |pool: Address| move |err| format!("{} via {}: {}", pool, rpc, err);
+    let tokens0 = (
+        pair0.token0().call().await.map_err(rpc_err(pool0))?,
+        pair0.token1().call().await.map_err(rpc_err(pool0))?,
+    );
+    let tokens1 = (
+        pair1.token0().call().await.map_err(rpc_err(pool1))?,
+        pair1.token1().call().await.map_err(rpc_err(pool1))?,
+    );
+    if tokens0 != tokens1 {
+        return Err(format!(
+            "pools trade different tokens: {} has {}/{}, {} has {}/{}",
+            pool0, tokens0.0, tokens0.1, pool1, tokens1.0, tokens1.1
+        ));
+    }
+    let r0 = pair0.getReserves().call().await.map_err(rpc_err(pool0))?;
+    let r1 = pair1.getReserves().call().await.map_err(rpc_err(pool1))?;
+    let a = (U256::from(r0._reserve0), U256::from(r0._reserve1));
+    let b = (U256::from(r1._reserve0), U256::from(r1._reserve1));
+    // pool a has to be the cheaper one: ay/ax < by/bx
+    let swapped = k(a.1, b.0) > k(b.1, a.0);
+    let (a, b) = if swapped { (b, a) } else { (a, b) };
+    Ok((
+        Reserves {
+            ax: a.0,
+            ay: a.1,
+            bx: b.0,
+            by: b.1,
+        },
+        swapped,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reserves(ax: u128, ay: u128, bx: u128, by: u128) -> Reserves {
+        Reserves {
+            ax: U256::from(ax),
+            ay: U256::from(ay),
+            bx: U256::from(bx),
+            by: U256::from(by),
+        }
+    }
+
+    fn pools() -> Reserves {
+        reserves(310000, 210000, 220000, 320000)
+    }
+
+    fn n(amount: i64) -> I256 {
+        I256::try_from(amount).unwrap()
+    }
+
+    #[test]
+    fn test_simulate() {
+        let simulation = simulate(pools(), None, 30).unwrap();
+        assert_eq!(simulation.ay_in, U256::from(40371));
+        assert_eq!(simulation.profit, n(18608));
+        let simulation = simulate(pools(), Some(U256::from(1000000)), 30).unwrap();
+        assert!(simulation.profit < I256::ZERO);
+        assert!(simulate(pools(), None, 200).unwrap().profit < n(18608));
+
+        // 18 decimal reserves far past u64, k overflows u128
+        let e18 = 10_u128.pow(18);
+        let simulation = simulate(
+            reserves(310000 * e18, 210000 * e18, 220000 * e18, 320000 * e18),
+            None,
+            30,
+        )
+        .unwrap();
+        assert!(simulation.profit > I256::ZERO);
+        assert!(print_simulation(&simulation, false).is_ok());
+    }
+
+    #[test]
+    fn test_simulate_errors() {
+        let swapped = reserves(220000, 320000, 310000, 210000);
+        assert!(simulate(swapped, None, 30).is_err());
+        let empty = Reserves {
+            ax: U256::ZERO,
+            ..pools()
+        };
+        assert!(simulate(empty, None, 30).is_err());
+        let huge = Reserves {
+            by: U256::MAX,
+            ..pools()
+        };
+        assert!(simulate(huge, None, 30).is_err());
+    }
+
+    #[test]
+    fn test_curve() {
+        let points = curve(pools(), 4, 30).unwrap();
+        assert_eq!(points.len(), 5);
+        assert_eq!(points[0].ay_in, U256::ZERO);
+        assert_eq!(points[2].ay_in, U256::from(40371));
+        assert_eq!(points[2].profit, n(18608));
+        assert!(points.iter().all(|point| point.profit <= n(18608)));
+    }
+
+    #[test]
+    fn test_chart() {
+        let points = curve(pools(), 8, 30).unwrap();
+        let chart = chart(&points, 5);
+        let lines = chart.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 6);
+        // the optimum is the middle sample and sits on the top row
+        assert_eq!(lines[0], "18608 |    *    ");
+        assert!(lines[4].starts_with("    0 |*"));
+        assert!(lines[5].ends_with("ay_in 0..80742"));
+    }
+
+    #[test]
+    fn test_sensitivity() {
+        let sensitivity = sensitivity(pools(), 100, 30).unwrap();
+        assert_eq!(sensitivity.profit, n(18608));
+        assert_eq!(sensitivity.moves.len(), 5);
+        let mut last = sensitivity.profit;
+        for reserve_move in sensitivity.moves.iter() {
+            assert!(reserve_move.pool0_moved < sensitivity.profit);
+            assert!(reserve_move.pool1_moved < sensitivity.profit);
+            assert!(reserve_move.both_moved < reserve_move.pool0_moved);
+            assert!(reserve_move.both_moved < last);
+            last = reserve_move.both_moved;
+        }
+        let breakeven = sensitivity.breakeven_bps.unwrap();
+        assert!(breakeven > 100 && breakeven < 10000);
+    }
 
-    let profit = s2_ady - ay_in;
-    println!("ay_in {ay_in} ay_out {s2_ady} -> profit {profit}");
+    #[test]
+    fn test_cli_args() {
+        let cli = Cli::try_parse_from(["u2arb", "simulate", "1", "2", "3", "4", "--fee", "5"]);
+        assert_eq!(cli.unwrap().fee, 5);
+        assert!(Cli::try_parse_from(["u2arb", "simulate", "1", "2", "3"]).is_err());
+        assert!(Cli::try_parse_from(["u2arb", "explain", "1", "2", "3", "x"]).is_err());
+        let weth = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+        let fetch = |pool0: &str| Cli::try_parse_from(["u2arb", "fetch", pool0, &weth[2..]]);
+        assert!(fetch(weth).is_ok());
+        assert!(fetch(&weth.to_lowercase()).is_ok());
+        // a mistyped checksum
+        assert!(fetch(&weth.replacen("aaA", "aaa", 1)).is_err());
+    }
 }
@@ -1,59 +1,972 @@
+use std::env;
+use std::fs;
+
+use alloy::primitives::Address;
 use once_cell::sync::OnceCell;
-use secp256k1::{PublicKey, Secp256k1, SecretKey};
-use serde::de::DeserializeOwned;
+use secp256k1::SecretKey;
+use serde::de::{self, Deserializer, Visitor};
 use serde::{Deserialize, Serialize};
-use sha3::Digest;
-use std::fs;
+use serde_yaml::{Mapping, Value};
+use tracing_subscriber::EnvFilter;
+
+use crate::address;
+use crate::allowance::Policy;
+use crate::chain::GasModel;
+use crate::dex::Dex;
+use crate::rule::PairRule;
 
-pub static FILENAME: &'static str = "config.yaml";
+// config.yaml holds the settings every profile shares plus a profiles: mapping of named
+// overrides, eg mainnet, sepolia and local. the profile comes from --profile, GOFI_PROFILE
+// or a top level profile: key, in that order. GOFI_<FIELD> environment variables win over
+// both, eg GOFI_PG_URL or GOFI_EXCLUDE_ADDRESSES=0xaa..,0xbb..
+// a chains: mapping, top level or in the profile, names more chains to scan alongside
+// this one. each is a set of overrides applied last, on top of the environment, so it
+// has its own chain_id, geth_url, uniswab, tokens and dexes and shares the rest.
+// yaml reads a short 0x.. as a number, quote it or leave the 0x off.
+
+pub static FILENAME: &str = "config.yaml";
 pub static CONFIG: OnceCell<Config> = OnceCell::new();
+pub const ENV_PREFIX: &str = "GOFI_";
+
+// fields without a serde default. test_required keeps this in step with Config.
+const REQUIRED: [&str; 7] = [
+    "geth_url",
+    "pg_url",
+    "uniswab",
+    "preferred_base_token",
+    "preferred_coin_token",
+    "minimum_out",
+    "tx_gas",
+];
+
+// where the trading key comes from, exactly one of them is set
+const SIGNERS: [&str; 3] = ["eth_priv_key", "eth_keystore", "eth_signer"];
+
+// fields an environment variable sets as a comma separated list
+const LISTS: [&str; 1] = ["exclude_addresses"];
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
+    #[serde(deserialize_with = "deserialize_geth_url")]
     pub geth_url: String,
+    #[serde(deserialize_with = "deserialize_pg_url")]
     pub pg_url: String,
-    pub eth_priv_key: String,
-    pub uniswab: String,
-    pub preferred_base_token: String,
-    pub preferred_coin_token: String,
+    // hex without 0x. better kept out of the file as GOFI_ETH_PRIV_KEY, or use one of
+    // the two below.
+    #[serde(default, deserialize_with = "deserialize_priv_key")]
+    pub eth_priv_key: Option<String>,
+    // web3 secret storage json, the password comes from GOFI_KEYSTORE_PASSWORD or a prompt
+    #[serde(default, deserialize_with = "deserialize_file")]
+    pub eth_keystore: Option<String>,
+    // ipc socket of clef or another signer with its account_ api
+    #[serde(default, deserialize_with = "deserialize_file")]
+    pub eth_signer: Option<String>,
+    // which of eth_signer's accounts, the first if not set
+    #[serde(default, deserialize_with = "address::deserialize_option")]
+    pub eth_signer_account: Option<Address>,
+    #[serde(deserialize_with = "address::deserialize")]
+    pub uniswab: Address,
+    // eip-155 id of geth_url's chain, checked at startup. what gofi records is keyed by it.
+    #[serde(default = "default_chain_id")]
+    pub chain_id: u64,
+    // legacy for chains without eip-1559 fees
+    #[serde(default)]
+    pub gas_model: GasModel,
+    // weth or the chain's equivalent. gas is priced in coin1 through the base token, so
+    // when set preferred_base_token has to be it.
+    #[serde(default, deserialize_with = "address::deserialize_option")]
+    pub wrapped_native: Option<Address>,
+    // the uniswap v2 forks to trade, pools of other factories are left alone. empty,
+    // every pool is taken to be a uniswap v2 one.
+    #[serde(default)]
+    pub dexes: Vec<Dex>,
+    #[serde(deserialize_with = "address::deserialize")]
+    pub preferred_base_token: Address,
+    // the quote token swabbed when there are no pairs, and the one rebalanced
+    #[serde(deserialize_with = "address::deserialize")]
+    pub preferred_coin_token: Address,
+    // profit after gas a swab has to clear, in quote token units
     pub minimum_out: f64,
+    // the quote tokens swabbed against preferred_base_token, see rule.rs
+    #[serde(default)]
+    pub pairs: Vec<PairRule>,
     pub tx_gas: u64,
-    pub exclude_addresses: Vec<String>,
+    #[serde(default, deserialize_with = "address::deserialize_vec")]
+    pub exclude_addresses: Vec<Address>,
+    #[serde(default, deserialize_with = "deserialize_redis_url")]
+    pub redis_url: Option<String>,
+    #[serde(default = "default_match_ttl_blocks")]
+    pub match_ttl_blocks: u64,
+    // the chain's block time, turns match_ttl_blocks into seconds
+    #[serde(default = "default_block_seconds")]
+    pub block_seconds: u64,
+    // blocks a swab may sit unmined before it is sped up or cancelled
+    #[serde(default = "default_replace_after_blocks")]
+    pub replace_after_blocks: u64,
+    // replacements before giving up on a nonce
+    #[serde(default = "default_max_replacements")]
+    pub max_replacements: u32,
+    // exact approves uniswab for the wallet's balance of a coin, unlimited for good
+    #[serde(default)]
+    pub approval_policy: Policy,
+    // run gofi approve before the first scan cycle
+    #[serde(default)]
+    pub approve_on_start: bool,
+    // wallet bands in token units. a balance outside its band is swapped back to the
+    // middle through the deepest pool of the pair, unset bands are left alone.
+    #[serde(default)]
+    pub rebalance_base_min: Option<f64>,
+    #[serde(default)]
+    pub rebalance_base_max: Option<f64>,
+    #[serde(default)]
+    pub rebalance_coin_min: Option<f64>,
+    #[serde(default)]
+    pub rebalance_coin_max: Option<f64>,
+    // how far under the quote a rebalancing swap may fill
+    #[serde(default = "default_rebalance_max_slippage_bps")]
+    pub rebalance_max_slippage_bps: u32,
+    // eth for this many swabs at the current gas price is never spent on rebalancing
+    #[serde(default = "default_gas_reserve_swabs")]
+    pub gas_reserve_swabs: u64,
+    // http url of a fork of geth_url, eg anvil --fork-url. set, pools are screened for
+    // transfer taxes and honeypots there before they are traded.
+    #[serde(default, deserialize_with = "deserialize_http_url_option")]
+    pub screen_url: Option<String>,
+    // the Screener runtime code make -C ethereum builds
+    #[serde(default = "default_screener_code")]
+    pub screener_code: String,
+    // a pool whose coin0 loses more than this to transfers is not traded
+    #[serde(default = "default_max_transfer_tax_bps")]
+    pub max_transfer_tax_bps: u32,
+    // hours a screening verdict is trusted before the pool is screened again
+    #[serde(default = "default_screen_ttl_hours")]
+    pub screen_ttl_hours: u64,
+    // http url of a node whose mempool is watched for swaps to back-run, its pending
+    // transaction filter is polled every cycle. swaps sent to a dex's router and pair
+    // swaps to known pools are decoded.
+    #[serde(default, deserialize_with = "deserialize_http_url_option")]
+    pub mempool_url: Option<String>,
+    // risk limits. the most coin1, in token units, one swab may put in, unless its pair
+    // rule says otherwise
+    #[serde(default)]
+    pub max_trade_notional: Option<f64>,
+    // a breach of the rest halts trading until gofi resume
+    #[serde(default)]
+    pub max_trades_per_hour: Option<u32>,
+    // coin1 lost today, in token units and after gas, counted for each quote token
+    #[serde(default)]
+    pub max_daily_loss: Option<f64>,
+    // failed swabs in a row
+    #[serde(default = "default_max_consecutive_reverts")]
+    pub max_consecutive_reverts: u32,
+    #[serde(default)]
+    pub min_eth_balance: Option<f64>,
+    // base token/usd stablecoin pool used to value pnl in usd
+    #[serde(default, deserialize_with = "address::deserialize_option")]
+    pub usd_reference_pool: Option<Address>,
+    // host:port for the prometheus /metrics endpoint
+    #[serde(default, deserialize_with = "deserialize_metrics_addr")]
+    pub metrics_addr: Option<String>,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    // tracing env filter directives, eg "info,gofi::unipool=warn". RUST_LOG takes precedence.
+    #[serde(default, deserialize_with = "deserialize_log_filter")]
+    pub log_filter: Option<String>,
+    // where this config came from, for the startup log
+    #[serde(skip)]
+    pub path: String,
+    #[serde(skip)]
+    pub profile: Option<String>,
+    // the name of this chain in chains, None for the top level one
+    #[serde(skip)]
+    pub chain: Option<String>,
+    // the top level config's chains, resolved
+    #[serde(skip)]
+    pub chains: Vec<Config>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+fn default_chain_id() -> u64 {
+    1
+}
+
+fn default_match_ttl_blocks() -> u64 {
+    3
+}
+
+fn default_block_seconds() -> u64 {
+    12
+}
+
+fn default_replace_after_blocks() -> u64 {
+    3
+}
+
+fn default_max_replacements() -> u32 {
+    3
+}
+
+fn default_rebalance_max_slippage_bps() -> u32 {
+    50
+}
+
+fn default_gas_reserve_swabs() -> u64 {
+    10
+}
+
+fn default_max_consecutive_reverts() -> u32 {
+    1
+}
+
+fn default_screener_code() -> String {
+    "ethereum/artifacts/Screener.bin-runtime".to_owned()
 }
 
-impl Config {
-    pub fn public_key_bytes(&self) -> [u8; 20] {
-        let secp = Secp256k1::new();
-        let priv_key_bytes = hex::decode(&self.eth_priv_key).unwrap();
-        let secret_key =
-            SecretKey::from_slice(&priv_key_bytes).expect("32 bytes, within curve order");
-        let public_key: [u8; 65] =
-            PublicKey::from_secret_key(&secp, &secret_key).serialize_uncompressed();
-        let mut hasher = sha3::Keccak256::new();
-        hasher.update(&public_key[1..]);
-        hasher.finalize()[12..32].try_into().unwrap()
+fn default_max_transfer_tax_bps() -> u32 {
+    10
+}
+
+fn default_screen_ttl_hours() -> u64 {
+    24
+}
+
+// read path, apply the profile, the environment and the chains and check every field.
+// all the problems are reported together so a broken config is fixed in one go. chain
+// picks one of the chains to use on its own.
+pub fn load(path: &str, profile: Option<&str>, chain: Option<&str>) -> Result<Config, Vec<String>> {
+    let yaml = fs::read_to_string(path).map_err(|err| vec![format!("{}: {}", path, err)])?;
+    let file = match serde_yaml::from_str::<Value>(&yaml) {
+        Ok(Value::Mapping(file)) => file,
+        Ok(Value::Null) => Mapping::new(),
+        Ok(_) => return Err(vec![format!("{}: not a mapping", path)]),
+        Err(err) => return Err(vec![format!("{}: {}", path, err)]),
+    };
+    let mut config = resolve(file, profile, env::vars())?;
+    if let Some(name) = chain {
+        config = select(config, name)?;
     }
+    let path = std::path::Path::new(path)
+        .canonicalize()
+        .map_or(path.to_owned(), |path| path.display().to_string());
+    for chain in config.chains.iter_mut() {
+        chain.path = path.clone();
+    }
+    config.path = path;
+    Ok(config)
+}
 
-    pub fn public_key(&self) -> String {
-        hex::encode(self.public_key_bytes().to_vec())
+// the file's top level, then the profile's section, then the environment
+pub fn resolve(
+    mut file: Mapping,
+    profile: Option<&str>,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<Config, Vec<String>> {
+    let mut errors = vec![];
+    let named = file.remove("profile");
+    let profiles = match file.remove("profiles") {
+        None | Some(Value::Null) => Mapping::new(),
+        Some(Value::Mapping(profiles)) => profiles,
+        Some(_) => {
+            errors.push("profiles: not a mapping".to_owned());
+            Mapping::new()
+        }
+    };
+    let profile = match profile {
+        Some(profile) => Some(profile.to_owned()),
+        None => match named {
+            None | Some(Value::Null) => None,
+            Some(Value::String(profile)) => Some(profile),
+            Some(_) => {
+                errors.push("profile: not a string".to_owned());
+                None
+            }
+        },
+    };
+    if let Some(name) = &profile {
+        match profiles.get(name.as_str()) {
+            Some(Value::Mapping(overrides)) => file.extend(overrides.clone()),
+            Some(Value::Null) => {}
+            Some(_) => errors.push(format!("profiles.{}: not a mapping", name)),
+            None => errors.push(format!(
+                "profile {}: not in profiles ({})",
+                name,
+                profiles
+                    .keys()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            )),
+        }
     }
+    let chains = match file.remove("chains") {
+        None | Some(Value::Null) => Mapping::new(),
+        Some(Value::Mapping(chains)) => chains,
+        Some(_) => {
+            errors.push("chains: not a mapping".to_owned());
+            Mapping::new()
+        }
+    };
+    let fields = fields();
+    for (name, text) in vars {
+        let Some(field) = name.strip_prefix(ENV_PREFIX).map(str::to_lowercase) else {
+            continue;
+        };
+        if fields.contains(&field.as_str()) {
+            let value = env_value(&field, &text);
+            file.insert(Value::String(field), value);
+        }
+    }
+    let config = build(file.clone()).map_err(|build_errors| errors.extend(build_errors));
+    let mut resolved: Vec<Config> = vec![];
+    for (name, overrides) in chains {
+        let Some(name) = name.as_str() else {
+            errors.push(format!("chains: {:?} is not a name", name));
+            continue;
+        };
+        let mut chain = file.clone();
+        match overrides {
+            Value::Mapping(overrides) => chain.extend(overrides),
+            Value::Null => {}
+            _ => {
+                errors.push(format!("chains.{}: not a mapping", name));
+                continue;
+            }
+        }
+        match build(chain) {
+            Ok(mut chain) => {
+                chain.chain = Some(name.to_owned());
+                chain.profile = profile.clone();
+                resolved.push(chain);
+            }
+            Err(chain_errors) => errors.extend(
+                chain_errors
+                    .into_iter()
+                    .map(|error| format!("chains.{}.{}", name, error)),
+            ),
+        }
+    }
+    let mut config = match config {
+        Ok(config) if errors.is_empty() => config,
+        _ => return Err(errors),
+    };
+    let mut chain_ids = vec![config.chain_id];
+    for chain in resolved.iter() {
+        if chain_ids.contains(&chain.chain_id) {
+            errors.push(format!(
+                "chains.{}.chain_id: {} is scanned twice",
+                chain.chain.as_deref().unwrap(),
+                chain.chain_id
+            ));
+        }
+        chain_ids.push(chain.chain_id);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    config.profile = profile;
+    config.chains = resolved;
+    Ok(config)
 }
-pub fn read_type<T>(filename: &str) -> T
-where
-    T: DeserializeOwned,
-{
-    let filepath = path(filename);
-    let yaml = fs::read_to_string(&filepath)
-        .unwrap_or_else(|err| panic!("{} -> {} {}", filename, &filepath, err));
-    let obj: T = serde_yaml::from_str(&yaml).unwrap_or_else(|err| panic!("{} {}", &filepath, err));
-    obj
+
+// one of config's chains, on its own
+pub fn select(config: Config, name: &str) -> Result<Config, Vec<String>> {
+    let names = config
+        .chains
+        .iter()
+        .filter_map(|chain| chain.chain.clone())
+        .collect::<Vec<String>>()
+        .join(", ");
+    config
+        .chains
+        .into_iter()
+        .find(|chain| chain.chain.as_deref() == Some(name))
+        .ok_or_else(|| vec![format!("chain {}: not in chains ({})", name, names)])
 }
 
-pub fn path(filename: &str) -> String {
-    std::path::Path::new(filename)
-        .canonicalize()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string()
+// the settings of one chain, checked
+fn build(file: Mapping) -> Result<Config, Vec<String>> {
+    let mut errors = check(&file);
+    let signers = SIGNERS
+        .into_iter()
+        .filter(|name| file.get(*name).is_some_and(|value| !value.is_null()))
+        .collect::<Vec<&str>>();
+    if signers.len() != 1 {
+        errors.push(format!(
+            "{}: set exactly one, have {}",
+            SIGNERS.join(", "),
+            if signers.is_empty() {
+                "none".to_owned()
+            } else {
+                signers.join(", ")
+            }
+        ));
+    }
+    for name in REQUIRED {
+        if !file.contains_key(name) {
+            errors.push(format!(
+                "{}: missing, set it in the file or {}{}",
+                name,
+                ENV_PREFIX,
+                name.to_uppercase()
+            ));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let config: Config =
+        serde_yaml::from_value(Value::Mapping(file)).map_err(|err| vec![err.to_string()])?;
+    let mut errors = check_bands(&config);
+    errors.extend(check_wrapped_native(&config));
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(config)
+}
+
+fn check_wrapped_native(config: &Config) -> Option<String> {
+    match config.wrapped_native {
+        Some(wrapped_native) if wrapped_native != config.preferred_base_token => Some(format!(
+            "preferred_base_token: {} is not wrapped_native {}, gas is priced through it",
+            config.preferred_base_token, wrapped_native
+        )),
+        _ => None,
+    }
+}
+
+// rebalance bands come as a min and a max, the min below the max
+fn check_bands(config: &Config) -> Vec<String> {
+    [
+        (
+            "rebalance_base",
+            config.rebalance_base_min,
+            config.rebalance_base_max,
+        ),
+        (
+            "rebalance_coin",
+            config.rebalance_coin_min,
+            config.rebalance_coin_max,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, min, max)| match (min, max) {
+        (Some(min), _) if min < 0.0 => Some(format!("{}_min: {} is negative", name, min)),
+        (Some(min), Some(max)) if min >= max => Some(format!(
+            "{}_min: {} is not below {}_max {}",
+            name, min, name, max
+        )),
+        (Some(_), None) => Some(format!("{}_max: missing, {}_min is set", name, name)),
+        (None, Some(_)) => Some(format!("{}_min: missing, {}_max is set", name, name)),
+        _ => None,
+    })
+    .collect()
+}
+
+// every key on its own, so one bad field does not hide the next
+fn check(file: &Mapping) -> Vec<String> {
+    let fields = fields();
+    let mut errors = vec![];
+    for (key, value) in file {
+        let Some(name) = key.as_str() else {
+            errors.push(format!("{:?}: not a field name", key));
+            continue;
+        };
+        if !fields.contains(&name) {
+            errors.push(format!("{}: unknown field", name));
+            continue;
+        }
+        let single = Mapping::from_iter([(key.clone(), value.clone())]);
+        if let Err(err) = serde_yaml::from_value::<Config>(Value::Mapping(single)) {
+            let err = err.to_string();
+            if !err.starts_with("missing field") {
+                errors.push(format!("{}: {}", name, err));
+            }
+        }
+    }
+    errors
+}
+
+// an environment variable as the yaml value it stands for. numbers stay numbers,
+// 0x.. stays a string, empty is null.
+fn env_value(field: &str, text: &str) -> Value {
+    if LISTS.contains(&field) {
+        return Value::Sequence(
+            text.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_owned()))
+                .collect(),
+        );
+    }
+    match serde_yaml::from_str::<Value>(text) {
+        Ok(value @ (Value::Null | Value::Bool(_) | Value::Number(_)))
+            if !text.starts_with("0x") =>
+        {
+            value
+        }
+        _ => Value::String(text.to_owned()),
+    }
+}
+
+// the field names serde knows Config by, taken from its Deserialize impl
+pub fn fields() -> &'static [&'static str] {
+    struct Fields(Option<&'static [&'static str]>);
+    impl<'de> Deserializer<'de> for &mut Fields {
+        type Error = de::value::Error;
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("fields only"))
+        }
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            self.0 = Some(fields);
+            Err(de::Error::custom("fields only"))
+        }
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum
+            identifier ignored_any
+        }
+    }
+    static FIELDS: OnceCell<&'static [&'static str]> = OnceCell::new();
+    FIELDS.get_or_init(|| {
+        let mut fields = Fields(None);
+        let _ = Config::deserialize(&mut fields);
+        fields.0.unwrap()
+    })
+}
+
+fn scheme(url: &str) -> Option<&str> {
+    url.split_once("://").map(|(scheme, _)| scheme)
+}
+
+fn deserialize_geth_url<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let url = String::deserialize(deserializer)?;
+    match scheme(&url) {
+        Some("http" | "https") => Ok(url),
+        _ => Err(de::Error::custom(format!(
+            "{}: want an http or https url",
+            url
+        ))),
+    }
+}
+
+// a postgres:// url or libpq key=value pairs
+fn deserialize_pg_url<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let url = String::deserialize(deserializer)?;
+    match scheme(&url) {
+        Some("postgres" | "postgresql") => Ok(url),
+        None if url.contains('=') => Ok(url),
+        _ => Err(de::Error::custom(format!(
+            "{}: want a postgres:// url or key=value pairs",
+            url
+        ))),
+    }
+}
+
+fn deserialize_http_url_option<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|url| match scheme(&url) {
+            Some("http" | "https") => Ok(url),
+            _ => Err(de::Error::custom(format!(
+                "{}: want an http or https url",
+                url
+            ))),
+        })
+        .transpose()
+}
+
+fn deserialize_redis_url<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|url| match scheme(&url) {
+            Some("redis" | "rediss" | "redis+unix" | "unix") => Ok(url),
+            _ => Err(de::Error::custom(format!("{}: want a redis:// url", url))),
+        })
+        .transpose()
+}
+
+// 32 bytes of hex, with or without 0x, that is a valid secp256k1 secret key
+fn deserialize_priv_key<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let Some(text) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let digits = text.trim().trim_start_matches("0x");
+    let bytes =
+        hex::decode(digits).map_err(|err| de::Error::custom(format!("not hex, {}", err)))?;
+    if bytes.len() != 32 {
+        return Err(de::Error::custom(format!("{} bytes, want 32", bytes.len())));
+    }
+    SecretKey::from_slice(&bytes).map_err(|err| de::Error::custom(err.to_string()))?;
+    Ok(Some(digits.to_lowercase()))
+}
+
+// a path that has to be there when gofi starts
+fn deserialize_file<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|path| match fs::metadata(&path) {
+            Ok(_) => Ok(path),
+            Err(err) => Err(de::Error::custom(format!("{}: {}", path, err))),
+        })
+        .transpose()
+}
+
+fn deserialize_metrics_addr<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|addr| match addr.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => Ok(addr),
+            _ => Err(de::Error::custom(format!("{}: want host:port", addr))),
+        })
+        .transpose()
+}
+
+fn deserialize_log_filter<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|filter| {
+            EnvFilter::builder()
+                .parse(&filter)
+                .map(|_| filter.clone())
+                .map_err(|err| de::Error::custom(format!("{}: {}", filter, err)))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d";
+
+    fn file(yaml: &str) -> Mapping {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn valid() -> String {
+        format!(
+            "geth_url: http://localhost:8545
+pg_url: postgres://localhost/gofi
+eth_priv_key: {KEY}
+uniswab: 000000000000000000000000000000000000005b
+preferred_base_token: 0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2
+preferred_coin_token: 0xdac17f958d2ee523a2206206994597c13d831ec7
+minimum_out: 0.1
+tx_gas: 300000
+"
+        )
+    }
+
+    fn no_env() -> std::vec::IntoIter<(String, String)> {
+        vec![].into_iter()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let config = resolve(file(&valid()), None, no_env()).unwrap();
+        assert_eq!(config.eth_priv_key.as_deref(), Some(&KEY[2..]));
+        assert_eq!(config.match_ttl_blocks, 3);
+        assert_eq!(config.block_seconds, 12);
+        assert!(config.exclude_addresses.is_empty());
+        assert_eq!(config.profile, None);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let yaml = format!(
+            "{}profile: mainnet
+profiles:
+  mainnet:
+    geth_url: https://mainnet.example
+  local:
+    geth_url: http://127.0.0.1:8545
+    tx_gas: 500000
+",
+            valid()
+        );
+        let config = resolve(file(&yaml), None, no_env()).unwrap();
+        assert_eq!(config.geth_url, "https://mainnet.example");
+        assert_eq!(config.profile.as_deref(), Some("mainnet"));
+        assert_eq!(config.tx_gas, 300000);
+        // the command line picks over the file
+        let config = resolve(file(&yaml), Some("local"), no_env()).unwrap();
+        assert_eq!(config.geth_url, "http://127.0.0.1:8545");
+        assert_eq!(config.tx_gas, 500000);
+        let errors = resolve(file(&yaml), Some("sepolia"), no_env()).unwrap_err();
+        assert_eq!(
+            errors,
+            ["profile sepolia: not in profiles (mainnet, local)"]
+        );
+    }
+
+    #[test]
+    fn test_env() {
+        let vars = [
+            ("GOFI_TX_GAS", "400000"),
+            ("GOFI_PG_URL", "host=/tmp user=gofi"),
+            ("GOFI_UNISWAB", "0x00000000000000000000000000000000000000aa"),
+            ("GOFI_EXCLUDE_ADDRESSES", "0x00000000000000000000000000000000000000a0, 00000000000000000000000000000000000000b0"),
+            ("GOFI_REDIS_URL", ""),
+            // not fields
+            ("GOFI_E2E_PG_URL", "postgres://elsewhere"),
+            ("GOFI_PROFILE", "local"),
+            ("PG_URL", "postgres://elsewhere"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let yaml = format!("{}redis_url: redis://localhost\n", valid());
+        let config = resolve(file(&yaml), None, vars.into_iter()).unwrap();
+        assert_eq!(config.tx_gas, 400000);
+        assert_eq!(config.pg_url, "host=/tmp user=gofi");
+        assert_eq!(config.uniswab, Address::with_last_byte(0xaa));
+        assert_eq!(
+            config.exclude_addresses,
+            [Address::with_last_byte(0xa0), Address::with_last_byte(0xb0)]
+        );
+        assert_eq!(config.redis_url, None);
+        assert_eq!(config.profile, None);
+    }
+
+    #[test]
+    fn test_chains() {
+        let yaml = format!(
+            "{}chains:
+  base:
+    chain_id: 8453
+    block_seconds: 2
+    geth_url: https://base.example
+    preferred_base_token: 0x4200000000000000000000000000000000000006
+    wrapped_native: 0x4200000000000000000000000000000000000006
+    dexes:
+    - name: uniswap v2
+      factory: 8909dc15e40173ff4699343b6eb8132c65e18ec6
+      fee_bps: 30
+  bsc:
+    chain_id: 56
+    gas_model: legacy
+",
+            valid()
+        );
+        let vars =
+            [("GOFI_TX_GAS", "400000")].map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let config = resolve(file(&yaml), None, vars.into_iter()).unwrap();
+        assert_eq!(config.chain_id, 1);
+        assert_eq!(config.chain, None);
+        assert!(config.dexes.is_empty());
+        assert_eq!(config.chains.len(), 2);
+        let base = &config.chains[0];
+        assert_eq!(base.chain.as_deref(), Some("base"));
+        assert_eq!(base.geth_url, "https://base.example");
+        assert_eq!(base.dexes[0].fee_bps, 30);
+        assert_eq!(base.block_seconds, 2);
+        assert_eq!(config.chains[1].block_seconds, 12);
+        // the rest is shared, the environment included
+        assert_eq!(base.uniswab, config.uniswab);
+        assert_eq!(base.tx_gas, 400000);
+        assert!(base.chains.is_empty());
+        assert_eq!(config.chains[1].gas_model, GasModel::Legacy);
+        let bsc = select(config, "bsc").unwrap();
+        assert_eq!(bsc.chain_id, 56);
+        let config = resolve(file(&yaml), None, no_env()).unwrap();
+        assert_eq!(
+            select(config, "arbitrum").unwrap_err(),
+            ["chain arbitrum: not in chains (base, bsc)"]
+        );
+
+        let yaml = format!(
+            "{}chains:
+  again:
+    tx_gas: lots
+  mainnet:
+    geth_url: https://mainnet.example
+",
+            valid()
+        );
+        let errors = resolve(file(&yaml), None, no_env()).unwrap_err();
+        assert_eq!(errors.len(), 1, "{:#?}", errors);
+        assert!(errors[0].starts_with("chains.again.tx_gas: "));
+        let yaml = yaml.replace("    tx_gas: lots\n", "    chain_id: 10\n");
+        assert_eq!(
+            resolve(file(&yaml), None, no_env()).unwrap_err(),
+            ["chains.mainnet.chain_id: 1 is scanned twice"]
+        );
+    }
+
+    #[test]
+    fn test_wrapped_native() {
+        let weth = format!(
+            "{}wrapped_native: 0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2\n",
+            valid()
+        );
+        assert!(resolve(file(&weth), None, no_env()).is_ok());
+        let other = format!(
+            "{}wrapped_native: 0x4200000000000000000000000000000000000006\n",
+            valid()
+        );
+        let errors = resolve(file(&other), None, no_env()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("preferred_base_token: "),
+            "{}",
+            errors[0]
+        );
+    }
+
+    #[test]
+    fn test_every_error() {
+        let yaml = "geth_url: ftp://localhost
+eth_priv_key: '0x1234'
+uniswab: '0x12'
+preferred_base_token: 0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756cc2
+preferred_coin_token: 0xdac17f958d2ee523a2206206994597c13d831ec7
+minimum_out: lots
+tx_gas: 300000
+metrics_addr: localhost
+log_format: xml
+approval_policy: infinite
+exclude_adresses: []
+";
+        let errors = resolve(file(yaml), None, no_env()).unwrap_err();
+        let fields = errors
+            .iter()
+            .map(|error| error.split(':').next().unwrap())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            fields,
+            [
+                "geth_url",
+                "eth_priv_key",
+                "uniswab",
+                "preferred_base_token",
+                "minimum_out",
+                "metrics_addr",
+                "log_format",
+                "approval_policy",
+                "exclude_adresses",
+                "pg_url",
+            ],
+            "{:#?}",
+            errors
+        );
+        assert!(errors[1].contains("2 bytes, want 32"));
+        assert!(errors[3].contains("checksum"));
+        assert!(errors[7].contains("unknown variant `infinite`"));
+        assert!(errors[8].contains("unknown field"));
+        assert!(errors[9].contains("GOFI_PG_URL"));
+    }
+
+    #[test]
+    fn test_signers() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = dir.path().join("keystore.json");
+        fs::write(&keystore, "{}").unwrap();
+        let without_key = valid().replace(&format!("eth_priv_key: {KEY}\n"), "");
+        let with_keystore = format!("{}eth_keystore: {}\n", without_key, keystore.display());
+        let config = resolve(file(&with_keystore), None, no_env()).unwrap();
+        assert_eq!(config.eth_priv_key, None);
+        assert_eq!(config.eth_keystore.as_deref(), keystore.to_str());
+        let errors = resolve(file(&without_key), None, no_env()).unwrap_err();
+        assert_eq!(
+            errors,
+            ["eth_priv_key, eth_keystore, eth_signer: set exactly one, have none"]
+        );
+        let both = format!("{}eth_keystore: {}\n", valid(), keystore.display());
+        let errors = resolve(file(&both), None, no_env()).unwrap_err();
+        assert!(errors[0].ends_with("have eth_priv_key, eth_keystore"));
+        let missing = format!(
+            "{}eth_signer: {}\n",
+            without_key,
+            dir.path().join("clef.ipc").display()
+        );
+        let errors = resolve(file(&missing), None, no_env()).unwrap_err();
+        assert!(errors[0].starts_with("eth_signer: "));
+    }
+
+    #[test]
+    fn test_bands() {
+        let band = |yaml: &str| resolve(file(&format!("{}{}", valid(), yaml)), None, no_env());
+        let config = band("rebalance_coin_min: 500\nrebalance_coin_max: 1500.5\n").unwrap();
+        assert_eq!(config.rebalance_coin_max, Some(1500.5));
+        assert_eq!(config.rebalance_max_slippage_bps, 50);
+        assert_eq!(
+            band("rebalance_coin_min: 500\n").unwrap_err(),
+            ["rebalance_coin_max: missing, rebalance_coin_min is set"]
+        );
+        let errors = band("rebalance_base_min: 2\nrebalance_base_max: 1\n").unwrap_err();
+        assert_eq!(
+            errors,
+            ["rebalance_base_min: 2 is not below rebalance_base_max 1"]
+        );
+    }
+
+    #[test]
+    fn test_required() {
+        let valid = file(&valid());
+        for field in fields() {
+            let mut file = valid.clone();
+            file.remove(*field);
+            let result = serde_yaml::from_value::<Config>(Value::Mapping(file));
+            assert_eq!(result.is_err(), REQUIRED.contains(field), "{}", field);
+        }
+        assert!(!fields().contains(&"path"));
+    }
+
+    #[test]
+    fn test_values() {
+        let check = |yaml: &str| check(&file(yaml));
+        assert!(check("pg_url: postgresql://gofi@%2Ftmp/gofi").is_empty());
+        assert!(!check("pg_url: mysql://localhost").is_empty());
+        assert!(check("redis_url: rediss://cache:6380").is_empty());
+        assert!(!check("redis_url: localhost:6379").is_empty());
+        assert!(check("metrics_addr: 0.0.0.0:9100").is_empty());
+        assert!(check("log_filter: info,gofi::unipool=warn").is_empty());
+        assert!(!check("log_filter: info,gofi=loud").is_empty());
+        // the curve order is not a key
+        let order = "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141";
+        assert!(!check(&format!("eth_priv_key: {}", order)).is_empty());
+        assert!(!check("eth_priv_key: zz").is_empty());
+        assert!(check("mempool_url: https://node:8545").is_empty());
+        assert!(!check("mempool_url: ws://node:8546").is_empty());
+        assert!(
+            check(
+                "dexes:
+- name: uniswap v2
+  factory: 5c69bee701ef814a2b6a3edd4b1652cb9cc5aa6f
+  router: 7a250d5630b4cf539739df2c5dacb4c659f2488d
+  fee_bps: 30"
+            )
+            .is_empty()
+        );
+        assert!(
+            !check(
+                "dexes:
+- name: uniswap v2
+  factory: '0x12'"
+            )
+            .is_empty()
+        );
+        assert!(check("gas_model: legacy").is_empty());
+        assert!(!check("gas_model: 1559").is_empty());
+        assert_eq!(env_value("tx_gas", "300000"), Value::from(300000));
+        assert_eq!(env_value("uniswab", "0x5b"), Value::from("0x5b"));
+        assert_eq!(env_value("redis_url", ""), Value::Null);
+    }
 }
@@ -0,0 +1,63 @@
+#![allow(clippy::too_many_arguments)] // sol! generated bindings
+
+use alloy::sol;
+
+sol!(
+    #[sol(rpc)]
+    UniswapV2Pair,
+    "sol-abi/UniswapV2Pair.json"
+);
+sol!(
+    #[sol(rpc)]
+    ERC20,
+    "sol-abi/ERC20.json"
+);
+sol!(
+    #[sol(rpc)]
+    UniSwab,
+    "ethereum/artifacts/UniSwab.abi"
+);
+sol!(
+    #[sol(rpc)]
+    UniswapV2Router02,
+    "sol-abi/uniswap-v2-router02.json"
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::sol_types::SolCall;
+
+    // signatures of the public functions declared in a solidity source, eg
+    // swap(uint256,address), without a compiler
+    fn declared_signatures(source: &str) -> Vec<String> {
+        source
+            .split("function ")
+            .skip(1)
+            .filter_map(|declaration| {
+                let (name, rest) = declaration.split_once('(')?;
+                let (params, rest) = rest.split_once(')')?;
+                let modifiers = rest.split('{').next()?;
+                if !modifiers.contains("public") && !modifiers.contains("external") {
+                    return None;
+                }
+                let types: Vec<&str> = params
+                    .split(',')
+                    .filter_map(|param| param.split_whitespace().next())
+                    .collect();
+                Some(format!("{}({})", name.trim(), types.join(",")))
+            })
+            .collect()
+    }
+
+    // the abi is kept in git for the bindings, make -C ethereum regenerates it
+    #[test]
+    fn test_uniswab_abi_matches_source() {
+        let source = std::fs::read_to_string("ethereum/contracts/UniSwab.sol").unwrap();
+        assert_eq!(
+            declared_signatures(&source),
+            [UniSwab::swabCall::SIGNATURE, UniSwab::swapCall::SIGNATURE],
+            "ethereum/artifacts/UniSwab.abi is out of date with UniSwab.sol"
+        );
+    }
+}
@@ -1,32 +1,292 @@
-use std::cmp;
+use std::cmp::Ordering;
+use std::fmt;
 
-// divive two u128 numbers, leaving as much precision as an f64 can hold
-pub(crate) fn scale(num_a: u128, num_b: u128) -> f64 {
-    let al2 = num_a.ilog2();
-    let bl2 = num_b.ilog2();
-    let big = cmp::max(al2, bl2);
-    let drop = if big > 52 { big - 52 } else { 0 }; // f64 = 52bit fraction + 11bit exponent
-    if drop > al2 || drop > bl2 {
-        return 0.0;
+use alloy::primitives::{I256, U256, U512};
+
+// digits after the point kept by ratios, enough for an 18 decimal token priced in a 6 decimal one
+pub const PRICE_DECIMALS: u8 = 18;
+
+// exact signed fixed-point number, value / 10^decimals. ratios are worked out in U512 so a
+// u256 amount scaled by 10^36 fits, only the quotient has to fit I256.
+#[derive(Clone, Copy, Debug)]
+pub struct Decimal {
+    value: I256,
+    decimals: u8,
+}
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal {
+        value: I256::ZERO,
+        decimals: 0,
+    };
+    pub const ONE: Decimal = Decimal {
+        value: I256::ONE,
+        decimals: 0,
+    };
+
+    pub fn new(value: I256, decimals: u8) -> Decimal {
+        Decimal { value, decimals }
+    }
+
+    // a raw token amount, e.g. 1500000 with 6 decimals is 1.5. None past 2^255.
+    pub fn from_units(amount: U256, decimals: u8) -> Option<Decimal> {
+        Some(Decimal::new(I256::try_from(amount).ok()?, decimals))
+    }
+
+    pub fn from_signed_units(amount: I256, decimals: u8) -> Decimal {
+        Decimal::new(amount, decimals)
+    }
+
+    pub fn from_int(n: i64) -> Decimal {
+        Decimal::new(I256::try_from(n).unwrap(), 0)
+    }
+
+    // num / den where each raw amount has its own token decimals, to PRICE_DECIMALS digits.
+    // None when den is zero or the decimals are too large to scale.
+    pub fn ratio(num: U256, num_decimals: u8, den: U256, den_decimals: u8) -> Option<Decimal> {
+        if den.is_zero() {
+            return None;
+        }
+        let num = U512::from(num).checked_mul(U512::from(pow10(
+            PRICE_DECIMALS as u32 + den_decimals as u32,
+        )?))?;
+        let den = U512::from(den).checked_mul(U512::from(pow10(num_decimals as u32)?))?;
+        let value = I256::try_from(U256::checked_from_limbs_slice((num / den).as_limbs())?).ok()?;
+        Some(Decimal::new(value, PRICE_DECIMALS))
+    }
+
+    pub fn decimals(&self) -> u8 {
+        self.decimals
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.value.is_negative()
+    }
+
+    // same number with more or fewer digits. dropped digits are truncated toward zero.
+    pub fn rescale(&self, decimals: u8) -> Option<Decimal> {
+        let value = match decimals.cmp(&self.decimals) {
+            Ordering::Equal => self.value,
+            Ordering::Greater => {
+                let factor = I256::try_from(pow10((decimals - self.decimals) as u32)?).ok()?;
+                self.value.checked_mul(factor)?
+            }
+            Ordering::Less => match pow10((self.decimals - decimals) as u32)
+                .and_then(|factor| I256::try_from(factor).ok())
+            {
+                Some(factor) => self.value / factor,
+                None => I256::ZERO,
+            },
+        };
+        Some(Decimal::new(value, decimals))
+    }
+
+    pub fn checked_add(&self, other: Decimal) -> Option<Decimal> {
+        let decimals = self.decimals.max(other.decimals);
+        let value = self
+            .rescale(decimals)?
+            .value
+            .checked_add(other.rescale(decimals)?.value)?;
+        Some(Decimal::new(value, decimals))
+    }
+
+    pub fn checked_sub(&self, other: Decimal) -> Option<Decimal> {
+        let negated = Decimal::new(other.value.checked_neg()?, other.decimals);
+        self.checked_add(negated)
+    }
+
+    // exact up to PRICE_DECIMALS digits, or the larger of the two decimals
+    pub fn checked_mul(&self, other: Decimal) -> Option<Decimal> {
+        let exact = self.decimals as u32 + other.decimals as u32;
+        let decimals = exact.min(PRICE_DECIMALS.max(self.decimals).max(other.decimals) as u32);
+        let product = self.value.checked_mul(other.value)?;
+        let factor = I256::try_from(pow10(exact - decimals)?).ok()?;
+        Some(Decimal::new(product / factor, decimals as u8))
+    }
+
+    // quotient to PRICE_DECIMALS digits. None when dividing by zero.
+    pub fn checked_div(&self, other: Decimal) -> Option<Decimal> {
+        if other.value.is_zero() {
+            return None;
+        }
+        let digits = PRICE_DECIMALS as u32 + other.decimals as u32;
+        let num = if digits > u8::MAX as u32 {
+            None
+        } else {
+            self.rescale(digits as u8)
+        }?;
+        Some(Decimal::new(num.value / other.value, PRICE_DECIMALS))
+    }
+
+    // for metrics and other places that only want an approximation
+    pub fn to_f64(self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+}
+
+fn pow10(exponent: u32) -> Option<U256> {
+    U256::from(10).checked_pow(U256::from(exponent))
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        let sign = |d: &Decimal| d.value.cmp(&I256::ZERO);
+        match sign(self).cmp(&sign(other)) {
+            Ordering::Equal => {}
+            unequal => return unequal,
+        }
+        // same sign: compare magnitudes at the same number of decimals
+        let magnitude = |d: &Decimal, decimals: u8| {
+            U512::from(d.value.unsigned_abs())
+                .checked_mul(U512::from(10).checked_pow(U512::from(decimals - d.decimals))?)
+        };
+        let decimals = self.decimals.max(other.decimals);
+        let ordering = match (magnitude(self, decimals), magnitude(other, decimals)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            // only a nonzero magnitude can overflow when scaled up
+            (None, _) => Ordering::Greater,
+            (_, None) => Ordering::Less,
+        };
+        if self.is_negative() {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+// trailing zeros are trimmed. a precision, as in {:.4}, truncates or pads to that many digits.
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let decimals = self.decimals as usize;
+        let digits = self.value.unsigned_abs().to_string();
+        let digits = format!("{:0>width$}", digits, width = decimals + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - decimals);
+        let fraction = match f.precision() {
+            Some(precision) if precision <= decimals => fraction[..precision].to_owned(),
+            Some(precision) => format!("{:0<precision$}", fraction, precision = precision),
+            None => fraction.trim_end_matches('0').to_owned(),
+        };
+        let sign = if self.is_negative() { "-" } else { "" };
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, whole)
+        } else {
+            write!(f, "{}{}.{}", sign, whole, fraction)
+        }
+    }
+}
+
+impl serde::Serialize for Decimal {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
     }
-    let a = (num_a >> drop) as f64;
-    let b = (num_b >> drop) as f64;
-    a / b
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn ratio(num: u128, den: u128) -> Decimal {
+        Decimal::ratio(U256::from(num), 0, U256::from(den), 0).unwrap()
+    }
+
+    fn units(amount: u128, decimals: u8) -> Decimal {
+        Decimal::from_units(U256::from(amount), decimals).unwrap()
+    }
+
+    fn signed(amount: i64, decimals: u8) -> Decimal {
+        Decimal::from_signed_units(I256::try_from(amount).unwrap(), decimals)
+    }
+
+    #[test]
+    fn test_ratio() {
+        assert_eq!(ratio(1, 1), Decimal::ONE);
+        assert_eq!(ratio(2_u128.pow(127), 2_u128.pow(127)), Decimal::ONE);
+        // an f64 cannot hold this, the old scale() called it zero
+        assert_eq!(ratio(2_u128.pow(53), 1).to_string(), "9007199254740992");
+        assert_eq!(ratio(1, 10).to_string(), "0.1");
+        assert_eq!(ratio(1, 3).to_string(), "0.333333333333333333");
+        assert_eq!(ratio(u128::MAX, 1).to_string(), u128::MAX.to_string());
+        assert_eq!(Decimal::ratio(U256::from(1), 0, U256::ZERO, 0), None);
+        assert_eq!(Decimal::ratio(U256::MAX, 0, U256::from(1), 0), None);
+        assert_eq!(Decimal::from_units(U256::MAX, 0), None);
+        // 3000 usdc (6 decimals) for 1 weth (18 decimals)
+        let price = Decimal::ratio(
+            U256::from(3000 * 10_u128.pow(6)),
+            6,
+            U256::from(10_u128.pow(18)),
+            18,
+        )
+        .unwrap();
+        assert_eq!(price.to_string(), "3000");
+        // and the other way around
+        let price = Decimal::ratio(
+            U256::from(10_u128.pow(18)),
+            18,
+            U256::from(3000 * 10_u128.pow(6)),
+            6,
+        )
+        .unwrap();
+        assert_eq!(price.to_string(), "0.000333333333333333");
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(units(1500000, 6).to_string(), "1.5");
+        assert_eq!(units(5, 6).to_string(), "0.000005");
+        assert_eq!(signed(-18608, 0).to_string(), "-18608");
+        assert_eq!(signed(-5, 2).to_string(), "-0.05");
+        assert_eq!(format!("{:.4}", units(123456789, 6)), "123.4567");
+        assert_eq!(format!("{:.4}", units(15, 1)), "1.5000");
+        assert_eq!(format!("{:.0}", units(15, 1)), "1");
+        assert_eq!(Decimal::ZERO.to_string(), "0");
+    }
+
+    #[test]
+    fn test_cmp() {
+        assert_eq!(units(1, 0), units(1000, 3));
+        assert!(units(1, 18) > Decimal::ZERO);
+        assert!(units(2, 6) > units(1999999, 12));
+        assert!(signed(-2, 0) < signed(-1, 0));
+        assert!(signed(-1, 18) < Decimal::ZERO);
+        // scaling 1 up to 255 decimals overflows, it still compares
+        assert!(units(1, 0) > units(u128::MAX, 255));
+        let mut profits = vec![ratio(1, 3), ratio(2_u128.pow(53), 1), Decimal::ZERO];
+        profits.sort();
+        assert_eq!(
+            profits,
+            vec![Decimal::ZERO, ratio(1, 3), ratio(2_u128.pow(53), 1)]
+        );
+    }
+
     #[test]
-    fn test_scale() {
-        assert_eq!(scale(1_u128, 1_u128), 1.0);
-        assert_eq!(scale(10_u128, 10_u128), 1.0);
-        assert_eq!(scale(2_u128.pow(127), 2_u128.pow(127)), 1.0);
-        assert_eq!(scale(2_u128.pow(52), 1_u128), 4503599627370496.0);
-        assert_eq!(scale(2_u128.pow(53), 1_u128), 0.0); // more than a 52 bit difference is a ratio of zero
-        assert_eq!(scale(2_u128.pow(53), 2_u128), 4503599627370496.0); // half a 53 bit number is 52 bits
-        assert_eq!(scale(10_u128, 1_u128), 10.0);
-        assert_eq!(scale(1_u128, 10_u128), 0.1);
+    fn test_arithmetic() {
+        let a = units(15, 1);
+        let b = units(25, 2);
+        assert_eq!(a.checked_add(b).unwrap().to_string(), "1.75");
+        assert_eq!(b.checked_sub(a).unwrap().to_string(), "-1.25");
+        assert_eq!(a.checked_mul(b).unwrap().to_string(), "0.375");
+        assert_eq!(a.checked_div(b).unwrap().to_string(), "6");
+        assert_eq!(a.checked_div(Decimal::ZERO), None);
+        assert_eq!(ratio(2, 3).rescale(2).unwrap().to_string(), "0.66");
+        assert_eq!(
+            ratio(2, 3).checked_mul(ratio(3, 1)).unwrap().to_string(),
+            "1.999999999999999998"
+        );
+        assert_eq!(units(2, 0).to_f64(), 2.0);
     }
 }
@@ -0,0 +1,139 @@
+use alloy::primitives::{Address, B256, b256, keccak256};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::unipool::POOL_FEE_BASIS_POINTS;
+use crate::{Pair, address};
+
+// the uniswap v2 forks a chain has. the indexer's pools table does not say which factory
+// made a pool, so a pool is matched to its dex by working out the pair address the
+// factory would have deployed for its tokens.
+
+pub const UNISWAP_INIT_CODE_HASH: B256 =
+    b256!("96e8ac4277198ff8b6f785478aa9a39f403cb768dd02cbee326c3e7da348845f");
+
+// a factory, the fee its pairs charge and the router02 in front of it, if any
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Dex {
+    pub name: String,
+    #[serde(deserialize_with = "address::deserialize")]
+    pub factory: Address,
+    // swaps sent to it are decoded from the mempool
+    #[serde(default, deserialize_with = "address::deserialize_option")]
+    pub router: Option<Address>,
+    #[serde(default = "default_fee_bps")]
+    pub fee_bps: u8,
+    #[serde(default = "uniswap_init_code_hash")]
+    pub init_code_hash: B256,
+}
+
+fn default_fee_bps() -> u8 {
+    POOL_FEE_BASIS_POINTS
+}
+
+fn uniswap_init_code_hash() -> B256 {
+    UNISWAP_INIT_CODE_HASH
+}
+
+impl Dex {
+    // UniswapV2Library.pairFor
+    pub fn pair_for(&self, token_a: Address, token_b: Address) -> Address {
+        let (token0, token1) = sorted(token_a, token_b);
+        let salt = keccak256([token0.as_slice(), token1.as_slice()].concat());
+        self.factory.create2(salt, self.init_code_hash)
+    }
+}
+
+// the dex that deployed pool, a pair of token0 and token1
+pub fn find(dexes: &[Dex], pool: Address, token0: Address, token1: Address) -> Option<&Dex> {
+    dexes
+        .iter()
+        .find(|dex| dex.pair_for(token0, token1) == pool)
+}
+
+// pairs with each pool's fee set from its dex. with no dexes configured every pool is
+// taken to be uniswap v2's, otherwise pairs with a pool of an unknown factory are dropped.
+pub fn priced(dexes: &[Dex], pairs: Vec<Pair>) -> Vec<Pair> {
+    if dexes.is_empty() {
+        return pairs;
+    }
+    pairs
+        .into_iter()
+        .filter_map(|mut pair| {
+            for snapshot in [&mut pair.pool0, &mut pair.pool1] {
+                let pool = &mut snapshot.pool;
+                match find(
+                    dexes,
+                    pool.contract_address,
+                    pool.coin0.contract_address,
+                    pool.coin1.contract_address,
+                ) {
+                    Some(dex) => pool.fee_bps = dex.fee_bps,
+                    None => {
+                        debug!(pool = %pool.contract_address, "pool of no known dex");
+                        return None;
+                    }
+                }
+            }
+            Some(pair)
+        })
+        .collect()
+}
+
+fn sorted(token_a: Address, token_b: Address) -> (Address, Address) {
+    if token_a < token_b {
+        (token_a, token_b)
+    } else {
+        (token_b, token_a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+
+    use super::*;
+    use crate::fixture::{COIN0, COIN1, POOL1, PairBuilder};
+
+    fn dex(name: &str, factory: u8, fee_bps: u8) -> Dex {
+        Dex {
+            name: name.to_owned(),
+            factory: Address::with_last_byte(factory),
+            router: None,
+            fee_bps,
+            init_code_hash: UNISWAP_INIT_CODE_HASH,
+        }
+    }
+
+    #[test]
+    fn test_pair_for() {
+        // mainnet USDC/WETH
+        let uniswap = Dex {
+            factory: address!("5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f"),
+            ..dex("uniswap", 0, 30)
+        };
+        let usdc = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+        let weth = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let pair = address!("B4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc");
+        assert_eq!(uniswap.pair_for(usdc, weth), pair);
+        assert_eq!(uniswap.pair_for(weth, usdc), pair);
+    }
+
+    #[test]
+    fn test_priced() {
+        let (cheap, dear) = (dex("cheap", 0xfa, 25), dex("dear", 0xfb, 30));
+        let pair = PairBuilder::new()
+            .addresses(cheap.pair_for(COIN0, COIN1), dear.pair_for(COIN0, COIN1))
+            .build();
+        let priced = priced(&[cheap.clone(), dear.clone()], vec![pair.clone()]);
+        assert_eq!(priced.len(), 1);
+        assert_eq!(priced[0].pool0.pool.fee_bps, 25);
+        assert_eq!(priced[0].pool1.pool.fee_bps, 30);
+        // pool1 is no dex's
+        let unknown = PairBuilder::new()
+            .addresses(cheap.pair_for(COIN0, COIN1), POOL1)
+            .build();
+        assert!(super::priced(&[cheap, dear], vec![unknown.clone()]).is_empty());
+        assert_eq!(super::priced(&[], vec![unknown]).len(), 1);
+    }
+}
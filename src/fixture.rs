@@ -0,0 +1,298 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use alloy::primitives::{Address, U256};
+use postgres::{Client, NoTls};
+use serde::Deserialize;
+
+use crate::unipool::POOL_FEE_BASIS_POINTS;
+use crate::{Coin, Match, Pair, Pool, PoolSnapshot, Reserve, address, record};
+
+// test builders for the domain types plus snapshots of real pairs in tests/fixtures.
+// the defaults are the deploy_uniswap.sh pools, 310000/210000 and 220000/320000 of
+// USDONA/USDONC, where pool0 is the cheaper one.
+
+pub const COIN0: Address = Address::with_last_byte(0xc0);
+pub const COIN1: Address = Address::with_last_byte(0xc1);
+pub const POOL0: Address = Address::with_last_byte(0xa0);
+pub const POOL1: Address = Address::with_last_byte(0xb0);
+
+pub fn coin(contract_address: Address, symbol: &str, decimals: i32) -> Coin {
+    Coin {
+        contract_address,
+        symbol: symbol.to_owned(),
+        decimals,
+    }
+}
+
+pub struct PairBuilder {
+    coin0: Coin,
+    coin1: Coin,
+    pools: [(Address, U256, U256, u32, u32); 2],
+    fees: [u8; 2],
+    flipped: bool,
+}
+
+impl PairBuilder {
+    pub fn new() -> PairBuilder {
+        PairBuilder {
+            coin0: coin(COIN0, "USDONA", 18),
+            coin1: coin(COIN1, "USDONC", 18),
+            pools: [
+                (POOL0, U256::from(310000), U256::from(210000), 1, 1),
+                (POOL1, U256::from(220000), U256::from(320000), 1, 1),
+            ],
+            fees: [POOL_FEE_BASIS_POINTS; 2],
+            flipped: false,
+        }
+    }
+
+    pub fn coins(mut self, coin0: Coin, coin1: Coin) -> PairBuilder {
+        self.coin0 = coin0;
+        self.coin1 = coin1;
+        self
+    }
+
+    // reserves as (x, y), coin0 then coin1
+    pub fn pool0(mut self, x: u128, y: u128) -> PairBuilder {
+        (self.pools[0].1, self.pools[0].2) = (U256::from(x), U256::from(y));
+        self
+    }
+
+    pub fn pool1(mut self, x: u128, y: u128) -> PairBuilder {
+        (self.pools[1].1, self.pools[1].2) = (U256::from(x), U256::from(y));
+        self
+    }
+
+    pub fn addresses(mut self, pool0: Address, pool1: Address) -> PairBuilder {
+        self.pools[0].0 = pool0;
+        self.pools[1].0 = pool1;
+        self
+    }
+
+    // (block number, block timestamp) of each pool's reserves
+    pub fn blocks(mut self, pool0: (u32, u32), pool1: (u32, u32)) -> PairBuilder {
+        (self.pools[0].3, self.pools[0].4) = pool0;
+        (self.pools[1].3, self.pools[1].4) = pool1;
+        self
+    }
+
+    // fee bps of each pool's dex
+    pub fn fees(mut self, pool0: u8, pool1: u8) -> PairBuilder {
+        self.fees = [pool0, pool1];
+        self
+    }
+
+    // coin0 is both pools' token1, the reserves stay (x, y)
+    pub fn flipped(mut self) -> PairBuilder {
+        self.flipped = true;
+        self
+    }
+
+    // pool1 first, the direction that never has an arb
+    pub fn reversed(mut self) -> PairBuilder {
+        self.pools.swap(0, 1);
+        self.fees.swap(0, 1);
+        self
+    }
+
+    pub fn build(self) -> Pair {
+        let mut fees = self.fees.into_iter();
+        let [pool0, pool1] =
+            self.pools
+                .map(
+                    |(contract_address, x, y, block_number, block_timestamp)| PoolSnapshot {
+                        pool: Pool {
+                            contract_address,
+                            coin0: self.coin0.clone(),
+                            coin1: self.coin1.clone(),
+                            fee_bps: fees.next().unwrap(),
+                            flipped: self.flipped,
+                        },
+                        reserve: Reserve {
+                            contract_address,
+                            x,
+                            y,
+                            block_number,
+                            block_timestamp,
+                        },
+                    },
+                );
+        Pair { pool0, pool1 }
+    }
+
+    // the match trade_simulate finds
+    pub fn simulate(self) -> Match {
+        crate::trade_simulate(self.build()).unwrap()
+    }
+
+    // a match with given amounts, simulated or not
+    pub fn matched(self, ay_in: u128, ax_out: u128, ay_out: u128) -> Match {
+        Match {
+            pair: self.build(),
+            pool0_ay_in: U256::from(ay_in),
+            pool0_ax_out: U256::from(ax_out),
+            pool1_ay_out: U256::from(ay_out),
+        }
+    }
+}
+
+// a pair as logged by gofi, in yaml or json. amounts are strings so they survive
+// json parsers that read numbers as f64.
+#[derive(Deserialize)]
+pub struct Fixture {
+    pub coin0: CoinFixture,
+    pub coin1: CoinFixture,
+    pub pool0: PoolFixture,
+    pub pool1: PoolFixture,
+    pub expect: Option<Expect>,
+}
+
+#[derive(Deserialize)]
+pub struct CoinFixture {
+    #[serde(deserialize_with = "address::deserialize")]
+    pub address: Address,
+    pub symbol: String,
+    pub decimals: i32,
+}
+
+#[derive(Deserialize)]
+pub struct PoolFixture {
+    #[serde(deserialize_with = "address::deserialize")]
+    pub address: Address,
+    pub reserve0: String,
+    pub reserve1: String,
+    pub block_number: u32,
+    pub block_timestamp: u32,
+}
+
+// what trade_simulate found when the fixture was recorded
+#[derive(Deserialize)]
+pub struct Expect {
+    pub ay_in: String,
+    pub profit: String,
+}
+
+impl Fixture {
+    pub fn pair(&self) -> Pair {
+        let amount = |digits: &str| digits.parse::<u128>().unwrap();
+        PairBuilder::new()
+            .coins(
+                coin(self.coin0.address, &self.coin0.symbol, self.coin0.decimals),
+                coin(self.coin1.address, &self.coin1.symbol, self.coin1.decimals),
+            )
+            .addresses(self.pool0.address, self.pool1.address)
+            .pool0(amount(&self.pool0.reserve0), amount(&self.pool0.reserve1))
+            .pool1(amount(&self.pool1.reserve0), amount(&self.pool1.reserve1))
+            .blocks(
+                (self.pool0.block_number, self.pool0.block_timestamp),
+                (self.pool1.block_number, self.pool1.block_timestamp),
+            )
+            .build()
+    }
+}
+
+pub fn dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+// a file in tests/fixtures, json by extension, otherwise yaml
+pub fn load(name: &str) -> Fixture {
+    let path = dir().join(name);
+    let text = fs::read_to_string(&path).unwrap_or_else(|err| panic!("{} {}", path.display(), err));
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        serde_json::from_str(&text).unwrap_or_else(|err| panic!("{} {}", path.display(), err))
+    } else {
+        serde_yaml::from_str(&text).unwrap_or_else(|err| panic!("{} {}", path.display(), err))
+    }
+}
+
+#[test]
+fn test_builder() {
+    let pair = PairBuilder::new().build();
+    assert_eq!(pair.pool0.pool.contract_address, POOL0);
+    assert_eq!(pair.pool1.reserve.y, U256::from(320000));
+    assert_eq!(pair.pool0.pool.coin1.symbol, "USDONC");
+    let pair = PairBuilder::new().reversed().build();
+    assert_eq!(pair.pool0.pool.contract_address, POOL1);
+    assert_eq!(pair.pool0.reserve.x, U256::from(220000));
+    let r#match = PairBuilder::new().simulate();
+    assert_eq!(r#match.pool0_ay_in, U256::from(40371));
+    assert_eq!(r#match.profit(), U256::from(18608));
+}
+
+// every snapshot in tests/fixtures still simulates to what was recorded.
+// a historical opportunity becomes a regression test by dropping its file in there.
+#[test]
+fn test_fixtures() {
+    let mut names = fs::read_dir(dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| {
+            [".yaml", ".yml", ".json"]
+                .iter()
+                .any(|ext| name.ends_with(ext))
+        })
+        .collect::<Vec<String>>();
+    names.sort();
+    assert!(!names.is_empty());
+    for name in names {
+        let fixture = load(&name);
+        let expect = fixture
+            .expect
+            .as_ref()
+            .unwrap_or_else(|| panic!("{} has no expect", name));
+        let r#match =
+            crate::trade_simulate(fixture.pair()).unwrap_or_else(|err| panic!("{} {}", name, err));
+        assert_eq!(
+            r#match.pool0_ay_in.to_string(),
+            expect.ay_in,
+            "{} ay_in",
+            name
+        );
+        assert_eq!(
+            r#match.profit().to_string(),
+            expect.profit,
+            "{} profit",
+            name
+        );
+    }
+}
+
+// a fresh schema in GOFI_E2E_PG_URL's database with the indexer's tables and gofi's,
+// dropped afterwards. test names the schema so tests can run in parallel.
+pub struct Schema {
+    pub db: Client,
+    name: String,
+}
+
+impl Schema {
+    pub fn create(test: &str) -> Schema {
+        let pg_url = std::env::var("GOFI_E2E_PG_URL").expect("GOFI_E2E_PG_URL");
+        let mut db = Client::connect(&pg_url, NoTls).unwrap();
+        let name = format!("gofi_{}_{}", test, std::process::id());
+        db.batch_execute(&format!(
+            "DROP SCHEMA IF EXISTS {name} CASCADE;
+             CREATE SCHEMA {name};
+             SET search_path TO {name};
+             CREATE TABLE coins (contract_address VARCHAR PRIMARY KEY, symbol VARCHAR NOT NULL, decimals INTEGER NOT NULL);
+             CREATE TABLE pools (contract_address VARCHAR PRIMARY KEY, token0 VARCHAR NOT NULL, token1 VARCHAR NOT NULL);
+             CREATE TABLE blocks (number INTEGER PRIMARY KEY, timestamp INTEGER NOT NULL);
+             CREATE TABLE reserves (contract_address VARCHAR NOT NULL, block_number INTEGER NOT NULL, x VARCHAR NOT NULL, y VARCHAR NOT NULL);"
+        ))
+        .unwrap();
+        record::create_tables(&mut db).unwrap();
+        Schema { db, name }
+    }
+}
+
+impl Drop for Schema {
+    fn drop(&mut self) {
+        let _ = self
+            .db
+            .batch_execute(&format!("DROP SCHEMA IF EXISTS {} CASCADE", self.name));
+    }
+}
@@ -1,20 +1,47 @@
 #![allow(dead_code)]
 
-use std::cmp;
+use std::cmp::{self, Reverse};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use alloy::{
-    primitives::{Address, U256, utils::format_units},
+    primitives::{Address, I256, U256, utils::format_units},
     providers::{Provider, ProviderBuilder},
-    signers::local::PrivateKeySigner,
-    sol,
     transports::http::reqwest::Url,
 };
 use chrono::DateTime;
-use hex::decode;
+use clap::{Parser, Subcommand};
 use postgres::{Client, NoTls};
+use tracing::{debug, error, info, info_span, warn};
+use tracing_subscriber::EnvFilter;
 
+use chain::Chain;
+use decimal::Decimal;
+use repository::{ChainDb, PairRepository};
+
+mod address;
+mod allowance;
+mod cache;
+mod chain;
 mod config;
+mod contracts;
 mod decimal;
+mod dex;
+#[cfg(test)]
+mod fixture;
+mod mempool;
+mod metrics;
+mod nonce;
+mod rebalance;
+mod record;
+mod report;
+mod repository;
+mod revert;
+mod risk;
+mod rule;
+mod screen;
+mod signer;
+mod supervise;
 mod unipool;
 
 macro_rules! sql_field {
@@ -23,275 +50,825 @@ macro_rules! sql_field {
     };
 }
 
-fn init() {
-    config::CONFIG
-        .set(config::read_type(config::FILENAME))
-        .unwrap();
-    tracing_subscriber::fmt::init();
+fn init(args: &Args) {
+    let config = config::load(&args.config, args.profile.as_deref(), args.chain.as_deref())
+        .unwrap_or_else(|errors| {
+            eprintln!("bad config {}", args.config);
+            for error in errors {
+                eprintln!("  {}", error);
+            }
+            std::process::exit(2);
+        });
+    config::CONFIG.set(config).unwrap();
+    let config = config::CONFIG.get().unwrap();
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(config.log_filter.as_deref().unwrap_or("info")));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.log_format {
+        config::LogFormat::Text => subscriber.init(),
+        config::LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+#[derive(Parser)]
+#[command(name = "gofi", about = "uniswap v2 pair arbitrage")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// config file
+    #[arg(long, global = true, env = "GOFI_CONFIG", default_value = config::FILENAME)]
+    config: String,
+    /// a section of the config file's profiles, eg mainnet, sepolia or local
+    #[arg(long, global = true, env = "GOFI_PROFILE")]
+    profile: Option<String>,
+    /// one of the config file's chains to work on alone, eg base. scan otherwise scans
+    /// them all, the other commands work on the top level chain
+    #[arg(long, global = true, env = "GOFI_CHAIN")]
+    chain: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// find pair arbitrage and swab the best match (default)
+    Scan(ScanArgs),
+    /// realized pnl from recorded executions
+    Report(report::ReportArgs),
+    /// approve uniswab for the coins gofi swabs and revoke excluded ones
+    Approve(allowance::ApproveArgs),
+    /// lift a risk limit halt so scan trades again
+    Resume,
+}
+
+#[derive(clap::Args, Clone, Copy, Default)]
+struct ScanArgs {
+    /// keep scanning, starting a new cycle every this many seconds
+    #[arg(long)]
+    every: Option<u64>,
 }
 
 fn main() -> Result<(), postgres::Error> {
-    init();
+    let args = Args::parse();
+    init(&args);
 
     let config = config::CONFIG.get().unwrap();
-    let pk_signer: PrivateKeySigner = config.eth_priv_key.parse().unwrap();
-    let my_address = pk_signer.address();
+    let mut db = chain_db(config)?;
+    if let Some(metrics_addr) = &config.metrics_addr {
+        let addr = metrics::serve(metrics_addr).unwrap();
+        info!(%addr, "serving /metrics");
+    }
+    match args.command.unwrap_or(Command::Scan(ScanArgs::default())) {
+        Command::Scan(scan_args) => scan_chains(config, db, scan_args),
+        Command::Report(report_args) => report::run(&mut db.client, &report_args),
+        Command::Approve(approve_args) => {
+            metrics::set_chain_id(config.chain_id);
+            let (chain, my_address) = connect(config);
+            allowance::run(config, &mut db, &chain, my_address, &approve_args).unwrap_or_else(
+                |err| {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                },
+            );
+            Ok(())
+        }
+        Command::Resume => risk::resume(config, &mut db),
+    }
+}
+
+fn chain_db(config: &config::Config) -> Result<ChainDb, postgres::Error> {
+    let mut client = Client::connect(&config.pg_url, NoTls)?;
+    record::create_tables(&mut client)?;
+    Ok(ChainDb {
+        client,
+        chain_id: config.chain_id,
+    })
+}
+
+// the top level chain and each of config.chains scan in a thread of their own. the first
+// to fail or panic ends gofi, the others would otherwise go on unwatched.
+fn scan_chains(
+    config: &'static config::Config,
+    db: ChainDb,
+    args: ScanArgs,
+) -> Result<(), postgres::Error> {
+    let chains = std::iter::once(config)
+        .chain(config.chains.iter())
+        .collect::<Vec<_>>();
+    let mut db = Some(db);
+    let jobs = chains
+        .iter()
+        .map(|&chain| {
+            let db = db.take();
+            Box::new(move || {
+                let mut db = match db {
+                    Some(db) => db,
+                    None => chain_db(chain)?,
+                };
+                scan(chain, &mut db, &args)
+            }) as supervise::Job<postgres::Error>
+        })
+        .collect();
+    match supervise::run(jobs) {
+        Ok(()) => Ok(()),
+        Err(supervise::Stopped::Failed(index, err)) => {
+            error!(chain_id = chains[index].chain_id, %err, "scan failed");
+            Err(err)
+        }
+        Err(supervise::Stopped::Panicked(index, message)) => {
+            error!(chain_id = chains[index].chain_id, message, "scan panicked");
+            std::process::exit(1);
+        }
+    }
+}
+
+// the node with the trading key and its address
+fn connect(config: &config::Config) -> (chain::Rpc<impl Provider>, Address) {
+    let signer = signer::Signer::load(config).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(2);
+    });
+    let my_address = signer.address();
     let provider = ProviderBuilder::new()
-        .wallet(pk_signer)
+        .wallet(signer.wallet())
         .with_gas_estimation()
         .connect_http(config.geth_url.parse::<Url>().unwrap());
-    let mut db = Client::connect(&config.pg_url, NoTls)?;
-    println!(
-        "gofi config:{} eth:0x{}",
-        config::FILENAME,
-        config.public_key(),
+    let replace = nonce::Replace {
+        after_blocks: config.replace_after_blocks,
+        max_replacements: config.max_replacements,
+        poll: Duration::from_secs(1),
+    };
+    let chain = chain::Rpc::new(
+        provider,
+        my_address,
+        config.uniswab,
+        config.tx_gas,
+        config.gas_model,
+        replace,
+    );
+    match chain.chain_id() {
+        Ok(chain_id) if chain_id == config.chain_id => {}
+        Ok(chain_id) => {
+            eprintln!(
+                "{} is chain {}, chain_id is {}",
+                config.geth_url, chain_id, config.chain_id
+            );
+            std::process::exit(2);
+        }
+        Err(err) => {
+            eprintln!("{}: {}", config.geth_url, err);
+            std::process::exit(2);
+        }
+    }
+    (chain, my_address)
+}
+
+fn scan(config: &config::Config, db: &mut ChainDb, args: &ScanArgs) -> Result<(), postgres::Error> {
+    // scan_chains gives each chain a thread, what it records below is this chain's
+    metrics::set_chain_id(config.chain_id);
+    let (chain, my_address) = connect(config);
+    let mut cache = config.redis_url.as_ref().map(|url| {
+        cache::Cache::connect(
+            url,
+            config.chain_id,
+            config.match_ttl_blocks,
+            config.block_seconds,
+        )
+        .unwrap()
+    });
+    info!(
+        config = config.path,
+        profile = config.profile,
+        chain = config.chain,
+        chain_id = config.chain_id,
+        eth = format!("0x{}", hex::encode(my_address)),
+        "gofi"
     );
+    let screener = config.screen_url.as_ref().map(|url| {
+        let provider = ProviderBuilder::new().connect_http(url.parse::<Url>().unwrap());
+        screen::Fork::new(provider, &config.screener_code).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        })
+    });
+    let watcher = config.mempool_url.as_ref().map(|url| {
+        mempool::Watcher::new(ProviderBuilder::new().connect_http(url.parse::<Url>().unwrap()))
+    });
+    if config.approve_on_start {
+        let pairs = db.pairs_with(config.preferred_base_token)?;
+        let approvals = allowance::plan(config, &chain, config.approval_policy, &pairs, my_address)
+            .and_then(|approvals| allowance::apply(&chain, &approvals));
+        if let Err(err) = approvals {
+            warn!(%err, "allowances not set");
+        }
+    }
+
+    for cycle in 1.. {
+        info_span!("scan_cycle", chain_id = config.chain_id, cycle).in_scope(|| {
+            let screener = screener.as_ref().map(|fork| fork as &dyn screen::Screen);
+            let watcher = watcher
+                .as_ref()
+                .map(|watcher| watcher as &dyn mempool::PendingSource);
+            scan_cycle(
+                config, db, &chain, screener, watcher, &mut cache, my_address,
+            )
+        })?;
+        match args.every {
+            Some(seconds) => thread::sleep(Duration::from_secs(seconds)),
+            None => break,
+        }
+    }
+    Ok(())
+}
 
-    let pools_count = rows_count(&mut db, "pools");
-    println!(
-        "sql finding pairs from {} pools where token0 = {}",
-        pools_count, &config.preferred_base_token
+#[allow(clippy::too_many_arguments)]
+fn scan_cycle(
+    config: &config::Config,
+    db: &mut impl PairRepository,
+    chain: &impl Chain,
+    screener: Option<&dyn screen::Screen>,
+    watcher: Option<&dyn mempool::PendingSource>,
+    cache: &mut Option<cache::Cache>,
+    my_address: Address,
+) -> Result<(), postgres::Error> {
+    let chain_id = config.chain_id.to_string();
+    metrics::SCAN_CYCLES.with_label_values(&[&chain_id]).inc();
+    let pools_count = db.pools_count()?;
+    metrics::POOLS_LOADED
+        .with_label_values(&[&chain_id])
+        .set(pools_count);
+    info!(
+        pools = pools_count,
+        base_token = %config.preferred_base_token,
+        "sql finding pairs"
     );
-    let pair_rows = pairs_with(&mut db, &config.preferred_base_token)?;
-    let pairs = pair_rows
-        .iter()
-        .map(|row| Pair::from_pair_row(&row))
-        .collect::<Vec<Pair>>();
+    let query_start = Instant::now();
+    let pairs = dex::priced(&config.dexes, db.pairs_with(config.preferred_base_token)?);
+    metrics::PAIRS_QUERY_SECONDS
+        .with_label_values(&[&chain_id])
+        .observe(query_start.elapsed().as_secs_f64());
     let pairs_count = pairs.len();
     let pairs_preferred = pairs
         .into_iter()
-        .filter(|pair| {
-            pair.pool0.pool.coin1.contract_address == config.preferred_coin_token
-                && !config
-                    .exclude_addresses
-                    .contains(&pair.pool0.pool.contract_address)
-                && !config
-                    .exclude_addresses
-                    .contains(&pair.pool1.pool.contract_address)
-        })
+        .filter(|pair| tradable(config, pair))
         .collect::<Vec<Pair>>();
-    println!(
-        "{} pairs found. filtered to {}",
-        pairs_count,
-        pairs_preferred.len()
+    let pairs_preferred = match screener {
+        Some(screener) => screen::filter(config, db, screener, pairs_preferred)?,
+        None => pairs_preferred,
+    };
+    info!(
+        pairs = pairs_count,
+        preferred = pairs_preferred.len(),
+        "pairs found"
     );
-    let mut matches = simulate(pairs_preferred);
-    matches.sort_by(|a, b| b.scaled_profit().partial_cmp(&a.scaled_profit()).unwrap());
-
-    let gas_price_wei = get_gas_price(&provider);
-    println!(
-        "{} pools make {} pairs. {} matches for {} gas {:.1}",
-        pools_count,
-        pairs_count,
-        matches.len(),
-        config.preferred_base_token,
-        decimal::scale(gas_price_wei, 10_u128.pow(9))
+    metrics::PAIRS_CONSIDERED
+        .with_label_values(&[&chain_id])
+        .set(pairs_preferred.len() as i64);
+    // the cache is shared with other workers, scanning goes on without it
+    if let Some(cache) = cache.as_mut()
+        && let Err(err) = pairs_preferred
+            .iter()
+            .flat_map(|pair| [&pair.pool0, &pair.pool1])
+            .try_for_each(|snapshot| {
+                cache
+                    .publish_reserve(snapshot.pool.contract_address, &snapshot.reserve)
+                    .map(|_| ())
+            })
+    {
+        warn!(%err, "reserves not published");
+    }
+    let mut matches = simulate(pairs_preferred.clone());
+    if let Some(cache) = cache.as_mut()
+        && let Err(err) = matches
+            .iter()
+            .try_for_each(|r#match| cache.publish_match(r#match))
+    {
+        warn!(%err, "matches not published");
+    }
+    matches.sort_by_key(|r#match| Reverse(r#match.scaled_profit()));
+
+    metrics::MATCHES_FOUND
+        .with_label_values(&[&chain_id])
+        .set(matches.len() as i64);
+
+    let gas_price_wei = match chain.gas_price() {
+        Ok(gas_price_wei) => gas_price_wei,
+        Err(err) => {
+            warn!(%err, "no gas price, cycle skipped");
+            return Ok(());
+        }
+    };
+    info!(
+        pools = pools_count,
+        pairs = pairs_count,
+        matches = matches.len(),
+        base_token = %config.preferred_base_token,
+        gas_gwei = %Decimal::from_units(U256::from(gas_price_wei), 9).unwrap(),
+        "simulated"
     );
 
     let gas_cost_wei = gas_price_wei * config.tx_gas as u128;
     for r#match in matches.iter().take(10) {
-        println!("{}", r#match.to_string(gas_cost_wei));
+        info!(
+            pool0 = %r#match.pair.pool0.pool.contract_address,
+            pool1 = %r#match.pair.pool1.pool.contract_address,
+            "match {}",
+            r#match.to_string(gas_cost_wei)
+        );
+    }
+    if let Some(watcher) = watcher {
+        match mempool::back_runs(watcher, &config.dexes, &pairs_preferred) {
+            // not swabbed, the swab would land before the swap it needs
+            Ok(back_runs) => {
+                for (tx, r#match) in back_runs.iter() {
+                    info!(
+                        %tx,
+                        pool0 = %r#match.pair.pool0.pool.contract_address,
+                        pool1 = %r#match.pair.pool1.pool.contract_address,
+                        "back-run {}",
+                        r#match.to_string(gas_cost_wei)
+                    );
+                }
+            }
+            Err(err) => warn!(%err, "mempool not read"),
+        }
     }
+    metrics::BEST_GROSS_PROFIT
+        .with_label_values(&[&chain_id])
+        .set(matches.first().map_or(0.0, |m| m.scaled_profit().to_f64()));
+    metrics::BEST_NET_PROFIT
+        .with_label_values(&[&chain_id])
+        .set(
+            matches
+                .iter()
+                .map(|m| m.scaled_net_profit(gas_cost_wei))
+                .max()
+                .map_or(0.0, |profit| profit.to_f64().max(0.0)),
+        );
+    let opportunity_ids = matches
+        .iter()
+        .map(|r#match| {
+            db.insert_opportunity(
+                r#match,
+                config.tx_gas,
+                gas_cost_wei,
+                r#match.gas_cost_coin1(gas_cost_wei),
+            )
+        })
+        .collect::<Result<Vec<i64>, postgres::Error>>()?;
 
     let winners_profitable = matches
         .iter()
-        .filter(|mtch| approval(mtch, gas_cost_wei))
-        .collect::<Vec<&Match>>();
+        .zip(opportunity_ids)
+        .filter(|(mtch, _)| {
+            // tradable pairs only, so there are always limits
+            rule::limits(config, &mtch.pair.pool0.pool.coin1)
+                .is_some_and(|limits| approval(mtch, gas_cost_wei, limits.minimum_out))
+        })
+        .collect::<Vec<(&Match, i64)>>();
 
-    if winners_profitable.len() > 0 {
-        for winner in winners_profitable[0..1].into_iter() {
-            println!("===========================================================");
-            maineth(winner, &provider, gas_cost_wei, my_address).unwrap();
+    // the daily loss is that of the best winner's quote token, or of the one rebalanced
+    let quote = match winners_profitable.first() {
+        Some((winner, _)) => Some(winner.pair.pool0.pool.coin1.clone()),
+        None if rebalance::enabled(config) => pairs_preferred
+            .iter()
+            .map(|pair| &pair.pool0.pool.coin1)
+            .find(|coin| coin.contract_address == config.preferred_coin_token)
+            .cloned(),
+        None => None,
+    };
+    let trading = match &quote {
+        Some(quote) => risk::guard(config, db, chain, quote, my_address)?,
+        None => false,
+    };
+    if trading && !winners_profitable.is_empty() {
+        for (winner, opportunity_id) in winners_profitable.into_iter() {
+            // other quotes wait for a cycle where they are the best
+            if quote.as_ref().map(|coin| coin.contract_address)
+                != Some(winner.pair.pool0.pool.coin1.contract_address)
+            {
+                continue;
+            }
+            let lock = match cache.as_mut() {
+                Some(cache) => match cache.lock_pair(
+                    winner.pair.pool0.pool.contract_address,
+                    winner.pair.pool1.pool.contract_address,
+                    cache::PAIR_LOCK_SECONDS,
+                ) {
+                    Ok(Some(lock)) => Some(lock),
+                    Ok(None) => {
+                        info!(
+                            pool0 = %winner.pair.pool0.pool.contract_address,
+                            pool1 = %winner.pair.pool1.pool.contract_address,
+                            "pair locked by another worker"
+                        );
+                        continue;
+                    }
+                    // another worker may hold it
+                    Err(err) => {
+                        warn!(
+                            %err,
+                            pool0 = %winner.pair.pool0.pool.contract_address,
+                            pool1 = %winner.pair.pool1.pool.contract_address,
+                            "pair not locked, skipped"
+                        );
+                        continue;
+                    }
+                },
+                None => None,
+            };
+            let result = info_span!(
+                "swab",
+                pool0 = %winner.pair.pool0.pool.contract_address,
+                pool1 = %winner.pair.pool1.pool.contract_address,
+                ay_in = %winner.pool0_ay_in,
+            )
+            .in_scope(|| {
+                let max_amount = risk::max_amount(config, &winner.pair.pool0.pool.coin1);
+                maineth(winner, chain, gas_cost_wei, max_amount, my_address)
+            });
+            // recorded before anything else can go wrong, the swab has been sent
+            let recorded = match &result {
+                Ok(execution) => db.insert_execution(opportunity_id, execution).map(|_| ()),
+                Err(_) => Ok(()),
+            };
+            if let (Some(cache), Some(lock)) = (cache.as_mut(), lock)
+                && let Err(err) = cache.unlock_pair(lock)
+            {
+                warn!(%err, "pair not unlocked, the lock expires");
+            }
+            recorded?;
+            let execution = match result {
+                Ok(execution) => execution,
+                Err(err) => {
+                    warn!(%err, "swab skipped");
+                    break;
+                }
+            };
+            if !execution.status {
+                // the next cycle's risk::guard decides whether to go on
+                warn!(
+                    tx = execution.tx_hash,
+                    failure = execution.failure.map(|failure| failure.as_str()),
+                    "swab failed"
+                );
+            }
+            break;
         }
-    } else {
-        println!("no winners over {}", config.minimum_out);
+    } else if winners_profitable.is_empty() {
+        // pair rules give each quote token its own minimum, there is no one to log
+        info!(
+            minimum_out = config.pairs.is_empty().then_some(config.minimum_out),
+            "no winners"
+        );
+    }
+
+    if trading
+        && rebalance::enabled(config)
+        && let Err(err) = rebalance::run(config, chain, &pairs_preferred, my_address)
+    {
+        warn!(%err, "rebalance failed");
     }
 
     Ok(())
 }
 
-fn approval(m: &Match, gas_cost_wei: u128) -> bool {
-    let gas_cost_coin1 =
-        unipool::get_y_out(gas_cost_wei, m.pair.pool0.reserve.x, m.pair.pool0.reserve.y);
-    m.profit() > gas_cost_coin1
+// the profit covers gas and the pair's minimum_out, in raw coin1
+fn approval(m: &Match, gas_cost_wei: u128, minimum_out: U256) -> bool {
+    m.profit() > m.gas_cost_coin1(gas_cost_wei).saturating_add(minimum_out)
 }
 
 #[cfg(test)]
 #[test]
 fn test_approval() {
-    // this much setup means refactoring is needed
-    let coin0 = Coin {
-        contract_address: "COIN0".to_owned(),
-        symbol: "C0".to_owned(),
-        decimals: 18,
-    };
-    let coin1 = Coin {
-        contract_address: "COIN1".to_owned(),
-        symbol: "C0".to_owned(),
-        decimals: 6,
-    };
-    let m = Match {
-        pair: Pair {
-            pool0: PoolSnapshot {
-                pool: Pool {
-                    contract_address: "POOL-A0".to_owned(),
-                    coin0: coin0.clone(),
-                    coin1: coin1.clone(),
-                },
-                reserve: Reserve {
-                    contract_address: "POOL-A1".to_owned(),
-                    x: 37407681086137164,
-                    y: 135629089,
-                    block_number: 1,
-                    block_timestamp: 1,
-                },
-            },
-            pool1: PoolSnapshot {
-                pool: Pool {
-                    contract_address: "POOL-B0".to_owned(),
-                    coin0: coin0.clone(),
-                    coin1: coin1.clone(),
-                },
-                reserve: Reserve {
-                    contract_address: "POOL-B1".to_owned(),
-                    x: 276578510416029,
-                    y: 1320886,
-                    block_number: 1,
-                    block_timestamp: 1,
-                },
-            },
-        },
-        pool0_ay_in: 144457,
-        pool0_ax_out: 1,
-        pool1_ay_out: 165295, // profit 20838
-    };
+    let m = fixture::PairBuilder::new()
+        .coins(
+            fixture::coin(fixture::COIN0, "C0", 18),
+            fixture::coin(fixture::COIN1, "C1", 6),
+        )
+        .pool0(37407681086137164, 135629089)
+        .pool1(276578510416029, 1320886)
+        .matched(144457, 1, 165295); // profit 20838
     let gas_cost_wei = 1;
-    assert!(approval(&m, gas_cost_wei));
-}
-
-#[tokio::main]
-async fn get_gas_price<T: Provider>(provider: T) -> u128 {
-    provider.get_gas_price().await.unwrap()
-}
-
-sol!(
-    #[sol(rpc)]
-    UniswapV2Pair,
-    "sol-abi/UniswapV2Pair.json"
-);
-sol!(
-    #[sol(rpc)]
-    ERC20,
-    "sol-abi/ERC20.json"
-);
-sol!(
-    #[sol(rpc)]
-    UniSwab,
-    "ethereum/artifacts/UniSwab.abi"
-);
-
-#[tokio::main]
-async fn maineth<T: Provider>(
+    assert!(approval(&m, gas_cost_wei, U256::ZERO));
+    assert!(approval(&m, gas_cost_wei, U256::from(20000)));
+    assert!(!approval(&m, gas_cost_wei, U256::from(20838)));
+}
+
+// whether scan swabs pair: a quote token some pair rule takes, with decimals its limits
+// fit, and no excluded pool or coin
+fn tradable(config: &config::Config, pair: &Pair) -> bool {
+    let excluded = |address| config.exclude_addresses.contains(address);
+    rule::limits(config, &pair.pool0.pool.coin1).is_some()
+        && !excluded(&pair.pool0.pool.contract_address)
+        && !excluded(&pair.pool1.pool.contract_address)
+        && !excluded(&pair.pool0.pool.coin0.contract_address)
+        && !excluded(&pair.pool0.pool.coin1.contract_address)
+}
+
+// checks the winner against fresh reserves and swabs it. reads before the swab fail with
+// Err so the match is skipped. once send_swab is called every outcome is an Execution, a
+// failed send or an unread balance included, so no trade goes unrecorded.
+fn maineth(
     winner: &Match,
-    provider: T,
+    chain: &impl Chain,
     gas_cost_wei: u128,
+    max_amount: U256,
     public_key: Address,
-) -> Result<(), String> {
-    let config = config::CONFIG.get().unwrap();
-    let uniswab = UniSwab::new(config.uniswab.parse().unwrap(), &provider);
-    let coin0 = ERC20::new(
-        winner
-            .pair
-            .pool0
-            .pool
-            .coin0
-            .contract_address
-            .parse()
-            .unwrap(),
-        &provider,
-    );
-    let coin1 = ERC20::new(
-        winner
-            .pair
-            .pool0
-            .pool
-            .coin1
-            .contract_address
-            .parse()
-            .unwrap(),
-        &provider,
-    );
+) -> Result<Execution, String> {
+    let coin0 = winner.pair.pool0.pool.coin0.contract_address;
+    let coin1 = winner.pair.pool0.pool.coin1.contract_address;
 
-    let eth_balance_start = provider.get_balance(public_key).await.unwrap();
-    println!(
-        "{} eth: {}",
-        public_key,
-        format_units(eth_balance_start, 18).unwrap()
+    let eth_balance_start = chain.eth_balance(public_key)?;
+    metrics::WALLET_BALANCE
+        .with_label_values(&[&metrics::chain_id(), "ETH"])
+        .set(Into::<f64>::into(eth_balance_start) / 10_f64.powi(18));
+    info!(
+        %public_key,
+        eth = %format_units(eth_balance_start, 18).unwrap(),
+        "balance"
     );
-    // erc20_allow(&public_key, uniswab.address(), &coin0).await;
-    let coin0_balance_start = coin0.balanceOf(public_key).call().await.unwrap();
-    println!(
-        "{} {}: {}",
-        public_key,
-        winner.pair.pool0.pool.coin0.symbol,
-        Into::<f64>::into(coin0_balance_start) / 10_f64.powi(winner.pair.pool0.pool.coin0.decimals),
+    let coin0_balance_start = chain.token_balance(coin0, public_key)?;
+    metrics::WALLET_BALANCE
+        .with_label_values(&[&metrics::chain_id(), &winner.pair.pool0.pool.coin0.symbol])
+        .set(
+            Into::<f64>::into(coin0_balance_start)
+                / 10_f64.powi(winner.pair.pool0.pool.coin0.decimals),
+        );
+    info!(
+        %public_key,
+        token = %winner.pair.pool0.pool.coin0.symbol,
+        amount = Into::<f64>::into(coin0_balance_start) / 10_f64.powi(winner.pair.pool0.pool.coin0.decimals),
+        "balance"
     );
-    // erc20_allow(&public_key, uniswab.address(), &coin1).await;
-    let coin1_balance_start = coin1.balanceOf(public_key).call().await.unwrap();
-    println!(
-        "{} {}: {}",
-        public_key,
-        winner.pair.pool0.pool.coin1.symbol,
-        Into::<f64>::into(coin1_balance_start) / 10_f64.powi(winner.pair.pool0.pool.coin1.decimals),
+    let coin1_balance_start = chain.token_balance(coin1, public_key)?;
+    metrics::WALLET_BALANCE
+        .with_label_values(&[&metrics::chain_id(), &winner.pair.pool0.pool.coin1.symbol])
+        .set(
+            Into::<f64>::into(coin1_balance_start)
+                / 10_f64.powi(winner.pair.pool0.pool.coin1.decimals),
+        );
+    info!(
+        %public_key,
+        token = %winner.pair.pool0.pool.coin1.symbol,
+        amount = Into::<f64>::into(coin1_balance_start) / 10_f64.powi(winner.pair.pool0.pool.coin1.decimals),
+        "balance"
     );
 
-    println!("winner: {}", winner.to_string(gas_cost_wei));
-    println!(
-        "winner p0: {} r0: {} r1: {} block: {} {}",
-        winner.pair.pool0.pool.contract_address,
-        winner.pair.pool0.reserve.x,
-        winner.pair.pool0.reserve.y,
-        winner.pair.pool0.reserve.block_number,
-        winner.pair.pool0.reserve.block_time_str(),
-    );
-    println!(
-        "winner p1: {} r0: {} r1: {} block: {} {}",
-        winner.pair.pool1.pool.contract_address,
-        winner.pair.pool1.reserve.x,
-        winner.pair.pool1.reserve.y,
-        winner.pair.pool1.reserve.block_number,
-        winner.pair.pool1.reserve.block_time_str(),
-    );
+    // uniswab pulls coin1 with transferFrom, a swab over the allowance would revert
+    let coin1_allowance = chain.allowance(coin1, public_key)?;
+    if coin1_allowance.is_zero() {
+        return Err(format!(
+            "uniswab has no {} allowance, see gofi approve",
+            winner.pair.pool0.pool.coin1.symbol
+        ));
+    }
 
-    // let uniswab = UniSwab::new(config.uniswab.parse().unwrap(), &provider);
-    let pool0 = UniswapV2Pair::new(
-        winner.pair.pool0.pool.contract_address.parse().unwrap(),
-        &provider,
-    );
-    let pool1 = UniswapV2Pair::new(
-        winner.pair.pool1.pool.contract_address.parse().unwrap(),
-        &provider,
+    info!("winner {}", winner.to_string(gas_cost_wei));
+    for snapshot in [&winner.pair.pool0, &winner.pair.pool1] {
+        info!(
+            pool = %snapshot.pool.contract_address,
+            r0 = %snapshot.reserve.x,
+            r1 = %snapshot.reserve.y,
+            block = snapshot.reserve.block_number,
+            block_time = %snapshot.reserve.block_time_str(),
+            "winner reserves"
+        );
+    }
+
+    let pool0 = winner.pair.pool0.pool.contract_address;
+    let pool1 = winner.pair.pool1.pool.contract_address;
+
+    let fresh_pair = fresh_reserves(winner, chain)?;
+    let fresh_match = trade_simulate(fresh_pair)?;
+    info!(profit = %fresh_match.scaled_profit(), "fresh profit");
+
+    if winner.pair.pool0.reserve.x == fresh_match.pair.pool0.reserve.x
+        && winner.pair.pool0.reserve.y == fresh_match.pair.pool0.reserve.y
+        && winner.pair.pool1.reserve.x == fresh_match.pair.pool1.reserve.x
+        && winner.pair.pool1.reserve.y == fresh_match.pair.pool1.reserve.y
+    {
+        let swab_amt = cmp::min(coin1_balance_start, winner.pool0_ay_in)
+            .min(coin1_allowance)
+            .min(max_amount);
+        info!(
+            amount = %swab_amt,
+            ay_in = %winner.pool0_ay_in,
+            balance = %coin1_balance_start,
+            allowance = %coin1_allowance,
+            max_amount = %max_amount,
+            "SWAB"
+        );
+        // a stuck swab is worth speeding up while the pools still have the arb
+        let still_wanted = || {
+            fresh_reserves(winner, chain)
+                .and_then(trade_simulate)
+                .is_ok_and(|r#match| r#match.profit() > U256::ZERO)
+        };
+        let swab_receipt = match chain.send_swab(
+            swab_amt,
+            pool0,
+            pool1,
+            (
+                winner.pair.pool0.pool.fee_bps,
+                winner.pair.pool1.pool.fee_bps,
+            ),
+            winner.pair.pool0.pool.flipped,
+            &still_wanted,
+        ) {
+            Ok(receipt) => receipt,
+            Err(err) => {
+                warn!(%err, "swab send failed");
+                metrics::SWAB_FAILURES
+                    .with_label_values(&[
+                        &metrics::chain_id(),
+                        revert::Failure::SendFailed.as_str(),
+                    ])
+                    .inc();
+                return Ok(Execution {
+                    // empty when nothing went out
+                    tx_hash: err.tx_hash.unwrap_or_default(),
+                    status: false,
+                    block_number: None,
+                    gas_used: 0,
+                    effective_gas_price: 0,
+                    eth_delta: I256::ZERO,
+                    coin0_delta: I256::ZERO,
+                    coin1_delta: I256::ZERO,
+                    revert_reason: Some(err.message),
+                    failure: Some(revert::Failure::SendFailed),
+                });
+            }
+        };
+        info!(
+            tx = %swab_receipt.tx_hash,
+            status = swab_receipt.status,
+            gas_used = swab_receipt.gas_used,
+            replacements = swab_receipt.replacements,
+            cancelled = swab_receipt.cancelled,
+            "swab receipt"
+        );
+        let status = if swab_receipt.cancelled {
+            "cancel_mined"
+        } else if swab_receipt.status {
+            "succeeded"
+        } else {
+            "reverted"
+        };
+        metrics::TRANSACTIONS
+            .with_label_values(&[&metrics::chain_id(), status])
+            .inc();
+        let (revert_reason, failure) = diagnose(chain, &swab_receipt, &fresh_match.pair);
+
+        // an unread balance keeps its start one, recording a 0 delta
+        let balance_end = |balance: Result<U256, String>, start: U256, token: &str| {
+            balance.unwrap_or_else(|err| {
+                warn!(%err, token, "balance after the swab unread");
+                start
+            })
+        };
+        let eth_balance_end = balance_end(chain.eth_balance(public_key), eth_balance_start, "ETH");
+        metrics::WALLET_BALANCE
+            .with_label_values(&[&metrics::chain_id(), "ETH"])
+            .set(Into::<f64>::into(eth_balance_end) / 10_f64.powi(18));
+        let eth_delta = balance_delta(eth_balance_start, eth_balance_end);
+        info!(
+            %public_key,
+            eth = %format_units(eth_balance_end, 18).unwrap(),
+            delta = %format_units(eth_delta, 18).unwrap(),
+            "balance"
+        );
+        let coin0_balance_end = balance_end(
+            chain.token_balance(coin0, public_key),
+            coin0_balance_start,
+            &winner.pair.pool0.pool.coin0.symbol,
+        );
+        metrics::WALLET_BALANCE
+            .with_label_values(&[&metrics::chain_id(), &winner.pair.pool0.pool.coin0.symbol])
+            .set(
+                Into::<f64>::into(coin0_balance_end)
+                    / 10_f64.powi(winner.pair.pool0.pool.coin0.decimals),
+            );
+        let coin0_delta = balance_delta(coin0_balance_start, coin0_balance_end);
+        info!(
+            %public_key,
+            token = %winner.pair.pool0.pool.coin0.symbol,
+            amount = %format_units(coin0_balance_end, winner.pair.pool0.pool.coin0.decimals as u8).unwrap(),
+            delta = %format_units(coin0_delta, winner.pair.pool0.pool.coin0.decimals as u8).unwrap(),
+            "balance"
+        );
+        let coin1_balance_end = balance_end(
+            chain.token_balance(coin1, public_key),
+            coin1_balance_start,
+            &winner.pair.pool0.pool.coin1.symbol,
+        );
+        metrics::WALLET_BALANCE
+            .with_label_values(&[&metrics::chain_id(), &winner.pair.pool0.pool.coin1.symbol])
+            .set(
+                Into::<f64>::into(coin1_balance_end)
+                    / 10_f64.powi(winner.pair.pool0.pool.coin1.decimals),
+            );
+        let coin1_delta = balance_delta(coin1_balance_start, coin1_balance_end);
+        info!(
+            %public_key,
+            token = %winner.pair.pool0.pool.coin1.symbol,
+            amount = %format_units(coin1_balance_end, winner.pair.pool0.pool.coin1.decimals as u8).unwrap(),
+            delta = %format_units(coin1_delta, winner.pair.pool0.pool.coin1.decimals as u8).unwrap(),
+            "balance"
+        );
+        Ok(Execution {
+            tx_hash: swab_receipt.tx_hash,
+            // a mined cancel is no swab
+            status: swab_receipt.status && !swab_receipt.cancelled,
+            block_number: swab_receipt.block_number,
+            gas_used: swab_receipt.gas_used,
+            effective_gas_price: swab_receipt.effective_gas_price,
+            eth_delta,
+            coin0_delta,
+            coin1_delta,
+            revert_reason,
+            failure,
+        })
+    } else {
+        metrics::FRESHNESS_ABORTS
+            .with_label_values(&[&metrics::chain_id()])
+            .inc();
+        Err("swap aborted. freshness check failed".to_owned())
+    }
+}
+
+// the revert reason and failure class of a swab that did not go through. sized_on has
+// the reserves the swab amount was computed from.
+fn diagnose(
+    chain: &impl Chain,
+    receipt: &chain::Receipt,
+    sized_on: &Pair,
+) -> (Option<String>, Option<revert::Failure>) {
+    if receipt.status && !receipt.cancelled {
+        return (None, None);
+    }
+    let (reason, failure) = if receipt.cancelled {
+        (None, revert::Failure::Cancelled)
+    } else {
+        let replay = chain.replay(receipt).unwrap_or_else(revert::Replay::Error);
+        // pools that changed before the swab's block were stale when we read them
+        let reserves_moved = receipt.block_number.is_some_and(|block| {
+            [&sized_on.pool0, &sized_on.pool1].iter().any(|snapshot| {
+                chain
+                    .reserves_at(snapshot.pool.contract_address, block - 1)
+                    .is_ok_and(|(x, y, _)| (x, y) != (snapshot.reserve.x, snapshot.reserve.y))
+            })
+        });
+        let reason = match &replay {
+            revert::Replay::Reverted(data) => Some(revert::decode(data)),
+            revert::Replay::Error(message) => Some(message.clone()),
+            revert::Replay::OutOfGas | revert::Replay::Succeeded => None,
+        };
+        (reason, revert::classify(&replay, reserves_moved))
+    };
+    warn!(
+        tx = %receipt.tx_hash,
+        reason = reason.as_deref().unwrap_or(""),
+        failure = failure.as_str(),
+        "swab failed"
     );
+    metrics::SWAB_FAILURES
+        .with_label_values(&[&metrics::chain_id(), failure.as_str()])
+        .inc();
+    (reason, Some(failure))
+}
 
-    let (r00, r01, btime0) = pool0.getReserves().call().await.unwrap().into();
+// the winner's pools with the reserves they have now
+fn fresh_reserves(winner: &Match, chain: &impl Chain) -> Result<Pair, String> {
+    let pool0 = winner.pair.pool0.pool.contract_address;
+    let pool1 = winner.pair.pool1.pool.contract_address;
+    let (r00, r01, btime0) = chain.reserves(pool0)?;
+    let (x0, y0) = winner.pair.pool0.pool.ordered(r00, r01);
     let btime0_str = DateTime::from_timestamp(btime0 as i64, 0).unwrap();
-    println!(
-        "fresh p0: {} r0: {} r1: {} btime: {} {}",
-        winner.pair.pool0.pool.contract_address, r00, r01, btime0, btime0_str
+    info!(
+        pool = %pool0,
+        r0 = %r00,
+        r1 = %r01,
+        btime = btime0,
+        block_time = %btime0_str,
+        "fresh reserves"
     );
-    let (r10, r11, btime1) = pool1.getReserves().call().await.unwrap().into();
+    let (r10, r11, btime1) = chain.reserves(pool1)?;
+    let (x1, y1) = winner.pair.pool1.pool.ordered(r10, r11);
     let btime1_str = DateTime::from_timestamp(btime1 as i64, 0).unwrap();
-    println!(
-        "fresh p1: {} r0: {} r1: {} btime: {} {}",
-        winner.pair.pool1.pool.contract_address, r10, r11, btime1, btime1_str
+    info!(
+        pool = %pool1,
+        r0 = %r10,
+        r1 = %r11,
+        btime = btime1,
+        block_time = %btime1_str,
+        "fresh reserves"
     );
-    let fresh_pair = Pair {
+    Ok(Pair {
         pool0: PoolSnapshot {
             pool: winner.pair.pool0.pool.clone(),
             reserve: Reserve {
-                contract_address: "0x00".to_owned(),
-                x: r00.to(),
-                y: r01.to(),
+                contract_address: pool0,
+                x: x0,
+                y: y0,
                 block_number: 0,
                 block_timestamp: btime0,
             },
@@ -299,162 +876,391 @@ async fn maineth<T: Provider>(
         pool1: PoolSnapshot {
             pool: winner.pair.pool1.pool.clone(),
             reserve: Reserve {
-                contract_address: "0x01".to_owned(),
-                x: r10.to(),
-                y: r11.to(),
+                contract_address: pool1,
+                x: x1,
+                y: y1,
                 block_number: 1,
                 block_timestamp: btime1,
             },
         },
-    };
-    let fresh_match = trade_simulate(fresh_pair)?;
-    println!("fresh profit: {}", fresh_match.scaled_profit());
+    })
+}
 
-    if winner.pair.pool0.reserve.x == fresh_match.pair.pool0.reserve.x
-        && winner.pair.pool0.reserve.y == fresh_match.pair.pool0.reserve.y
-        && winner.pair.pool1.reserve.x == fresh_match.pair.pool1.reserve.x
-        && winner.pair.pool1.reserve.y == fresh_match.pair.pool1.reserve.y
-    {
-        let swab_amt = cmp::min(
-            coin1_balance_start.saturating_to::<u128>(),
-            winner.pool0_ay_in,
-        );
-        println!(
-            "SWAB {} ({}/{}), {}, {}",
-            swab_amt,
-            winner.pool0_ay_in,
-            coin1_balance_start,
-            &winner.pair.pool0.pool.contract_address,
-            &winner.pair.pool1.pool.contract_address,
-        );
-        let swab_tx = uniswab.swab(
-            U256::from(swab_amt),
-            Address::from_slice(&decode(&winner.pair.pool0.pool.contract_address).unwrap()),
-            Address::from_slice(&decode(&winner.pair.pool1.pool.contract_address).unwrap()),
-        );
-        let swab_tx_receipt = swab_tx
-            .gas(config.tx_gas)
-            .send()
-            .await
-            .unwrap()
-            .get_receipt()
-            .await
-            .unwrap();
-        println!("swab tx {}", swab_tx_receipt.transaction_hash);
-
-        let eth_balance_end = provider.get_balance(public_key).await.unwrap();
-        println!(
-            "{} eth: {} delta {}",
-            public_key,
-            format_units(eth_balance_end, 18).unwrap(),
-            format_units(eth_balance_start - eth_balance_end, 18).unwrap()
+#[cfg(test)]
+mod swab_tests {
+    use super::*;
+    use chain::{FakeChain, Receipt};
+    use fixture::{COIN0, COIN1, POOL0, POOL1, PairBuilder};
+    use repository::FakeRepository;
+
+    const ME: Address = Address::repeat_byte(0xee);
+
+    fn pair() -> Pair {
+        PairBuilder::new().build()
+    }
+
+    // a chain where both pools still hold the reserves in pair() and ME has coin1_balance.
+    // a swab returns a successful receipt and adds profit to the coin1 balance.
+    fn fake_chain(coin1_balance: u64, profit: u64) -> FakeChain {
+        let mut chain = FakeChain::default();
+        for snapshot in [pair().pool0, pair().pool1] {
+            let reserve = snapshot.reserve;
+            chain.reserves.insert(
+                snapshot.pool.contract_address,
+                (reserve.x, reserve.y, reserve.block_timestamp),
+            );
+        }
+        let coin1 = COIN1;
+        chain.set_balance(coin1, ME, U256::from(coin1_balance));
+        chain
+            .balances_after
+            .insert((coin1, ME), U256::from(coin1_balance + profit));
+        chain.receipt = Some(Receipt {
+            tx_hash: "0x01".to_owned(),
+            status: true,
+            block_number: Some(2),
+            gas_used: 100000,
+            effective_gas_price: 0,
+            cancelled: false,
+            replacements: 0,
+        });
+        chain
+    }
+
+    fn sent(chain: &FakeChain) -> Vec<(U256, Address, Address, bool)> {
+        chain.sent.borrow().clone()
+    }
+
+    #[test]
+    fn test_maineth_swabs_fresh_match() {
+        let winner = trade_simulate(pair()).unwrap();
+        let chain = fake_chain(1000000, 18608);
+        let execution = maineth(&winner, &chain, 0, U256::MAX, ME).unwrap();
+        assert_eq!(sent(&chain), vec![(U256::from(40371), POOL0, POOL1, false)]);
+        assert!(execution.status);
+        assert_eq!(execution.coin1_delta, I256::try_from(18608).unwrap());
+        assert_eq!(execution.coin0_delta, I256::ZERO);
+        assert_eq!((execution.revert_reason, execution.failure), (None, None));
+        // the pools still have the arb, a stuck swab would be sped up
+        assert_eq!(*chain.wanted.borrow(), [true]);
+    }
+
+    #[test]
+    fn test_maineth_swabs_flipped_pools() {
+        // coin0 is token1 of both pools, the chain reports reserves in token order
+        let winner = trade_simulate(PairBuilder::new().flipped().build()).unwrap();
+        let mut chain = fake_chain(1000000, 18608);
+        for (x, y, _) in chain.reserves.values_mut() {
+            std::mem::swap(x, y);
+        }
+        let execution = maineth(&winner, &chain, 0, U256::MAX, ME).unwrap();
+        assert_eq!(sent(&chain), vec![(U256::from(40371), POOL0, POOL1, true)]);
+        assert!(execution.status);
+    }
+
+    #[test]
+    fn test_maineth_cancelled() {
+        let winner = trade_simulate(pair()).unwrap();
+        let mut chain = fake_chain(1000000, 0);
+        chain.receipt.as_mut().unwrap().cancelled = true;
+        let execution = maineth(&winner, &chain, 0, U256::MAX, ME).unwrap();
+        assert!(!execution.status);
+        assert_eq!(execution.coin1_delta, I256::ZERO);
+        assert_eq!(execution.failure, Some(revert::Failure::Cancelled));
+    }
+
+    #[test]
+    fn test_maineth_reverted() {
+        let winner = trade_simulate(pair()).unwrap();
+        let mut chain = fake_chain(1000000, 0);
+        chain.receipt.as_mut().unwrap().status = false;
+        let revert = alloy::sol_types::Revert::from("UniSwab: no profit");
+        chain.replay = Some(revert::Replay::Reverted(
+            alloy::sol_types::SolError::abi_encode(&revert).into(),
+        ));
+        let execution = maineth(&winner, &chain, 0, U256::MAX, ME).unwrap();
+        assert!(!execution.status);
+        assert_eq!(
+            execution.revert_reason.as_deref(),
+            Some("revert: UniSwab: no profit")
         );
-        let coin0_balance_end = coin0.balanceOf(public_key).call().await.unwrap();
-        println!(
-            "{} {}: {} delta {}",
-            public_key,
-            winner.pair.pool0.pool.coin0.symbol,
-            format_units(
-                coin0_balance_end,
-                winner.pair.pool0.pool.coin0.decimals.to_string()
-            )
-            .unwrap(),
-            format_units(
-                coin0_balance_start - coin0_balance_end,
-                winner.pair.pool0.pool.coin0.decimals.to_string()
-            )
-            .unwrap()
+        // the block before had the reserves the swab was sized on
+        assert_eq!(execution.failure, Some(revert::Failure::FrontRun));
+        chain
+            .reserves_before
+            .insert(POOL1, (U256::from(230000), U256::from(310000), 1));
+        let execution = maineth(&winner, &chain, 0, U256::MAX, ME).unwrap();
+        assert_eq!(execution.failure, Some(revert::Failure::StaleReserves));
+        // no revert data to be had
+        chain.replay = None;
+        let execution = maineth(&winner, &chain, 0, U256::MAX, ME).unwrap();
+        assert_eq!(execution.revert_reason.as_deref(), Some("no replay"));
+        assert_eq!(execution.failure, Some(revert::Failure::Unknown));
+    }
+
+    #[test]
+    fn test_maineth_send_failed() {
+        let winner = trade_simulate(pair()).unwrap();
+        let mut chain = fake_chain(1000000, 18608);
+        chain.receipt = None;
+        let execution = maineth(&winner, &chain, 0, U256::MAX, ME).unwrap();
+        assert_eq!(sent(&chain).len(), 1);
+        assert!(!execution.status);
+        assert_eq!(execution.coin1_delta, I256::ZERO);
+        assert_eq!(execution.revert_reason.as_deref(), Some("send failed"));
+        assert_eq!(execution.failure, Some(revert::Failure::SendFailed));
+        assert_eq!(execution.tx_hash, "");
+        // out but not seen mined, kept so the trade can be found
+        chain.sent_hash = Some("0x02".to_owned());
+        let execution = maineth(&winner, &chain, 0, U256::MAX, ME).unwrap();
+        assert_eq!(execution.tx_hash, "0x02");
+        assert_eq!(execution.failure, Some(revert::Failure::SendFailed));
+    }
+
+    #[test]
+    fn test_maineth_sizes_to_balance() {
+        let winner = trade_simulate(pair()).unwrap();
+        let chain = fake_chain(10000, 1);
+        maineth(&winner, &chain, 0, U256::MAX, ME).unwrap();
+        assert_eq!(sent(&chain)[0].0, U256::from(10000));
+    }
+
+    #[test]
+    fn test_maineth_allowance() {
+        let winner = trade_simulate(pair()).unwrap();
+        let chain = fake_chain(1000000, 1);
+        chain
+            .allowances
+            .borrow_mut()
+            .insert(COIN1, U256::from(20000));
+        maineth(&winner, &chain, 0, U256::MAX, ME).unwrap();
+        assert_eq!(sent(&chain)[0].0, U256::from(20000));
+        chain.allowances.borrow_mut().insert(COIN1, U256::ZERO);
+        let err = maineth(&winner, &chain, 0, U256::MAX, ME).err().unwrap();
+        assert!(err.contains("no USDONC allowance"), "{}", err);
+        assert_eq!(sent(&chain).len(), 1);
+    }
+
+    #[test]
+    fn test_maineth_freshness() {
+        let winner = trade_simulate(pair()).unwrap();
+        let mut chain = fake_chain(1000000, 18608);
+        // someone traded pool1 since the reserves were indexed
+        chain
+            .reserves
+            .insert(POOL1, (U256::from(230000), U256::from(306100), 2));
+        let err = maineth(&winner, &chain, 0, U256::MAX, ME).err().unwrap();
+        assert!(err.contains("freshness"), "{}", err);
+        assert!(sent(&chain).is_empty());
+
+        // an arb that closed entirely fails the fresh simulation
+        chain
+            .reserves
+            .insert(POOL1, (U256::from(310000), U256::from(210000), 2));
+        assert!(maineth(&winner, &chain, 0, U256::MAX, ME).is_err());
+        assert!(sent(&chain).is_empty());
+
+        // and an rpc failure skips the match
+        chain.reserves.clear();
+        assert!(maineth(&winner, &chain, 0, U256::MAX, ME).is_err());
+        assert!(sent(&chain).is_empty());
+    }
+
+    #[test]
+    fn test_scan_cycle() {
+        let config: config::Config = serde_yaml::from_str(&format!(
+            "geth_url: http://localhost:8545
+pg_url: postgres://localhost/gofi
+eth_priv_key: 0000000000000000000000000000000000000000000000000000000000000001
+uniswab: 000000000000000000000000000000000000005b
+preferred_base_token: {COIN0}
+preferred_coin_token: {COIN1}
+minimum_out: 0
+tx_gas: 300000
+exclude_addresses: []"
+        ))
+        .unwrap();
+        let mut db = FakeRepository {
+            pairs: vec![pair(), PairBuilder::new().reversed().build()],
+            ..FakeRepository::default()
+        };
+        let chain = fake_chain(1000000, 18608);
+        scan_cycle(&config, &mut db, &chain, None, None, &mut None, ME).unwrap();
+        // only the cheaper pool first is an arb
+        assert_eq!(
+            db.opportunities,
+            vec![(U256::from(40371), U256::from(18608))]
         );
-        let coin1_balance_end = coin1.balanceOf(public_key).call().await.unwrap();
-        println!(
-            "{} {}: {} delta {}",
-            public_key,
-            winner.pair.pool0.pool.coin1.symbol,
-            format_units(
-                coin1_balance_end,
-                winner.pair.pool0.pool.coin1.decimals.to_string()
-            )
-            .unwrap(),
-            format_units(
-                coin1_balance_start - coin1_balance_end,
-                winner.pair.pool0.pool.coin1.decimals.to_string()
-            )
-            .unwrap()
+        assert_eq!(db.executions.len(), 1);
+        assert_eq!(db.executions[0].0, 0);
+        assert_eq!(
+            db.executions[0].1.coin1_delta,
+            I256::try_from(18608).unwrap()
         );
-        match swab_tx_receipt.status() {
-            true => Ok(()),
-            false => Err("eth tx failed".to_owned()),
-        }
-    } else {
-        Err("swap aborted. freshness check failed".to_owned())
+
+        // gas worth more than the profit is not approved
+        let mut db = FakeRepository {
+            pairs: vec![pair()],
+            ..FakeRepository::default()
+        };
+        let mut chain = fake_chain(1000000, 18608);
+        chain.gas_price = 1;
+        scan_cycle(&config, &mut db, &chain, None, None, &mut None, ME).unwrap();
+        assert_eq!(db.opportunities.len(), 1);
+        assert!(db.executions.is_empty());
+        assert!(sent(&chain).is_empty());
+        // no gas price skips the cycle
+        let mut db = FakeRepository {
+            pairs: vec![pair()],
+            ..FakeRepository::default()
+        };
+        let mut chain = fake_chain(1000000, 18608);
+        chain.gas_price_error = Some("node down".to_owned());
+        scan_cycle(&config, &mut db, &chain, None, None, &mut None, ME).unwrap();
+        assert!(db.opportunities.is_empty());
+        assert!(sent(&chain).is_empty());
+        // a failed swab halts the next cycle, until it is resumed
+        let mut db = FakeRepository {
+            pairs: vec![pair()],
+            ..FakeRepository::default()
+        };
+        let mut chain = fake_chain(1000000, 18608);
+        chain.receipt.as_mut().unwrap().status = false;
+        scan_cycle(&config, &mut db, &chain, None, None, &mut None, ME).unwrap();
+        assert_eq!(db.executions.len(), 1);
+        scan_cycle(&config, &mut db, &chain, None, None, &mut None, ME).unwrap();
+        assert_eq!(sent(&chain).len(), 1);
+        assert_eq!(db.halts.len(), 1);
+        risk::resume(&config, &mut db).unwrap();
+        scan_cycle(&config, &mut db, &chain, None, None, &mut None, ME).unwrap();
+        assert_eq!(sent(&chain).len(), 2);
+        assert_eq!(db.halts.len(), 1);
     }
-}
 
-async fn erc20_allow<T: Provider>(
-    owner_address: &Address,
-    spender_address: &Address,
-    coin: &ERC20::ERC20Instance<T>,
-) {
-    let allowance = coin
-        .allowance(*owner_address, *spender_address)
-        .call()
-        .await
-        .unwrap();
-    if allowance == U256::from(0) {
-        let tx = coin
-            .approve(*spender_address, U256::MAX)
-            .send()
-            .await
+    #[test]
+    fn test_scan_cycle_pairs() {
+        let other = fixture::coin(Address::with_last_byte(0xc2), "USDOND", 18);
+        let config_with = |pairs: &str| -> config::Config {
+            serde_yaml::from_str(&format!(
+                "geth_url: http://localhost:8545
+pg_url: postgres://localhost/gofi
+eth_priv_key: 0000000000000000000000000000000000000000000000000000000000000001
+uniswab: 000000000000000000000000000000000000005b
+preferred_base_token: {COIN0}
+preferred_coin_token: {COIN1}
+minimum_out: 0
+tx_gas: 300000
+pairs:
+{pairs}"
+            ))
             .unwrap()
-            .get_receipt()
-            .await
-            .unwrap();
-        println!("erc20 allownace tx: {}", hex::encode(tx.transaction_hash));
+        };
+        let pairs = || {
+            let coin0 = fixture::coin(COIN0, "USDONA", 18);
+            vec![
+                pair(),
+                PairBuilder::new().coins(coin0, other.clone()).build(),
+            ]
+        };
+
+        // a quote's minimum_out above the profit, other has no rule
+        let config = config_with(&format!(
+            "- quote: {COIN1}\n  minimum_out: 0.00000000000002"
+        ));
+        let mut db = FakeRepository {
+            pairs: pairs(),
+            ..FakeRepository::default()
+        };
+        let chain = fake_chain(1000000, 18608);
+        scan_cycle(&config, &mut db, &chain, None, None, &mut None, ME).unwrap();
+        assert_eq!(db.opportunities.len(), 1);
+        assert!(db.executions.is_empty());
+        assert!(sent(&chain).is_empty());
+
+        // any takes both, one quote is swabbed a cycle
+        let config = config_with("- quote: any");
+        let mut db = FakeRepository {
+            pairs: pairs(),
+            ..FakeRepository::default()
+        };
+        let chain = fake_chain(1000000, 18608);
+        scan_cycle(&config, &mut db, &chain, None, None, &mut None, ME).unwrap();
+        assert_eq!(db.opportunities.len(), 2);
+        assert_eq!(db.executions.len(), 1);
+
+        // a token claiming more decimals than parse_units takes is not traded
+        let hostile = fixture::coin(other.contract_address, "HOSTILE", 100);
+        let mut db = FakeRepository {
+            pairs: vec![
+                pair(),
+                PairBuilder::new()
+                    .coins(fixture::coin(COIN0, "USDONA", 18), hostile)
+                    .build(),
+            ],
+            ..FakeRepository::default()
+        };
+        let chain = fake_chain(1000000, 18608);
+        scan_cycle(&config, &mut db, &chain, None, None, &mut None, ME).unwrap();
+        assert_eq!(db.opportunities.len(), 1);
     }
 }
 
 #[derive(Clone)]
 struct Pool {
-    contract_address: String,
+    contract_address: Address,
     coin0: Coin,
     coin1: Coin,
+    // what its dex charges on the amount in, see dex::priced
+    fee_bps: u8,
+    // coin0, the base token, is the pool's token1. coin0/coin1 and its reserve's x/y are
+    // then token1/token0 and reserve1/reserve0.
+    flipped: bool,
 }
 
 impl Pool {
     pub fn from_pair_row(row: &postgres::Row, pool_digit: &str) -> Pool {
         let pool_contract_address_0: &str = row.get(sql_field!("p{}_contract_address", pool_digit));
         Pool {
-            contract_address: pool_contract_address_0.to_owned(),
+            contract_address: address::from_db(pool_contract_address_0),
             coin0: Coin::from_pair_row(row, pool_digit, "0"),
             coin1: Coin::from_pair_row(row, pool_digit, "1"),
+            fee_bps: unipool::POOL_FEE_BASIS_POINTS,
+            flipped: row.get(sql_field!("p{}_flipped", pool_digit)),
         }
     }
+
+    // the pool's (token0, token1) amounts as (coin0, coin1), and back
+    pub fn ordered(&self, a: U256, b: U256) -> (U256, U256) {
+        if self.flipped { (b, a) } else { (a, b) }
+    }
 }
 
 #[derive(Clone)]
 struct Coin {
-    contract_address: String,
+    contract_address: Address,
     symbol: String,
     decimals: i32,
 }
 
 impl Coin {
-    pub fn from_pair_row(row: &postgres::Row, pool_digit: &str, token_digit: &str) -> Coin {
-        let contract_address = row.get(format!("p{}_token{}", pool_digit, token_digit).as_str());
-        let symbol = row.get(format!("p{}_token{}_symbol", pool_digit, token_digit).as_str());
-        let decimals = row.get(format!("p{}_token{}_decimals", pool_digit, token_digit).as_str());
+    pub fn from_pair_row(row: &postgres::Row, pool_digit: &str, coin_digit: &str) -> Coin {
+        let contract_address: &str =
+            row.get(format!("p{}_coin{}", pool_digit, coin_digit).as_str());
+        let symbol = row.get(format!("p{}_coin{}_symbol", pool_digit, coin_digit).as_str());
+        let decimals = row.get(format!("p{}_coin{}_decimals", pool_digit, coin_digit).as_str());
         Coin {
-            contract_address,
+            contract_address: address::from_db(contract_address),
             symbol,
             decimals,
         }
     }
 }
 
+#[derive(Clone)]
 struct Reserve {
-    contract_address: String,
-    x: u128,
-    y: u128,
+    contract_address: Address,
+    x: U256,
+    y: U256,
     block_number: u32,
     block_timestamp: u32,
 }
@@ -463,44 +1269,45 @@ impl Reserve {
     pub fn from_pair_row(row: &postgres::Row, pool_digit: &str) -> Reserve {
         let pool_contract_address: &str = row.get(sql_field!("p{}_contract_address", pool_digit));
         let pool_digits_x: &str = row.get(sql_field!("qty_x{}", pool_digit));
-        let pool_x = u128::from_str_radix(pool_digits_x, 10).unwrap();
+        let pool_x = pool_digits_x.parse::<U256>().unwrap();
         let pool_digits_y: &str = row.get(sql_field!("qty_y{}", pool_digit));
-        let pool_y = u128::from_str_radix(pool_digits_y, 10).unwrap();
+        let pool_y = pool_digits_y.parse::<U256>().unwrap();
         let pool_block: i32 = row.get(sql_field!("p{}_block_number", pool_digit));
         let pool_timestamp: i32 = row.get(sql_field!("p{}_block_timestamp", pool_digit));
         Reserve {
-            contract_address: pool_contract_address.to_owned(),
+            contract_address: address::from_db(pool_contract_address),
             x: pool_x,
             y: pool_y,
             block_number: pool_block as u32,
             block_timestamp: pool_timestamp as u32,
         }
     }
-    pub fn block_time_str(self: &Self) -> String {
+    pub fn block_time_str(&self) -> String {
         DateTime::from_timestamp(self.block_timestamp as i64, 0)
             .unwrap()
             .to_string()
     }
 }
 
+#[derive(Clone)]
 struct PoolSnapshot {
     pool: Pool,
     reserve: Reserve,
 }
 
 impl PoolSnapshot {
-    fn price(self: &Self) -> f64 {
-        let d0 = self.pool.coin0.decimals;
-        let d1 = self.pool.coin1.decimals;
-        let scale = decimal::scale(self.reserve.y, self.reserve.x);
-        if d0 > d1 {
-            scale * 10.0_f64.powf((d0 - d1) as f64)
-        } else {
-            scale / 10.0_f64.powf((d1 - d0) as f64)
-        }
+    // coin0 priced in coin1, adjusted for token decimals. None for an empty pool.
+    fn price(&self) -> Option<Decimal> {
+        Decimal::ratio(
+            self.reserve.y,
+            self.pool.coin1.decimals as u8,
+            self.reserve.x,
+            self.pool.coin0.decimals as u8,
+        )
     }
 }
 
+#[derive(Clone)]
 struct Pair {
     pool0: PoolSnapshot,
     pool1: PoolSnapshot,
@@ -524,27 +1331,20 @@ impl Pair {
 
 struct Match {
     pair: Pair,
-    pool0_ay_in: u128,
-    pool0_ax_out: u128,
-    pool1_ay_out: u128,
+    pool0_ay_in: U256,
+    pool0_ax_out: U256,
+    pool1_ay_out: U256,
 }
 
 impl Match {
-    pub fn to_string(self: &Self, gas_cost_wei: u128) -> String {
+    pub fn to_string(&self, gas_cost_wei: u128) -> String {
         format!(
-            "{:0.4}{} profit:{:0.4}{} gas:{:0.4}{} p0:{} #{} p1:{} #{} ",
-            self.pool0_ay_in as f64 / 10_f64.powi(self.pair.pool0.pool.coin1.decimals),
+            "{}{} profit:{:0.4}{} gas:{}{} p0:{} #{} p1:{} #{} ",
+            self.coin1_units(self.pool0_ay_in),
             self.pair.pool0.pool.coin1.symbol,
             self.scaled_profit(),
             self.pair.pool0.pool.coin1.symbol,
-            decimal::scale(
-                unipool::get_y_out(
-                    gas_cost_wei,
-                    self.pair.pool0.reserve.x,
-                    self.pair.pool0.reserve.y
-                ),
-                10_u128.pow(6)
-            ),
+            self.coin1_units(self.gas_cost_coin1(gas_cost_wei)),
             self.pair.pool0.pool.coin1.symbol,
             self.pair.pool0.pool.contract_address,
             self.pair.pool0.reserve.block_time_str(),
@@ -557,20 +1357,92 @@ impl Match {
         )
     }
 
-    pub fn profit(self: &Self) -> u128 {
+    pub fn direction(&self) -> String {
+        format!(
+            "{}>{}>{}",
+            self.pair.pool0.pool.coin1.symbol,
+            self.pair.pool0.pool.coin0.symbol,
+            self.pair.pool1.pool.coin1.symbol,
+        )
+    }
+
+    // gas paid in eth (coin0), valued in coin1 at pool0.
+    // a cost that cannot be computed is U256::MAX so it never gets approved.
+    pub fn gas_cost_coin1(&self, gas_cost_wei: u128) -> U256 {
+        unipool::get_y_out(
+            U256::from(gas_cost_wei),
+            self.pair.pool0.reserve.x,
+            self.pair.pool0.reserve.y,
+        )
+        .unwrap_or(U256::MAX)
+    }
+
+    pub fn profit(&self) -> U256 {
         self.pool1_ay_out.saturating_sub(self.pool0_ay_in)
     }
-    pub fn scaled_profit(self: &Self) -> f64 {
-        self.profit() as f64 / 10_f64.powi(self.pair.pool0.pool.coin1.decimals)
+    pub fn scaled_profit(&self) -> Decimal {
+        // trade_simulate keeps amounts within uint112
+        Decimal::from_units(self.profit(), self.pair.pool0.pool.coin1.decimals as u8).unwrap()
+    }
+    pub fn scaled_net_profit(&self, gas_cost_wei: u128) -> Decimal {
+        let gas = I256::try_from(self.gas_cost_coin1(gas_cost_wei)).unwrap_or(I256::MAX);
+        let net = I256::try_from(self.profit()).unwrap().saturating_sub(gas);
+        Decimal::from_signed_units(net, self.pair.pool0.pool.coin1.decimals as u8)
     }
+    fn coin1_units(&self, amount: U256) -> String {
+        match Decimal::from_units(amount, self.pair.pool0.pool.coin1.decimals as u8) {
+            Some(amount) => format!("{:0.4}", amount),
+            None => format!("{}(raw)", amount),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Execution {
+    tx_hash: String,
+    status: bool,
+    block_number: Option<u64>,
+    gas_used: u64,
+    effective_gas_price: u128,
+    eth_delta: I256,
+    coin0_delta: I256,
+    coin1_delta: I256,
+    // why it failed, None when it went through
+    revert_reason: Option<String>,
+    failure: Option<revert::Failure>,
+}
+
+// realized change in a balance, negative when it went down
+fn balance_delta(start: U256, end: U256) -> I256 {
+    I256::try_from(end).unwrap() - I256::try_from(start).unwrap()
+}
+
+#[cfg(test)]
+#[test]
+fn test_balance_delta() {
+    assert_eq!(
+        balance_delta(U256::from(10), U256::from(25)),
+        I256::try_from(15).unwrap()
+    );
+    assert_eq!(
+        balance_delta(U256::from(25), U256::from(10)),
+        I256::try_from(-15).unwrap()
+    );
 }
 
 fn simulate(pairs: Vec<Pair>) -> Vec<Match> {
     let mut matches = vec![];
     for pair in pairs {
-        match trade_simulate(pair) {
+        let span = info_span!(
+            "simulate",
+            pool0 = %pair.pool0.pool.contract_address,
+            pool1 = %pair.pool1.pool.contract_address,
+            block0 = pair.pool0.reserve.block_number,
+            block1 = pair.pool1.reserve.block_number,
+        );
+        match span.in_scope(|| trade_simulate(pair)) {
             Ok(r#match) => matches.push(r#match),
-            Err(_err) => (),
+            Err(err) => debug!(%err, "no match"),
         }
     }
     matches
@@ -581,27 +1453,29 @@ fn trade_simulate(pair: Pair) -> Result<Match, String> {
     let ay = pair.pool0.reserve.y;
     let bx = pair.pool1.reserve.x;
     let by = pair.pool1.reserve.y;
-    let p1 = pair.pool0.price();
-    let p2 = pair.pool1.price();
-    if p1 < p2 {
-        println!(
-            "{}1 price {} {}2 price {} ratio:{:.4} p0:{} p1:{}",
-            if p1 < p2 { "P" } else { "p" },
-            p1,
-            if p1 > p2 { "P" } else { "p" },
-            p2,
-            1.0 - (p1 / p2),
-            pair.pool0.pool.contract_address,
-            pair.pool1.pool.contract_address,
-        );
+    if let (Some(p1), Some(p2)) = (pair.pool0.price(), pair.pool1.price())
+        && p1 < p2
+    {
+        let ratio = Decimal::ONE
+            .checked_sub(p1.checked_div(p2).unwrap())
+            .unwrap();
+        debug!(%p1, %p2, %ratio, "pool0 cheaper");
+    }
+
+    // uniswap v2 stores reserves as uint112. anything bigger is bad data.
+    for reserve in [ax, ay, bx, by] {
+        if reserve.bit_len() > 112 {
+            return Err(format!("reserve {} does not fit uint112", reserve));
+        }
     }
 
     // f(b) - f(a) == 0
-    let oay_in = unipool::optimal_ay_in(ax, ay, bx, by)?;
+    let (a_fee, b_fee) = (pair.pool0.pool.fee_bps, pair.pool1.pool.fee_bps);
+    let oay_in = unipool::optimal_ay_in_with_fees(ax, ay, bx, by, a_fee, b_fee)?;
 
     // trade simulation
-    let s1_adx = unipool::get_y_out(oay_in, pair.pool0.reserve.y, pair.pool0.reserve.x);
-    let s2_ady = unipool::get_y_out(s1_adx, pair.pool1.reserve.x, pair.pool1.reserve.y);
+    let s1_adx = unipool::get_y_out_with_fee(oay_in, ay, ax, a_fee)?;
+    let s2_ady = unipool::get_y_out_with_fee(s1_adx, bx, by, b_fee)?;
     // let profit = s2_ady - oay_in as u128;
 
     Ok(Match {
@@ -612,31 +1486,43 @@ fn trade_simulate(pair: Pair) -> Result<Match, String> {
     })
 }
 
-fn pool(db: &mut postgres::Client, contract_address_in: &str) -> Pool {
-    let sql = "SELECT * from pools where contract_address = $1";
-    let rows = db.query(sql, &[&contract_address_in]).unwrap();
-    let contract_address = rows[0].get::<_, String>("contract_address");
-    let token0 = rows[0].get::<_, String>("token0");
-    let coin0 = coin(db, &token0);
-    let token1 = rows[0].get::<_, String>("token1");
-    let coin1 = coin(db, &token1);
+// None when the indexer has no such pool or coin
+fn pool(
+    db: &mut postgres::Client,
+    contract_address_in: Address,
+) -> Result<Option<Pool>, postgres::Error> {
+    let sql = "SELECT * from pools where lower(right(contract_address, 40)) = $1";
+    let Some(row) = db.query_opt(sql, &[&address::to_db(contract_address_in)])? else {
+        return Ok(None);
+    };
+    let contract_address = address::from_db(row.get("contract_address"));
+    let (Some(coin0), Some(coin1)) = (
+        coin(db, address::from_db(row.get("token0")))?,
+        coin(db, address::from_db(row.get("token1")))?,
+    ) else {
+        return Ok(None);
+    };
 
-    Pool {
+    Ok(Some(Pool {
         contract_address,
         coin0,
         coin1,
-    }
+        fee_bps: unipool::POOL_FEE_BASIS_POINTS,
+        flipped: false,
+    }))
 }
 
-fn coin(db: &mut postgres::Client, contract_address: &str) -> Coin {
-    let sql = "SELECT * from coins where contract_address = $1";
-    let rows = db.query(sql, &[&contract_address]).unwrap();
-    let row = &rows[0];
-    Coin {
-        contract_address: row.get::<_, String>("contract_address"),
+fn coin(
+    db: &mut postgres::Client,
+    contract_address: Address,
+) -> Result<Option<Coin>, postgres::Error> {
+    let sql = "SELECT * from coins where lower(right(contract_address, 40)) = $1";
+    let row = db.query_opt(sql, &[&address::to_db(contract_address)])?;
+    Ok(row.map(|row| Coin {
+        contract_address: address::from_db(row.get("contract_address")),
         symbol: row.get::<_, String>("symbol"),
         decimals: row.get::<_, i32>("decimals"),
-    }
+    }))
 }
 
 fn rows_count(db: &mut postgres::Client, table_name: &str) -> i64 {
@@ -645,57 +1531,87 @@ fn rows_count(db: &mut postgres::Client, table_name: &str) -> i64 {
     rows[0].get::<_, i64>("count")
 }
 
-fn reserves_for(db: &mut postgres::Client, token: &str, block_number: i32) -> (u128, u128, i32) {
-    let sql = "SELECT * from reserves where contract_address = $1 and block_number = $2 order by block_number desc limit 1";
-    let rows = db.query(sql, &[&token, &block_number]).unwrap();
+fn reserves_for(db: &mut postgres::Client, token: Address, block_number: i32) -> (U256, U256, i32) {
+    let sql = "SELECT * from reserves where lower(right(contract_address, 40)) = $1 and block_number = $2 order by block_number desc limit 1";
+    let rows = db
+        .query(sql, &[&address::to_db(token), &block_number])
+        .unwrap();
     let row = &rows[0];
     let digits_x: &str = row.get::<_, &str>("x");
     let digits_y: &str = row.get::<_, &str>("y");
     let block_number = row.get::<_, i32>("block_number");
-    let x = u128::from_str_radix(digits_x, 10).unwrap();
-    let y = u128::from_str_radix(digits_y, 10).unwrap();
+    let x = digits_x.parse::<U256>().unwrap();
+    let y = digits_y.parse::<U256>().unwrap();
     (x, y, block_number)
 }
 
+// None before the indexer has seen the pool's reserves
+fn reserves_latest(
+    db: &mut postgres::Client,
+    contract_address: Address,
+) -> Result<Option<Reserve>, postgres::Error> {
+    let sql =
+        "SELECT r.*, b.timestamp from reserves AS r JOIN blocks AS b ON b.number = r.block_number
+               where lower(right(r.contract_address, 40)) = $1 order by r.block_number desc limit 1";
+    let row = db.query_opt(sql, &[&address::to_db(contract_address)])?;
+    Ok(row.map(|row| Reserve {
+        contract_address,
+        x: row.get::<_, &str>("x").parse::<U256>().unwrap(),
+        y: row.get::<_, &str>("y").parse::<U256>().unwrap(),
+        block_number: row.get::<_, i32>("block_number") as u32,
+        block_timestamp: row.get::<_, i32>("timestamp") as u32,
+    }))
+}
+
+// every two pools of base_token and the same quote token. base_token is coin0 and its
+// reserve x whether it is the pools' token0 or token1.
 fn pairs_with(
     db: &mut postgres::Client,
-    base_token: &str,
+    base_token: Address,
 ) -> Result<Vec<postgres::Row>, postgres::Error> {
     let sql = "WITH latest_reserves AS
               (SELECT contract_address, block_number, x,y, ROW_NUMBER() OVER(PARTITION BY contract_address ORDER BY block_number desc)
-                FROM reserves ORDER BY contract_address, block_number)
+                FROM reserves ORDER BY contract_address, block_number),
+              based AS
+              (SELECT p.contract_address, p.token0 AS coin0, p.token1 AS coin1, false AS flipped, r.x, r.y, r.block_number
+                FROM pools AS p JOIN latest_reserves AS r ON p.contract_address = r.contract_address AND r.row_number = 1
+                WHERE lower(right(p.token0, 40)) = $1
+               UNION ALL
+               SELECT p.contract_address, p.token1, p.token0, true, r.y, r.x, r.block_number
+                FROM pools AS p JOIN latest_reserves AS r ON p.contract_address = r.contract_address AND r.row_number = 1
+                WHERE lower(right(p.token1, 40)) = $1)
               SELECT p1.contract_address as p1_contract_address,
-                     p1.token0 as p1_token0,
-                     p1.token1 as p1_token1,
+                     p1.coin0 as p1_coin0,
+                     p1.coin1 as p1_coin1,
+                     p1.flipped as p1_flipped,
                      p2.contract_address as p2_contract_address,
-                     p2.token0 as p2_token0,
-                     p2.token1 as p2_token1,
-                     p1c0.symbol as p1_token0_symbol,
-                     p1c1.symbol as p1_token1_symbol,
-                     p2c0.symbol as p2_token0_symbol,
-                     p2c1.symbol as p2_token1_symbol,
-                     p1c0.decimals as p1_token0_decimals,
-                     p1c1.decimals as p1_token1_decimals,
-                     p2c0.decimals as p2_token0_decimals,
-                     p2c1.decimals as p2_token1_decimals,
-                     lrp1.x as qty_x1, lrp2.x AS qty_x2, lrp1.block_number AS p1_block_number,
-                     lrp1.y as qty_y1, lrp2.y AS qty_y2, lrp2.block_number AS p2_block_number,
-                     lrp1b.timestamp as p1_block_timestamp,
-                     lrp2b.timestamp as p2_block_timestamp,
-                     ((lrp1.x::decimal/lrp1.y::decimal) - (lrp2.x::decimal/lrp2.y::decimal))::float8 as spread,
-                     (least(lrp1.x::decimal , lrp2.x::decimal ) *
-                       ((lrp1.x::decimal/lrp1.y::decimal) - (lrp2.x::decimal/lrp2.y::decimal)))::float8 as value
-              FROM pools AS p1
-              JOIN pools AS p2 ON p1.token0 = p2.token0 AND p1.token1 = p2.token1 AND p1.contract_address != p2.contract_address AND p1.token0 = $1
-              JOIN latest_reserves AS lrp1 ON p1.contract_address = lrp1.contract_address AND lrp1.row_number = 1
-              JOIN latest_reserves AS lrp2 ON p2.contract_address = lrp2.contract_address AND lrp2.row_number = 1
-              JOIN blocks as lrp1b ON lrp1b.number = lrp1.block_number
-              JOIN blocks as lrp2b ON lrp2b.number = lrp2.block_number
-              JOIN coins as p1c0 ON p1c0.contract_address = p1.token0
-              JOIN coins as p1c1 ON p1c1.contract_address = p1.token1
-              JOIN coins as p2c0 ON p2c0.contract_address = p2.token0
-              JOIN coins as p2c1 ON p2c1.contract_address = p2.token1
+                     p2.coin0 as p2_coin0,
+                     p2.coin1 as p2_coin1,
+                     p2.flipped as p2_flipped,
+                     p1c0.symbol as p1_coin0_symbol,
+                     p1c1.symbol as p1_coin1_symbol,
+                     p2c0.symbol as p2_coin0_symbol,
+                     p2c1.symbol as p2_coin1_symbol,
+                     p1c0.decimals as p1_coin0_decimals,
+                     p1c1.decimals as p1_coin1_decimals,
+                     p2c0.decimals as p2_coin0_decimals,
+                     p2c1.decimals as p2_coin1_decimals,
+                     p1.x as qty_x1, p2.x AS qty_x2, p1.block_number AS p1_block_number,
+                     p1.y as qty_y1, p2.y AS qty_y2, p2.block_number AS p2_block_number,
+                     p1b.timestamp as p1_block_timestamp,
+                     p2b.timestamp as p2_block_timestamp,
+                     ((p1.x::decimal/p1.y::decimal) - (p2.x::decimal/p2.y::decimal))::float8 as spread,
+                     (least(p1.x::decimal , p2.x::decimal ) *
+                       ((p1.x::decimal/p1.y::decimal) - (p2.x::decimal/p2.y::decimal)))::float8 as value
+              FROM based AS p1
+              JOIN based AS p2 ON p1.coin1 = p2.coin1 AND p1.contract_address != p2.contract_address
+              JOIN blocks as p1b ON p1b.number = p1.block_number
+              JOIN blocks as p2b ON p2b.number = p2.block_number
+              JOIN coins as p1c0 ON p1c0.contract_address = p1.coin0
+              JOIN coins as p1c1 ON p1c1.contract_address = p1.coin1
+              JOIN coins as p2c0 ON p2c0.contract_address = p2.coin0
+              JOIN coins as p2c1 ON p2c1.contract_address = p2.coin1
               ORDER BY value desc";
 
-    db.query(sql, &[&base_token])
+    db.query(sql, &[&address::to_db(base_token)])
 }
@@ -0,0 +1,551 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use alloy::{
+    consensus::Transaction as _,
+    primitives::{Address, B256, Bytes, U256},
+    providers::Provider,
+    rpc::types::Transaction,
+    sol_types::SolCall,
+};
+use tracing::{debug, warn};
+
+use crate::contracts::{UniswapV2Pair, UniswapV2Router02::*};
+use crate::dex::Dex;
+use crate::unipool;
+use crate::{Match, Pair, metrics};
+
+// the reserves table and the latest block are behind swaps still in the mempool. pending
+// transactions are polled from mempool_url with a pending transaction filter, swaps sent
+// to a dex's router02 and direct pair swaps among them are replayed on a copy of the
+// pairs' reserves with get_y_out, and each pending swap's post-state is simulated for a
+// back-run.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingTx {
+    pub hash: B256,
+    pub to: Option<Address>,
+    pub input: Bytes,
+    pub value: U256,
+}
+
+pub trait PendingSource {
+    // transactions that entered the mempool since the last call
+    fn pending(&self) -> Result<Vec<PendingTx>, String>;
+}
+
+// one pool of a path, token0 in when zero_for_one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hop {
+    pub pool: Address,
+    pub zero_for_one: bool,
+}
+
+// what a pending transaction does to the pools
+#[derive(Clone, Debug, PartialEq)]
+pub enum Intent {
+    // amount_in of the first hop's token through every hop, reverting under amount_out_min
+    ExactIn {
+        hops: Vec<Hop>,
+        amount_in: U256,
+        amount_out_min: U256,
+    },
+    // amount_out of the last hop's token, reverting over amount_in_max
+    ExactOut {
+        hops: Vec<Hop>,
+        amount_out: U256,
+        amount_in_max: U256,
+    },
+    // pair.swap, whatever was sent in beforehand is worked out from the reserves
+    PairSwap {
+        pool: Address,
+        amount0_out: U256,
+        amount1_out: U256,
+    },
+}
+
+// reserve0, reserve1 and the fee in basis points by pool
+pub type Book = HashMap<Address, (U256, U256, u8)>;
+
+// the swap a transaction to a router or a known pool makes, None for anything else
+pub fn decode(tx: &PendingTx, dexes: &[Dex], book: &Book) -> Option<Intent> {
+    let to = tx.to?;
+    if book.contains_key(&to) {
+        let call = UniswapV2Pair::swapCall::abi_decode(&tx.input).ok()?;
+        return Some(Intent::PairSwap {
+            pool: to,
+            amount0_out: call.amount0Out,
+            amount1_out: call.amount1Out,
+        });
+    }
+    let dex = dexes.iter().find(|dex| dex.router == Some(to))?;
+    let hops = |path: &[Address]| {
+        path.windows(2)
+            .map(|hop| Hop {
+                pool: dex.pair_for(hop[0], hop[1]),
+                zero_for_one: hop[0] < hop[1],
+            })
+            .collect::<Vec<Hop>>()
+    };
+    let exact_in = |path: &[Address], amount_in, amount_out_min| Intent::ExactIn {
+        hops: hops(path),
+        amount_in,
+        amount_out_min,
+    };
+    let exact_out = |path: &[Address], amount_out, amount_in_max| Intent::ExactOut {
+        hops: hops(path),
+        amount_out,
+        amount_in_max,
+    };
+    let input = &tx.input;
+    let selector = <[u8; 4]>::try_from(input.get(..4)?).ok()?;
+    // fee on transfer variants are taken at face value
+    let intent = match selector {
+        swapExactTokensForTokensCall::SELECTOR => {
+            let call = swapExactTokensForTokensCall::abi_decode(input).ok()?;
+            exact_in(&call.path, call.amountIn, call.amountOutMin)
+        }
+        swapExactTokensForTokensSupportingFeeOnTransferTokensCall::SELECTOR => {
+            let call = swapExactTokensForTokensSupportingFeeOnTransferTokensCall::abi_decode(input)
+                .ok()?;
+            exact_in(&call.path, call.amountIn, call.amountOutMin)
+        }
+        swapExactTokensForETHCall::SELECTOR => {
+            let call = swapExactTokensForETHCall::abi_decode(input).ok()?;
+            exact_in(&call.path, call.amountIn, call.amountOutMin)
+        }
+        swapExactTokensForETHSupportingFeeOnTransferTokensCall::SELECTOR => {
+            let call =
+                swapExactTokensForETHSupportingFeeOnTransferTokensCall::abi_decode(input).ok()?;
+            exact_in(&call.path, call.amountIn, call.amountOutMin)
+        }
+        swapExactETHForTokensCall::SELECTOR => {
+            let call = swapExactETHForTokensCall::abi_decode(input).ok()?;
+            exact_in(&call.path, tx.value, call.amountOutMin)
+        }
+        swapExactETHForTokensSupportingFeeOnTransferTokensCall::SELECTOR => {
+            let call =
+                swapExactETHForTokensSupportingFeeOnTransferTokensCall::abi_decode(input).ok()?;
+            exact_in(&call.path, tx.value, call.amountOutMin)
+        }
+        swapTokensForExactTokensCall::SELECTOR => {
+            let call = swapTokensForExactTokensCall::abi_decode(input).ok()?;
+            exact_out(&call.path, call.amountOut, call.amountInMax)
+        }
+        swapTokensForExactETHCall::SELECTOR => {
+            let call = swapTokensForExactETHCall::abi_decode(input).ok()?;
+            exact_out(&call.path, call.amountOut, call.amountInMax)
+        }
+        swapETHForExactTokensCall::SELECTOR => {
+            let call = swapETHForExactTokensCall::abi_decode(input).ok()?;
+            exact_out(&call.path, call.amountOut, tx.value)
+        }
+        _ => return None,
+    };
+    Some(intent)
+}
+
+// apply intent to book, returning the pools it moved. hops stop at the first pool the
+// book does not have, a swap that would revert on its limit moves nothing.
+pub fn apply(intent: &Intent, book: &mut Book) -> Result<Vec<Address>, String> {
+    let mut after = book.clone();
+    let mut moved = vec![];
+    match intent {
+        Intent::ExactIn {
+            hops,
+            amount_in,
+            amount_out_min,
+        } => {
+            let mut amount = *amount_in;
+            for hop in hops {
+                let Some(reserves) = after.get_mut(&hop.pool) else {
+                    break;
+                };
+                let (reserve_in, reserve_out, fee) = sides(reserves, hop.zero_for_one);
+                let out = unipool::get_y_out_with_fee(amount, *reserve_in, *reserve_out, fee)?;
+                *reserve_in += amount;
+                *reserve_out -= out;
+                moved.push(hop.pool);
+                amount = out;
+            }
+            if moved.len() == hops.len() && amount < *amount_out_min {
+                return Ok(vec![]);
+            }
+        }
+        Intent::ExactOut {
+            hops,
+            amount_out,
+            amount_in_max,
+        } => {
+            let mut amount = *amount_out;
+            for hop in hops.iter().rev() {
+                let Some(reserves) = after.get_mut(&hop.pool) else {
+                    break;
+                };
+                let (reserve_in, reserve_out, fee) = sides(reserves, hop.zero_for_one);
+                let amount_in = unipool::get_x_in_with_fee(amount, *reserve_in, *reserve_out, fee)
+                    .ok_or_else(|| format!("{} does not hold {}", hop.pool, amount))?;
+                *reserve_in += amount_in;
+                *reserve_out -= amount;
+                moved.push(hop.pool);
+                amount = amount_in;
+            }
+            if moved.len() == hops.len() && amount > *amount_in_max {
+                return Ok(vec![]);
+            }
+        }
+        Intent::PairSwap {
+            pool,
+            amount0_out,
+            amount1_out,
+        } => {
+            let reserves = after
+                .get_mut(pool)
+                .ok_or_else(|| format!("{} not in the book", pool))?;
+            // one side out, the other must have come in
+            let (zero_for_one, amount) = if amount0_out.is_zero() {
+                (true, *amount1_out)
+            } else {
+                (false, *amount0_out)
+            };
+            let (reserve_in, reserve_out, fee) = sides(reserves, zero_for_one);
+            let amount_in = unipool::get_x_in_with_fee(amount, *reserve_in, *reserve_out, fee)
+                .ok_or_else(|| format!("{} does not hold {}", pool, amount))?;
+            *reserve_in += amount_in;
+            *reserve_out -= amount;
+            moved.push(*pool);
+        }
+    }
+    *book = after;
+    Ok(moved)
+}
+
+// the pending swaps that open an arb, each simulated on its own on top of pairs
+pub fn back_runs(
+    source: &dyn PendingSource,
+    dexes: &[Dex],
+    pairs: &[Pair],
+) -> Result<Vec<(B256, Match)>, String> {
+    let book = pairs
+        .iter()
+        .flat_map(|pair| [&pair.pool0, &pair.pool1])
+        .map(|snapshot| {
+            let (reserve0, reserve1) = snapshot
+                .pool
+                .ordered(snapshot.reserve.x, snapshot.reserve.y);
+            (
+                snapshot.pool.contract_address,
+                (reserve0, reserve1, snapshot.pool.fee_bps),
+            )
+        })
+        .collect::<Book>();
+    let mut back_runs = vec![];
+    for tx in source.pending()? {
+        let Some(intent) = decode(&tx, dexes, &book) else {
+            continue;
+        };
+        metrics::PENDING_SWAPS
+            .with_label_values(&[&metrics::chain_id()])
+            .inc();
+        let mut predicted = book.clone();
+        let moved = match apply(&intent, &mut predicted) {
+            Ok(moved) => moved,
+            Err(err) => {
+                debug!(tx = %tx.hash, %err, "pending swap not applied");
+                continue;
+            }
+        };
+        for pair in pairs.iter().filter(|pair| {
+            moved.contains(&pair.pool0.pool.contract_address)
+                || moved.contains(&pair.pool1.pool.contract_address)
+        }) {
+            let mut pair = pair.clone();
+            for snapshot in [&mut pair.pool0, &mut pair.pool1] {
+                let (reserve0, reserve1, _) = predicted[&snapshot.pool.contract_address];
+                (snapshot.reserve.x, snapshot.reserve.y) =
+                    snapshot.pool.ordered(reserve0, reserve1);
+            }
+            if let Ok(r#match) = crate::trade_simulate(pair) {
+                metrics::BACK_RUNS
+                    .with_label_values(&[&metrics::chain_id()])
+                    .inc();
+                back_runs.push((tx.hash, r#match));
+            }
+        }
+    }
+    Ok(back_runs)
+}
+
+// (reserve in, reserve out, fee)
+fn sides(reserves: &mut (U256, U256, u8), zero_for_one: bool) -> (&mut U256, &mut U256, u8) {
+    if zero_for_one {
+        (&mut reserves.0, &mut reserves.1, reserves.2)
+    } else {
+        (&mut reserves.1, &mut reserves.0, reserves.2)
+    }
+}
+
+// polls a node's pending transaction filter, making a new one when it expires
+pub struct Watcher<P> {
+    provider: P,
+    filter: Mutex<Option<U256>>,
+}
+
+impl<P: Provider> Watcher<P> {
+    pub fn new(provider: P) -> Watcher<P> {
+        Watcher {
+            provider,
+            filter: Mutex::new(None),
+        }
+    }
+}
+
+impl<P: Provider> PendingSource for Watcher<P> {
+    #[tokio::main]
+    async fn pending(&self) -> Result<Vec<PendingTx>, String> {
+        let filter = *self.filter.lock().unwrap();
+        let filter = match filter {
+            Some(filter) => filter,
+            None => {
+                // the first poll starts the filter, transactions come from the next
+                let filter = metrics::rpc(
+                    "eth_newPendingTransactionFilter",
+                    self.provider.new_pending_transactions_filter(true),
+                )
+                .await
+                .map_err(|err| err.to_string())?;
+                *self.filter.lock().unwrap() = Some(filter);
+                return Ok(vec![]);
+            }
+        };
+        let txs = metrics::rpc(
+            "eth_getFilterChanges",
+            self.provider.get_filter_changes::<Transaction>(filter),
+        )
+        .await
+        .map_err(|err| {
+            warn!(%err, "pending transaction filter lost");
+            *self.filter.lock().unwrap() = None;
+            err.to_string()
+        })?;
+        Ok(txs
+            .into_iter()
+            .map(|tx| PendingTx {
+                hash: *tx.inner.tx_hash(),
+                to: tx.to(),
+                input: tx.input().clone(),
+                value: tx.value(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex::UNISWAP_INIT_CODE_HASH;
+    use crate::fixture::{COIN0, COIN1, POOL1, PairBuilder, coin};
+
+    const ROUTER: Address = Address::with_last_byte(0x2a);
+
+    fn dex() -> Dex {
+        Dex {
+            name: "uniswap".to_owned(),
+            factory: Address::with_last_byte(0xfa),
+            router: Some(ROUTER),
+            fee_bps: 30,
+            init_code_hash: UNISWAP_INIT_CODE_HASH,
+        }
+    }
+
+    fn tx(to: Address, input: Vec<u8>, value: u64) -> PendingTx {
+        PendingTx {
+            hash: B256::with_last_byte(1),
+            to: Some(to),
+            input: input.into(),
+            value: U256::from(value),
+        }
+    }
+
+    struct FakeSource(Vec<PendingTx>);
+
+    impl PendingSource for FakeSource {
+        fn pending(&self) -> Result<Vec<PendingTx>, String> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let pool = dex().pair_for(COIN0, COIN1);
+        let book = Book::from([(POOL1, (U256::ZERO, U256::ZERO, 30))]);
+        let path = vec![COIN1, COIN0];
+        let input = swapExactTokensForTokensCall {
+            amountIn: U256::from(100),
+            amountOutMin: U256::from(90),
+            path: path.clone(),
+            to: Address::ZERO,
+            deadline: U256::ZERO,
+        }
+        .abi_encode();
+        let hop = Hop {
+            pool,
+            zero_for_one: false,
+        };
+        assert_eq!(
+            decode(&tx(ROUTER, input.clone(), 0), &[dex()], &book),
+            Some(Intent::ExactIn {
+                hops: vec![hop],
+                amount_in: U256::from(100),
+                amount_out_min: U256::from(90)
+            })
+        );
+        // another router
+        assert_eq!(decode(&tx(POOL1, input, 0), &[dex()], &Book::new()), None);
+        let input = swapETHForExactTokensCall {
+            amountOut: U256::from(50),
+            path,
+            to: Address::ZERO,
+            deadline: U256::ZERO,
+        }
+        .abi_encode();
+        assert_eq!(
+            decode(&tx(ROUTER, input, 60), &[dex()], &book),
+            Some(Intent::ExactOut {
+                hops: vec![hop],
+                amount_out: U256::from(50),
+                amount_in_max: U256::from(60)
+            })
+        );
+        let input = UniswapV2Pair::swapCall {
+            amount0Out: U256::ZERO,
+            amount1Out: U256::from(7),
+            to: Address::ZERO,
+            data: Bytes::new(),
+        }
+        .abi_encode();
+        assert_eq!(
+            decode(&tx(POOL1, input, 0), &[dex()], &book),
+            Some(Intent::PairSwap {
+                pool: POOL1,
+                amount0_out: U256::ZERO,
+                amount1_out: U256::from(7)
+            })
+        );
+        // not a swap
+        let input = addLiquidityETHCall {
+            token: COIN0,
+            amountTokenDesired: U256::from(1),
+            amountTokenMin: U256::ZERO,
+            amountETHMin: U256::ZERO,
+            to: Address::ZERO,
+            deadline: U256::ZERO,
+        }
+        .abi_encode();
+        assert_eq!(decode(&tx(ROUTER, input, 1), &[dex()], &book), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let (x, y) = (U256::from(300000), U256::from(300000));
+        // a dex cheaper than uniswap
+        let book = Book::from([(POOL1, (x, y, 25))]);
+        let sell = |amount_out_min: u64| Intent::ExactIn {
+            hops: vec![Hop {
+                pool: POOL1,
+                zero_for_one: true,
+            }],
+            amount_in: U256::from(3000),
+            amount_out_min: U256::from(amount_out_min),
+        };
+        let out = unipool::get_y_out_with_fee(U256::from(3000), x, y, 25).unwrap();
+        let mut predicted = book.clone();
+        assert_eq!(apply(&sell(0), &mut predicted), Ok(vec![POOL1]));
+        assert_eq!(predicted[&POOL1], (x + U256::from(3000), y - out, 25));
+        // it would revert
+        let mut predicted = book.clone();
+        assert_eq!(apply(&sell(3000), &mut predicted), Ok(vec![]));
+        assert_eq!(predicted, book);
+
+        let buy = Intent::ExactOut {
+            hops: vec![Hop {
+                pool: POOL1,
+                zero_for_one: false,
+            }],
+            amount_out: U256::from(2000),
+            amount_in_max: U256::MAX,
+        };
+        let mut predicted = book.clone();
+        apply(&buy, &mut predicted).unwrap();
+        let (reserve0, reserve1, _) = predicted[&POOL1];
+        assert_eq!(reserve0, x - U256::from(2000));
+        let paid = reserve1 - y;
+        let out = |paid| unipool::get_y_out_with_fee(paid, y, x, 25).unwrap();
+        assert!(out(paid) >= U256::from(2000));
+        assert!(out(paid - U256::from(1)) < U256::from(2000));
+        // a pair swap of the same is the same
+        let mut swapped = book.clone();
+        let swap = Intent::PairSwap {
+            pool: POOL1,
+            amount0_out: U256::from(2000),
+            amount1_out: U256::ZERO,
+        };
+        assert_eq!(apply(&swap, &mut swapped), Ok(vec![POOL1]));
+        assert_eq!(swapped, predicted);
+    }
+
+    #[test]
+    fn test_back_runs() {
+        let pool0 = dex().pair_for(COIN0, COIN1);
+        // level pools, nothing to arb until someone dumps coin0 into pool0
+        let pair = PairBuilder::new()
+            .pool0(300000, 300000)
+            .pool1(300000, 300000)
+            .addresses(pool0, POOL1)
+            .build();
+        let input = |amount_in: u64| {
+            swapExactTokensForTokensCall {
+                amountIn: U256::from(amount_in),
+                amountOutMin: U256::ZERO,
+                path: vec![COIN0, COIN1],
+                to: Address::ZERO,
+                deadline: U256::ZERO,
+            }
+            .abi_encode()
+        };
+        let source = FakeSource(vec![
+            tx(ROUTER, input(30000), 0),
+            tx(COIN0, input(30000), 0),
+        ]);
+        let found = back_runs(&source, &[dex()], std::slice::from_ref(&pair)).unwrap();
+        assert_eq!(found.len(), 1);
+        let (hash, r#match) = &found[0];
+        assert_eq!(*hash, B256::with_last_byte(1));
+        assert!(r#match.profit() > U256::ZERO);
+        assert_eq!(r#match.pair.pool0.reserve.x, U256::from(300000 + 30000));
+        // too small to pay the pool fees twice
+        let source = FakeSource(vec![tx(ROUTER, input(100), 0)]);
+        assert!(back_runs(&source, &[dex()], &[pair]).unwrap().is_empty());
+
+        // coin0 as the pools' token1, the dump still lands on x
+        let flipped = PairBuilder::new()
+            .coins(coin(COIN1, "USDONC", 18), coin(COIN0, "USDONA", 18))
+            .pool0(300000, 600000)
+            .pool1(300000, 600000)
+            .addresses(pool0, POOL1)
+            .flipped()
+            .build();
+        let dump = swapExactTokensForTokensCall {
+            amountIn: U256::from(30000),
+            amountOutMin: U256::ZERO,
+            path: vec![COIN1, COIN0],
+            to: Address::ZERO,
+            deadline: U256::ZERO,
+        }
+        .abi_encode();
+        let source = FakeSource(vec![tx(ROUTER, dump, 0)]);
+        let found = back_runs(&source, &[dex()], &[flipped]).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1.pair.pool0.reserve.x, U256::from(330000));
+    }
+}
@@ -0,0 +1,296 @@
+use std::cell::RefCell;
+use std::future::IntoFuture;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, GaugeVec, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder, register_gauge_vec,
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
+};
+use tracing::warn;
+
+// a scraper that connects and sends nothing is dropped after this
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// every metric is by chain_id, each chain scans on its own. those moved below the scan
+// loop take it from the thread, see set_chain_id.
+thread_local! {
+    static CHAIN_ID: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+pub static SCAN_CYCLES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "gofi_scan_cycles_total",
+        "scan cycles started",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+pub static POOLS_LOADED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "gofi_pools_loaded",
+        "rows in the pools table",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+pub static PAIRS_CONSIDERED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "gofi_pairs_considered",
+        "pairs left after filtering",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+pub static MATCHES_FOUND: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "gofi_matches_found",
+        "pairs with a simulated arb",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+pub static BEST_GROSS_PROFIT: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "gofi_best_gross_profit",
+        "best simulated profit this cycle, in coin1",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+pub static BEST_NET_PROFIT: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "gofi_best_net_profit",
+        "best simulated profit after gas this cycle, in coin1",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+pub static PAIRS_QUERY_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "gofi_pairs_query_seconds",
+        "pairs_with sql latency",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+pub static RPC_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "gofi_rpc_seconds",
+        "eth rpc latency",
+        &["chain_id", "method"]
+    )
+    .unwrap()
+});
+pub static RPC_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "gofi_rpc_errors_total",
+        "eth rpc errors",
+        &["chain_id", "method"]
+    )
+    .unwrap()
+});
+pub static FRESHNESS_ABORTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "gofi_freshness_aborts_total",
+        "swabs aborted because reserves moved",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+// status is one of sent, sped_up, cancelled, succeeded, reverted, cancel_mined
+pub static TRANSACTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "gofi_transactions_total",
+        "swab transactions",
+        &["chain_id", "status"]
+    )
+    .unwrap()
+});
+// failure is a revert::Failure
+pub static SWAB_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "gofi_swab_failures_total",
+        "failed swabs by cause",
+        &["chain_id", "failure"]
+    )
+    .unwrap()
+});
+// status is succeeded, reverted or skipped, the last when eth is down to the gas reserve
+pub static REBALANCES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "gofi_rebalances_total",
+        "wallet rebalancing swaps",
+        &["chain_id", "status"]
+    )
+    .unwrap()
+});
+// verdict is a screen::Verdict
+pub static SCREENINGS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "gofi_screenings_total",
+        "pools screened for transfer taxes and honeypots",
+        &["chain_id", "verdict"]
+    )
+    .unwrap()
+});
+pub static PENDING_SWAPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "gofi_pending_swaps_total",
+        "pending swaps decoded from the mempool",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+pub static BACK_RUNS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "gofi_back_runs_total",
+        "arbs a pending swap would open",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+pub static HALTED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "gofi_halted",
+        "1 while a risk limit halts trading",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+pub static WALLET_BALANCE: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "gofi_wallet_balance",
+        "wallet balance by token",
+        &["chain_id", "token"]
+    )
+    .unwrap()
+});
+
+// label what this thread records with chain_id from here on
+pub fn set_chain_id(chain_id: u64) {
+    CHAIN_ID.with(|current| *current.borrow_mut() = chain_id.to_string());
+}
+
+// the chain_id set on this thread, empty when none was
+pub fn chain_id() -> String {
+    CHAIN_ID.with(|current| current.borrow().clone())
+}
+
+// time an rpc call and count its errors under the given method name
+pub async fn rpc<T, E>(method: &str, call: impl IntoFuture<Output = Result<T, E>>) -> Result<T, E> {
+    let start = Instant::now();
+    let result = call.await;
+    let chain_id = chain_id();
+    RPC_SECONDS
+        .with_label_values(&[&chain_id, method])
+        .observe(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        RPC_ERRORS.with_label_values(&[&chain_id, method]).inc();
+    }
+    result
+}
+
+pub fn render() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+// serve /metrics from a background thread, each request in one of its own. returns the
+// bound address.
+pub fn serve(addr: &str) -> std::io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || {
+                if let Err(err) = respond(stream) {
+                    warn!(%err, "metrics request failed");
+                }
+            });
+        }
+    });
+    Ok(local_addr)
+}
+
+fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", render())
+    } else {
+        ("404 Not Found", "not found\n".to_owned())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        TextEncoder::new().format_type(),
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn scrape(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    // the registry is process-global and other tests move the same metrics, so these use
+    // labels of their own and assert deltas
+
+    #[test]
+    fn test_scrape_metrics() {
+        let addr = serve("127.0.0.1:0").unwrap();
+        // a client that sends nothing does not hold up the others
+        let _silent = TcpStream::connect(addr).unwrap();
+        let sent = TRANSACTIONS.with_label_values(&["test_scrape", "sent"]);
+        let before = sent.get();
+        POOLS_LOADED.with_label_values(&["test_scrape"]).set(42);
+        sent.inc();
+        let response = scrape(addr, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("gofi_pools_loaded{chain_id=\"test_scrape\"} 42"));
+        assert!(response.contains(&format!(
+            "gofi_transactions_total{{chain_id=\"test_scrape\",status=\"sent\"}} {}",
+            before + 1
+        )));
+        assert!(scrape(addr, "/").starts_with("HTTP/1.1 404"));
+    }
+
+    #[tokio::test]
+    async fn test_rpc_errors() {
+        // a chain_id no other test thread uses
+        set_chain_id(32343);
+        let errors = |method| RPC_ERRORS.with_label_values(&["32343", method]).get();
+        let calls = || {
+            RPC_SECONDS
+                .with_label_values(&["32343", "test_ok"])
+                .get_sample_count()
+        };
+        let (ok_errors, err_errors, ok_calls) = (errors("test_ok"), errors("test_err"), calls());
+        let ok: Result<u8, String> = rpc("test_ok", async { Ok(1) }).await;
+        assert_eq!(ok, Ok(1));
+        let err: Result<u8, String> = rpc("test_err", async { Err("down".to_owned()) }).await;
+        assert!(err.is_err());
+        assert_eq!(errors("test_err") - err_errors, 1);
+        assert_eq!(errors("test_ok") - ok_errors, 0);
+        assert_eq!(calls() - ok_calls, 1);
+    }
+}
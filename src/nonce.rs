@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use alloy::primitives::{Address, Bytes, U256};
+use tracing::{info, warn};
+
+use crate::chain::{Node, Receipt, TxRequest};
+use crate::metrics;
+
+// nonces are handed out locally so sends in quick succession never share one. a send
+// that sits unmined for replace_after_blocks is sent again with the same nonce and
+// higher fees, as the same call while still_wanted says the opportunity is there and as
+// a 0 value transfer to ourselves once it is not.
+
+// geth's txpool wants a replacement to pay at least 10% more
+const FEE_BUMP_PERCENT: u128 = 15;
+const CANCEL_GAS: u64 = 21000;
+const MAX_RPC_ERRORS: u32 = 10;
+const MAX_STALLED_POLLS: u32 = 120;
+
+#[derive(Default)]
+pub struct NonceManager {
+    next: Mutex<HashMap<Address, u64>>,
+}
+
+impl NonceManager {
+    // the next nonce for from. pending is the node's count, which is ahead of ours when
+    // something else sent from the same account.
+    pub fn reserve(
+        &self,
+        from: Address,
+        pending: impl FnOnce() -> Result<u64, String>,
+    ) -> Result<u64, String> {
+        let mut next = self.next.lock().unwrap();
+        let nonce = match next.get(&from) {
+            Some(&nonce) => nonce,
+            None => pending()?,
+        };
+        next.insert(from, nonce + 1);
+        Ok(nonce)
+    }
+
+    // after a send that may or may not have reached the node, ask it again next time
+    pub fn forget(&self, from: Address) {
+        self.next.lock().unwrap().remove(&from);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Replace {
+    pub after_blocks: u64,
+    pub max_replacements: u32,
+    pub poll: Duration,
+}
+
+// why send gave up. tx_hash is the last transaction it broadcast, which may still get
+// mined, None when nothing went out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SendError {
+    pub message: String,
+    pub tx_hash: Option<String>,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.tx_hash {
+            Some(tx_hash) => write!(f, "{}, last sent {}", self.message, tx_hash),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl From<String> for SendError {
+    fn from(message: String) -> SendError {
+        SendError {
+            message,
+            tx_hash: None,
+        }
+    }
+}
+
+// send to/data with a fresh nonce and wait until it or one of its replacements is mined.
+// once it is out, rpc errors are retried up to MAX_RPC_ERRORS in a row and a node whose
+// block stays the same for MAX_STALLED_POLLS polls is given up on.
+pub fn send(
+    node: &impl Node,
+    nonces: &NonceManager,
+    replace: &Replace,
+    to: Address,
+    data: Bytes,
+    gas: u64,
+    still_wanted: &dyn Fn() -> bool,
+) -> Result<Receipt, SendError> {
+    let from = node.from();
+    // before the nonce is taken, a failure here would leave a gap the node never fills
+    let (max_fee_per_gas, max_priority_fee_per_gas) = node.fees()?;
+    let nonce = nonces.reserve(from, || node.pending_nonce(from))?;
+    let mut tx = TxRequest {
+        to,
+        data,
+        value: U256::ZERO,
+        nonce,
+        gas,
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+    };
+    // (hash, whether it is a cancel)
+    let mut hashes = vec![(node.send(&tx).inspect_err(|_| nonces.forget(from))?, false)];
+    metrics::TRANSACTIONS
+        .with_label_values(&[&metrics::chain_id(), "sent"])
+        .inc();
+    // the last one may still get in, the node's pending count covers it
+    let give_up = |message: String, hashes: &[(String, bool)]| {
+        nonces.forget(from);
+        SendError {
+            message,
+            tx_hash: hashes.last().map(|(hash, _)| hash.clone()),
+        }
+    };
+    // the block the newest one was sent at, once the node has said
+    let mut sent_at = None;
+    let mut last_block = None;
+    let mut errors = 0;
+    let mut stalled = 0;
+    let mut cancelled = false;
+    let mut replacements = 0;
+    loop {
+        if errors == MAX_RPC_ERRORS {
+            return Err(give_up(
+                format!("nonce {}: {} rpc errors in a row", nonce, errors),
+                &hashes,
+            ));
+        }
+        if stalled == MAX_STALLED_POLLS {
+            return Err(give_up(
+                format!("nonce {}: no new block after {} polls", nonce, stalled),
+                &hashes,
+            ));
+        }
+        let block = match poll(node, &hashes) {
+            Ok(Poll::Pending(block)) => block,
+            Ok(Poll::Mined(receipt)) => return Ok(receipt),
+            Err(err) => {
+                errors += 1;
+                warn!(nonce, %err, errors, "waiting for the receipt");
+                thread::sleep(replace.poll);
+                continue;
+            }
+        };
+        errors = 0;
+        if last_block == Some(block) {
+            stalled += 1;
+        } else {
+            (last_block, stalled) = (Some(block), 0);
+        }
+        let sent_block = *sent_at.get_or_insert(block);
+        if block < sent_block + replace.after_blocks {
+            thread::sleep(replace.poll);
+            continue;
+        }
+        if replacements == replace.max_replacements {
+            return Err(give_up(
+                format!(
+                    "nonce {} not mined after {} replacements",
+                    nonce, replacements
+                ),
+                &hashes,
+            ));
+        }
+        let (max_fee_per_gas, max_priority_fee_per_gas) = match node.fees() {
+            Ok(fees) => fees,
+            Err(err) => {
+                errors += 1;
+                warn!(nonce, %err, errors, "no fees to replace with");
+                thread::sleep(replace.poll);
+                continue;
+            }
+        };
+        replacements += 1;
+        tx.max_fee_per_gas = bump(tx.max_fee_per_gas).max(max_fee_per_gas);
+        tx.max_priority_fee_per_gas = bump(tx.max_priority_fee_per_gas)
+            .max(max_priority_fee_per_gas)
+            .min(tx.max_fee_per_gas);
+        if !cancelled && !still_wanted() {
+            cancelled = true;
+            tx.to = from;
+            tx.data = Bytes::new();
+            tx.gas = CANCEL_GAS;
+        }
+        let label = if cancelled { "cancelled" } else { "sped_up" };
+        match node.send(&tx) {
+            Ok(hash) => {
+                info!(
+                    nonce,
+                    %hash,
+                    replaced = %hashes[hashes.len() - 1].0,
+                    blocks = block - sent_block,
+                    label,
+                    "replaced stuck transaction"
+                );
+                metrics::TRANSACTIONS
+                    .with_label_values(&[&metrics::chain_id(), label])
+                    .inc();
+                hashes.push((hash, cancelled));
+            }
+            // usually the one before got mined meanwhile, the receipts say
+            Err(err) => warn!(nonce, %err, label, "replacement not sent"),
+        }
+        sent_at = Some(block);
+    }
+}
+
+enum Poll {
+    Mined(Receipt),
+    // none is mined yet at this block
+    Pending(u64),
+}
+
+// the receipt of whichever of hashes got mined, newest first, any of them can be the one
+// that got in
+fn poll(node: &impl Node, hashes: &[(String, bool)]) -> Result<Poll, String> {
+    for (replacements, (hash, cancelled)) in hashes.iter().enumerate().rev() {
+        if let Some(receipt) = node.receipt(hash)? {
+            return Ok(Poll::Mined(Receipt {
+                cancelled: *cancelled,
+                replacements: replacements as u32,
+                ..receipt
+            }));
+        }
+    }
+    node.block_number().map(Poll::Pending)
+}
+
+fn bump(fee: u128) -> u128 {
+    fee + fee * FEE_BUMP_PERCENT / 100 + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use super::*;
+
+    const ME: Address = Address::repeat_byte(0xee);
+    const UNISWAB: Address = Address::with_last_byte(0x5b);
+
+    // every block_number call is a new block. the send with index mined gets a receipt
+    // once the chain reaches block mined_at.
+    struct FakeNode {
+        block: Cell<u64>,
+        pending: u64,
+        sent: RefCell<Vec<TxRequest>>,
+        mined: Option<(usize, u64)>,
+        fees_down: Cell<bool>,
+        // the next this many receipt calls fail
+        receipt_errors: Cell<u32>,
+        // block_number stays where it is
+        stalled: bool,
+    }
+
+    impl FakeNode {
+        fn new(mined: Option<(usize, u64)>) -> FakeNode {
+            FakeNode {
+                block: Cell::new(100),
+                pending: 5,
+                sent: RefCell::new(vec![]),
+                mined,
+                fees_down: Cell::new(false),
+                receipt_errors: Cell::new(0),
+                stalled: false,
+            }
+        }
+    }
+
+    impl Node for FakeNode {
+        fn from(&self) -> Address {
+            ME
+        }
+
+        fn block_number(&self) -> Result<u64, String> {
+            if !self.stalled {
+                self.block.set(self.block.get() + 1);
+            }
+            Ok(self.block.get())
+        }
+
+        fn pending_nonce(&self, _address: Address) -> Result<u64, String> {
+            Ok(self.pending)
+        }
+
+        fn fees(&self) -> Result<(u128, u128), String> {
+            if self.fees_down.get() {
+                return Err("no fees".to_owned());
+            }
+            Ok((100, 10))
+        }
+
+        fn send(&self, tx: &TxRequest) -> Result<String, String> {
+            self.sent.borrow_mut().push(tx.clone());
+            Ok(format!("0x{}", self.sent.borrow().len() - 1))
+        }
+
+        fn receipt(&self, tx_hash: &str) -> Result<Option<Receipt>, String> {
+            if self.receipt_errors.get() > 0 {
+                self.receipt_errors.set(self.receipt_errors.get() - 1);
+                return Err("timeout".to_owned());
+            }
+            Ok(self.mined.and_then(|(index, mined_at)| {
+                (tx_hash == format!("0x{}", index) && self.block.get() >= mined_at).then(|| {
+                    Receipt {
+                        tx_hash: tx_hash.to_owned(),
+                        status: true,
+                        block_number: Some(mined_at),
+                        gas_used: 21000,
+                        effective_gas_price: 1,
+                        cancelled: false,
+                        replacements: 0,
+                    }
+                })
+            }))
+        }
+    }
+
+    fn replace() -> Replace {
+        Replace {
+            after_blocks: 3,
+            max_replacements: 2,
+            poll: Duration::ZERO,
+        }
+    }
+
+    fn swab(node: &FakeNode, nonces: &NonceManager, wanted: bool) -> Result<Receipt, SendError> {
+        send(
+            node,
+            nonces,
+            &replace(),
+            UNISWAB,
+            Bytes::from(vec![1, 2, 3]),
+            300000,
+            &|| wanted,
+        )
+    }
+
+    #[test]
+    fn test_reserve() {
+        let nonces = NonceManager::default();
+        assert_eq!(nonces.reserve(ME, || Ok(5)), Ok(5));
+        // local from now on, the node has not seen the first one yet
+        assert_eq!(nonces.reserve(ME, || Ok(5)), Ok(6));
+        assert_eq!(nonces.reserve(UNISWAB, || Ok(0)), Ok(0));
+        nonces.forget(ME);
+        assert_eq!(nonces.reserve(ME, || Ok(7)), Ok(7));
+        assert!(nonces.reserve(UNISWAB, || Err("unused".to_owned())).is_ok());
+    }
+
+    #[test]
+    fn test_mined() {
+        let node = FakeNode::new(Some((0, 101)));
+        let nonces = NonceManager::default();
+        let receipt = swab(&node, &nonces, true).unwrap();
+        assert_eq!((receipt.tx_hash.as_str(), receipt.replacements), ("0x0", 0));
+        assert!(!receipt.cancelled);
+        assert_eq!(node.sent.borrow()[0].nonce, 5);
+        assert_eq!(nonces.reserve(ME, || Ok(5)), Ok(6));
+    }
+
+    #[test]
+    fn test_no_fees_keeps_the_nonce() {
+        let node = FakeNode::new(Some((0, 101)));
+        let nonces = NonceManager::default();
+        node.fees_down.set(true);
+        assert_eq!(
+            swab(&node, &nonces, true).unwrap_err(),
+            SendError::from("no fees".to_owned())
+        );
+        assert!(node.sent.borrow().is_empty());
+        node.fees_down.set(false);
+        swab(&node, &nonces, true).unwrap();
+        assert_eq!(node.sent.borrow()[0].nonce, 5);
+    }
+
+    #[test]
+    fn test_sped_up() {
+        let node = FakeNode::new(Some((1, 106)));
+        let receipt = swab(&node, &NonceManager::default(), true).unwrap();
+        assert_eq!((receipt.tx_hash.as_str(), receipt.replacements), ("0x1", 1));
+        assert!(!receipt.cancelled);
+        let sent = node.sent.borrow();
+        assert_eq!(sent[1].nonce, sent[0].nonce);
+        assert_eq!(sent[1].data, sent[0].data);
+        assert_eq!(
+            (sent[1].max_fee_per_gas, sent[1].max_priority_fee_per_gas),
+            (116, 12)
+        );
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let node = FakeNode::new(Some((1, 106)));
+        let receipt = swab(&node, &NonceManager::default(), false).unwrap();
+        assert!(receipt.cancelled);
+        let sent = node.sent.borrow();
+        assert_eq!(
+            sent[1],
+            TxRequest {
+                to: ME,
+                data: Bytes::new(),
+                value: U256::ZERO,
+                nonce: 5,
+                gas: CANCEL_GAS,
+                max_fee_per_gas: 116,
+                max_priority_fee_per_gas: 12,
+            }
+        );
+    }
+
+    #[test]
+    fn test_gives_up() {
+        let node = FakeNode::new(None);
+        let nonces = NonceManager::default();
+        let err = swab(&node, &nonces, true).unwrap_err();
+        assert_eq!(err.message, "nonce 5 not mined after 2 replacements");
+        assert_eq!(err.tx_hash.as_deref(), Some("0x2"));
+        let sent = node.sent.borrow();
+        assert_eq!(sent.len(), 3);
+        assert!(sent[2].max_fee_per_gas > sent[1].max_fee_per_gas);
+        // the node is asked again
+        assert_eq!(nonces.reserve(ME, || Ok(6)), Ok(6));
+    }
+
+    #[test]
+    fn test_rpc_errors_after_sending() {
+        // a few are retried
+        let node = FakeNode::new(Some((0, 101)));
+        node.receipt_errors.set(MAX_RPC_ERRORS - 1);
+        let receipt = swab(&node, &NonceManager::default(), true).unwrap();
+        assert_eq!(receipt.tx_hash, "0x0");
+
+        // too many in a row give up, with the hash of what is out
+        let node = FakeNode::new(Some((0, 101)));
+        let nonces = NonceManager::default();
+        node.receipt_errors.set(MAX_RPC_ERRORS);
+        let err = swab(&node, &nonces, true).unwrap_err();
+        assert_eq!(err.message, "nonce 5: 10 rpc errors in a row");
+        assert_eq!(err.tx_hash.as_deref(), Some("0x0"));
+        assert_eq!(nonces.reserve(ME, || Ok(6)), Ok(6));
+    }
+
+    #[test]
+    fn test_stalled_node() {
+        let node = FakeNode {
+            stalled: true,
+            ..FakeNode::new(None)
+        };
+        let err = swab(&node, &NonceManager::default(), true).unwrap_err();
+        assert_eq!(err.message, "nonce 5: no new block after 120 polls");
+        assert_eq!(err.tx_hash.as_deref(), Some("0x0"));
+        assert_eq!(node.sent.borrow().len(), 1);
+    }
+}
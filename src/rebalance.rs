@@ -0,0 +1,373 @@
+use alloy::primitives::{Address, U256, utils::parse_units};
+use tracing::{info, warn};
+
+use crate::chain::{Chain, Receipt};
+use crate::config::Config;
+use crate::unipool;
+use crate::{Coin, Pair, Pool, metrics};
+
+// swabs pay out in coin1 and gas goes out in eth, so the wallet drifts. when coin1 or
+// coin0 leaves its band the other one is swapped for it, back to the middle of the band,
+// through the deepest pool of the pair with uniswab.swap. coin1 goes first as swabs are
+// sized by it, and eth for gas_reserve_swabs swabs is never spent on rebalancing.
+
+// raw token units
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Band {
+    pub min: U256,
+    pub max: U256,
+}
+
+impl Band {
+    fn target(&self) -> U256 {
+        (self.min + self.max) / U256::from(2)
+    }
+}
+
+// one coin of the pool: its reserve, our balance and its band
+struct Leg {
+    reserve: U256,
+    balance: U256,
+    band: Option<Band>,
+}
+
+pub fn enabled(config: &Config) -> bool {
+    config.rebalance_base_min.is_some() || config.rebalance_coin_min.is_some()
+}
+
+// swap back into the bands if a balance is out. pairs are the ones scan considered, coin1
+// being preferred_coin_token.
+pub fn run(
+    config: &Config,
+    chain: &impl Chain,
+    pairs: &[Pair],
+    owner: Address,
+) -> Result<Option<Receipt>, String> {
+    let Some(pool) = deepest(pairs, config.preferred_coin_token) else {
+        return Ok(None);
+    };
+    let (r0, r1, _) = chain.reserves(pool.contract_address)?;
+    let (x, y) = pool.ordered(r0, r1);
+    let bands = (
+        band(
+            config.rebalance_base_min,
+            config.rebalance_base_max,
+            &pool.coin0,
+        )?,
+        band(
+            config.rebalance_coin_min,
+            config.rebalance_coin_max,
+            &pool.coin1,
+        )?,
+    );
+    let balances = (
+        chain.token_balance(pool.coin0.contract_address, owner)?,
+        chain.token_balance(pool.coin1.contract_address, owner)?,
+    );
+    let Some((zero_for_one, amount_in)) = plan((x, y), balances, bands, pool.fee_bps) else {
+        return Ok(None);
+    };
+    let (reserve_in, reserve_out, coin_in, coin_out) = if zero_for_one {
+        (x, y, &pool.coin0, &pool.coin1)
+    } else {
+        (y, x, &pool.coin1, &pool.coin0)
+    };
+    let quote = unipool::get_y_out_with_fee(amount_in, reserve_in, reserve_out, pool.fee_bps)?;
+    let amount_out_min = quote * U256::from(10000 - config.rebalance_max_slippage_bps.min(10000))
+        / U256::from(10000);
+
+    let gas_cost = U256::from(chain.gas_price()?) * U256::from(config.tx_gas);
+    let gas_reserve = gas_cost * U256::from(config.gas_reserve_swabs);
+    let eth = chain.eth_balance(owner)?;
+    if eth < gas_reserve + gas_cost {
+        warn!(%eth, %gas_reserve, "not rebalancing, eth is down to the gas reserve");
+        metrics::REBALANCES
+            .with_label_values(&[&config.chain_id.to_string(), "skipped"])
+            .inc();
+        return Ok(None);
+    }
+    info!(
+        pool = %pool.contract_address,
+        sell = %coin_in.symbol,
+        buy = %coin_out.symbol,
+        amount_in = %amount_in,
+        quote = %quote,
+        amount_out_min = %amount_out_min,
+        "rebalance"
+    );
+    let receipt = chain.swap(
+        amount_in,
+        amount_out_min,
+        pool.contract_address,
+        // the pool's token0 is coin1 when flipped
+        zero_for_one != pool.flipped,
+        pool.fee_bps,
+    )?;
+    info!(
+        tx = %receipt.tx_hash,
+        status = receipt.status,
+        gas_used = receipt.gas_used,
+        "rebalance receipt"
+    );
+    let status = if receipt.status {
+        "succeeded"
+    } else {
+        "reverted"
+    };
+    metrics::REBALANCES
+        .with_label_values(&[&config.chain_id.to_string(), status])
+        .inc();
+    Ok(Some(receipt))
+}
+
+// (whether coin0 is sold, amount in) to bring the balances back in their bands.
+// reserves and balances are (coin0, coin1), the pool charges fee_bps.
+pub fn plan(
+    reserves: (U256, U256),
+    balances: (U256, U256),
+    bands: (Option<Band>, Option<Band>),
+    fee_bps: u8,
+) -> Option<(bool, U256)> {
+    let coin0 = Leg {
+        reserve: reserves.0,
+        balance: balances.0,
+        band: bands.0,
+    };
+    let coin1 = Leg {
+        reserve: reserves.1,
+        balance: balances.1,
+        band: bands.1,
+    };
+    if let Some(band) = coin1.band {
+        if coin1.balance < band.min {
+            return buy(band.target() - coin1.balance, &coin0, &coin1, fee_bps)
+                .map(|amount| (true, amount));
+        }
+        if coin1.balance > band.max {
+            return sell(coin1.balance - band.target(), &coin1, &coin0, fee_bps)
+                .map(|amount| (false, amount));
+        }
+    }
+    if let Some(band) = coin0.band {
+        if coin0.balance < band.min {
+            return buy(band.target() - coin0.balance, &coin1, &coin0, fee_bps)
+                .map(|amount| (false, amount));
+        }
+        if coin0.balance > band.max {
+            return sell(coin0.balance - band.target(), &coin0, &coin1, fee_bps)
+                .map(|amount| (true, amount));
+        }
+    }
+    None
+}
+
+// what to sell of sold to get want of bought, no more than sold can spare above its band
+fn buy(want: U256, sold: &Leg, bought: &Leg, fee_bps: u8) -> Option<U256> {
+    let spare = sold
+        .balance
+        .saturating_sub(sold.band.map_or(U256::ZERO, |band| band.min));
+    // all we can spare when the pool does not hold want
+    let amount = unipool::get_x_in_with_fee(want, sold.reserve, bought.reserve, fee_bps)
+        .map_or(spare, |amount| amount.min(spare));
+    (!amount.is_zero()).then_some(amount)
+}
+
+// what to sell of an excess of sold, no more than bought has room for below its band
+fn sell(excess: U256, sold: &Leg, bought: &Leg, fee_bps: u8) -> Option<U256> {
+    let amount = match bought.band {
+        Some(band) => {
+            let room = band.max.saturating_sub(bought.balance);
+            unipool::get_x_in_with_fee(room, sold.reserve, bought.reserve, fee_bps)
+                .map_or(excess, |amount| amount.min(excess))
+        }
+        None => excess,
+    };
+    (!amount.is_zero()).then_some(amount)
+}
+
+// the pool with the most coin1 among the pairs
+fn deepest(pairs: &[Pair], coin1: Address) -> Option<&Pool> {
+    pairs
+        .iter()
+        .filter(|pair| pair.pool0.pool.coin1.contract_address == coin1)
+        .flat_map(|pair| [&pair.pool0, &pair.pool1])
+        .max_by_key(|snapshot| snapshot.reserve.y)
+        .map(|snapshot| &snapshot.pool)
+}
+
+fn band(min: Option<f64>, max: Option<f64>, coin: &Coin) -> Result<Option<Band>, String> {
+    let units = |amount: f64| {
+        parse_units(&amount.to_string(), coin.decimals as u8)
+            .map(Into::into)
+            .map_err(|err| format!("{} {}: {}", coin.symbol, amount, err))
+    };
+    match (min, max) {
+        (Some(min), Some(max)) => Ok(Some(Band {
+            min: units(min)?,
+            max: units(max)?,
+        })),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::FakeChain;
+    use crate::fixture::{COIN0, COIN1, POOL1, PairBuilder};
+
+    const ME: Address = Address::repeat_byte(0xee);
+    const FEE: u8 = unipool::POOL_FEE_BASIS_POINTS;
+
+    fn band(min: u64, max: u64) -> Option<Band> {
+        Some(Band {
+            min: U256::from(min),
+            max: U256::from(max),
+        })
+    }
+
+    fn amounts(coin0: u64, coin1: u64) -> (U256, U256) {
+        (U256::from(coin0), U256::from(coin1))
+    }
+
+    #[test]
+    fn test_plan() {
+        let reserves = amounts(1000000, 1000000);
+        // in band
+        assert_eq!(
+            plan(reserves, amounts(500, 500), (None, band(400, 600)), FEE),
+            None
+        );
+        // coin1 short, coin0 sold to get back to 500
+        let (zero_for_one, amount) =
+            plan(reserves, amounts(5000, 100), (None, band(400, 600)), FEE).unwrap();
+        assert!(zero_for_one);
+        let out = unipool::get_y_out(amount, reserves.0, reserves.1).unwrap();
+        assert!(out >= U256::from(400) && out < U256::from(402), "{}", out);
+        // only what coin0 can spare above its own band
+        assert_eq!(
+            plan(
+                reserves,
+                amounts(5000, 100),
+                (band(4900, 6000), band(400, 600)),
+                FEE
+            ),
+            Some((true, U256::from(100)))
+        );
+        assert_eq!(
+            plan(
+                reserves,
+                amounts(4900, 100),
+                (band(4900, 6000), band(400, 600)),
+                FEE
+            ),
+            None
+        );
+        // coin1 over, the excess goes back to coin0
+        assert_eq!(
+            plan(reserves, amounts(0, 900), (None, band(400, 600)), FEE),
+            Some((false, U256::from(400)))
+        );
+        // but not past coin0's band
+        let (zero_for_one, amount) = plan(
+            reserves,
+            amounts(5950, 900),
+            (band(4000, 6000), band(400, 600)),
+            FEE,
+        )
+        .unwrap();
+        assert!(!zero_for_one);
+        assert!(amount < U256::from(60), "{}", amount);
+        // coin0 is looked at once coin1 is in band
+        assert_eq!(
+            plan(reserves, amounts(7000, 500), (band(4000, 6000), None), FEE),
+            Some((true, U256::from(2000)))
+        );
+        let (zero_for_one, amount) = plan(
+            reserves,
+            amounts(7000, 500),
+            (band(4000, 6000), band(400, 600)),
+            FEE,
+        )
+        .unwrap();
+        assert!(zero_for_one);
+        assert!(amount < U256::from(110), "{}", amount);
+        let (zero_for_one, _) =
+            plan(reserves, amounts(1000, 5000), (band(4000, 6000), None), FEE).unwrap();
+        assert!(!zero_for_one);
+    }
+
+    fn config(extra: &str) -> Config {
+        serde_yaml::from_str(&format!(
+            "geth_url: http://localhost:8545
+pg_url: postgres://localhost/gofi
+eth_priv_key: 0000000000000000000000000000000000000000000000000000000000000001
+uniswab: 000000000000000000000000000000000000005b
+preferred_base_token: {COIN0}
+preferred_coin_token: {COIN1}
+minimum_out: 0
+tx_gas: 300000
+{extra}"
+        ))
+        .unwrap()
+    }
+
+    // pool1 has more coin1
+    fn fake_chain(eth: u64, coin0: u64, coin1: u64) -> FakeChain {
+        let mut chain = FakeChain::default();
+        let pair = PairBuilder::new().build();
+        for snapshot in [pair.pool0, pair.pool1] {
+            let reserve = snapshot.reserve;
+            chain.reserves.insert(
+                snapshot.pool.contract_address,
+                (reserve.x, reserve.y, reserve.block_timestamp),
+            );
+        }
+        chain.gas_price = 1;
+        chain.set_balance(Address::ZERO, ME, U256::from(eth));
+        chain.set_balance(COIN0, ME, U256::from(coin0));
+        chain.set_balance(COIN1, ME, U256::from(coin1));
+        chain
+    }
+
+    #[test]
+    fn test_run() {
+        // 0.000000000000001 is 1000 raw units of an 18 decimal coin
+        let config = config(
+            "rebalance_coin_min: 0.000000000000001
+rebalance_coin_max: 0.000000000000003
+rebalance_max_slippage_bps: 100",
+        );
+        let pairs = [PairBuilder::new().build()];
+        let chain = fake_chain(300000 * 12, 10000, 100);
+        let receipt = run(&config, &chain, &pairs, ME).unwrap();
+        assert!(receipt.unwrap().status);
+        let (amount_in, amount_out_min, pool, zero_for_one) = chain.swaps.borrow()[0];
+        assert_eq!((pool, zero_for_one), (POOL1, true));
+        let quote = unipool::get_y_out(amount_in, U256::from(220000), U256::from(320000)).unwrap();
+        assert!(quote >= U256::from(1900));
+        assert_eq!(amount_out_min, quote * U256::from(99) / U256::from(100));
+        // the same pools with coin0 as their token1, the node has their reserves the
+        // other way round
+        let flipped = [PairBuilder::new().flipped().build()];
+        let mut chain = fake_chain(300000 * 12, 10000, 100);
+        for reserves in chain.reserves.values_mut() {
+            (reserves.0, reserves.1) = (reserves.1, reserves.0);
+        }
+        run(&config, &chain, &flipped, ME).unwrap();
+        let (flipped_amount_in, _, pool, zero_for_one) = chain.swaps.borrow()[0];
+        assert_eq!(
+            (flipped_amount_in, pool, zero_for_one),
+            (amount_in, POOL1, false)
+        );
+
+        // in band, nothing to do
+        let chain = fake_chain(300000 * 12, 10000, 2000);
+        assert!(run(&config, &chain, &pairs, ME).unwrap().is_none());
+        // eth for 10 swabs plus this one is kept
+        let chain = fake_chain(300000 * 10, 10000, 100);
+        assert!(run(&config, &chain, &pairs, ME).unwrap().is_none());
+        assert!(chain.swaps.borrow().is_empty());
+        assert!(!enabled(&self::config("")));
+    }
+}
@@ -0,0 +1,359 @@
+use alloy::primitives::{Address, U256};
+
+use chrono::Utc;
+
+use crate::report;
+use crate::risk::{Halt, Stats};
+use crate::screen::{Screening, Verdict};
+use crate::{Execution, Match, address};
+
+// numeric amounts are stored as text like the reserves table. cast with ::numeric to do math in sql.
+// an opportunity's gas_estimate is the gas a swab is sent with, gas_cost_wei that at the cycle's
+// gas price.
+// every row is keyed by the chain_id of the chain it happened on, rows from before there
+// were chains are mainnet's.
+pub fn create_tables(db: &mut postgres::Client) -> Result<(), postgres::Error> {
+    db.batch_execute(
+        "CREATE TABLE IF NOT EXISTS opportunities (
+            id BIGSERIAL PRIMARY KEY,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            pool0 VARCHAR NOT NULL,
+            pool1 VARCHAR NOT NULL,
+            direction VARCHAR NOT NULL,
+            token_in VARCHAR NOT NULL,
+            ay_in VARCHAR NOT NULL,
+            ax_out VARCHAR NOT NULL,
+            ay_out VARCHAR NOT NULL,
+            profit VARCHAR NOT NULL,
+            gas_cost_wei VARCHAR NOT NULL,
+            gas_cost_coin1 VARCHAR NOT NULL,
+            p0_block_number INTEGER NOT NULL,
+            p1_block_number INTEGER NOT NULL
+         );
+         CREATE TABLE IF NOT EXISTS executions (
+            id BIGSERIAL PRIMARY KEY,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            opportunity_id BIGINT NOT NULL REFERENCES opportunities(id),
+            tx_hash VARCHAR NOT NULL,
+            status BOOLEAN NOT NULL,
+            block_number BIGINT,
+            gas_used BIGINT NOT NULL,
+            effective_gas_price VARCHAR NOT NULL,
+            eth_delta VARCHAR NOT NULL,
+            coin0_delta VARCHAR NOT NULL,
+            coin1_delta VARCHAR NOT NULL
+         );
+         ALTER TABLE opportunities ADD COLUMN IF NOT EXISTS gas_estimate BIGINT;
+         ALTER TABLE executions ADD COLUMN IF NOT EXISTS revert_reason VARCHAR;
+         ALTER TABLE executions ADD COLUMN IF NOT EXISTS failure VARCHAR;
+         CREATE TABLE IF NOT EXISTS token_screenings (
+            id BIGSERIAL PRIMARY KEY,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            pool VARCHAR NOT NULL,
+            token VARCHAR NOT NULL,
+            verdict VARCHAR NOT NULL,
+            buy_tax_bps INTEGER,
+            sell_tax_bps INTEGER,
+            reason VARCHAR
+         );
+         CREATE TABLE IF NOT EXISTS halts (
+            id BIGSERIAL PRIMARY KEY,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            reason VARCHAR NOT NULL,
+            reset_at TIMESTAMPTZ
+         );
+         ALTER TABLE opportunities ADD COLUMN IF NOT EXISTS chain_id BIGINT NOT NULL DEFAULT 1;
+         ALTER TABLE executions ADD COLUMN IF NOT EXISTS chain_id BIGINT NOT NULL DEFAULT 1;
+         ALTER TABLE token_screenings ADD COLUMN IF NOT EXISTS chain_id BIGINT NOT NULL DEFAULT 1;
+         ALTER TABLE halts ADD COLUMN IF NOT EXISTS chain_id BIGINT NOT NULL DEFAULT 1;",
+    )
+}
+
+pub fn insert_opportunity(
+    db: &mut postgres::Client,
+    chain_id: u64,
+    r#match: &Match,
+    gas_estimate: u64,
+    gas_cost_wei: u128,
+    gas_cost_coin1: U256,
+) -> Result<i64, postgres::Error> {
+    let sql = "INSERT INTO opportunities (pool0, pool1, direction, token_in, ay_in, ax_out, ay_out, profit,
+                 gas_estimate, gas_cost_wei, gas_cost_coin1, p0_block_number, p1_block_number, chain_id)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14) RETURNING id";
+    let row = db.query_one(
+        sql,
+        &[
+            &address::to_db(r#match.pair.pool0.pool.contract_address),
+            &address::to_db(r#match.pair.pool1.pool.contract_address),
+            &r#match.direction(),
+            &address::to_db(r#match.pair.pool0.pool.coin1.contract_address),
+            &r#match.pool0_ay_in.to_string(),
+            &r#match.pool0_ax_out.to_string(),
+            &r#match.pool1_ay_out.to_string(),
+            &r#match.profit().to_string(),
+            &(gas_estimate as i64),
+            &gas_cost_wei.to_string(),
+            &gas_cost_coin1.to_string(),
+            &(r#match.pair.pool0.reserve.block_number as i32),
+            &(r#match.pair.pool1.reserve.block_number as i32),
+            &(chain_id as i64),
+        ],
+    )?;
+    Ok(row.get::<_, i64>("id"))
+}
+
+pub fn insert_execution(
+    db: &mut postgres::Client,
+    chain_id: u64,
+    opportunity_id: i64,
+    execution: &Execution,
+) -> Result<i64, postgres::Error> {
+    let sql = "INSERT INTO executions (opportunity_id, tx_hash, status, block_number, gas_used,
+                 effective_gas_price, eth_delta, coin0_delta, coin1_delta, revert_reason, failure, chain_id)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING id";
+    let row = db.query_one(
+        sql,
+        &[
+            &opportunity_id,
+            &execution.tx_hash,
+            &execution.status,
+            &execution.block_number.map(|number| number as i64),
+            &(execution.gas_used as i64),
+            &execution.effective_gas_price.to_string(),
+            &execution.eth_delta.to_string(),
+            &execution.coin0_delta.to_string(),
+            &execution.coin1_delta.to_string(),
+            &execution.revert_reason,
+            &execution.failure.map(|failure| failure.as_str()),
+            &(chain_id as i64),
+        ],
+    )?;
+    Ok(row.get::<_, i64>("id"))
+}
+
+pub fn insert_screening(
+    db: &mut postgres::Client,
+    chain_id: u64,
+    screening: &Screening,
+) -> Result<i64, postgres::Error> {
+    let sql =
+        "INSERT INTO token_screenings (pool, token, verdict, buy_tax_bps, sell_tax_bps, reason, chain_id)
+               VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id";
+    let row = db.query_one(
+        sql,
+        &[
+            &address::to_db(screening.pool),
+            &address::to_db(screening.token),
+            &screening.verdict.as_str(),
+            &screening.buy_tax_bps.map(|bps| bps as i32),
+            &screening.sell_tax_bps.map(|bps| bps as i32),
+            &screening.reason,
+            &(chain_id as i64),
+        ],
+    )?;
+    Ok(row.get::<_, i64>("id"))
+}
+
+// the latest verdict of every pool screened in the last max_age_hours
+pub fn screenings(
+    db: &mut postgres::Client,
+    chain_id: u64,
+    max_age_hours: u64,
+) -> Result<Vec<Screening>, postgres::Error> {
+    let sql = "SELECT DISTINCT ON (pool) pool, token, verdict, buy_tax_bps, sell_tax_bps, reason
+               FROM token_screenings
+               WHERE chain_id = $1 AND created_at > now() - make_interval(hours => $2)
+               ORDER BY pool, created_at DESC, id DESC";
+    let rows = db.query(sql, &[&(chain_id as i64), &(max_age_hours as i32)])?;
+    Ok(rows
+        .iter()
+        .map(|row| Screening {
+            pool: address::from_db(row.get("pool")),
+            token: address::from_db(row.get("token")),
+            verdict: Verdict::from_db(row.get("verdict")),
+            buy_tax_bps: row
+                .get::<_, Option<i32>>("buy_tax_bps")
+                .map(|bps| bps as u32),
+            sell_tax_bps: row
+                .get::<_, Option<i32>>("sell_tax_bps")
+                .map(|bps| bps as u32),
+            reason: row.get("reason"),
+        })
+        .collect())
+}
+
+// executions before the last gofi resume are not counted, pnl_today is of quote's swabs
+pub fn risk_stats(
+    db: &mut postgres::Client,
+    chain_id: u64,
+    quote: Address,
+) -> Result<Stats, postgres::Error> {
+    let sql = "WITH resumed AS (
+                 SELECT max(reset_at) AS at FROM halts WHERE chain_id = $1
+               ), recent AS (
+                 SELECT * FROM executions, resumed
+                 WHERE chain_id = $1 AND (at IS NULL OR created_at > at)
+               )
+               SELECT
+                 (SELECT count(*) FROM recent
+                  WHERE created_at > now() - interval '1 hour') AS trades_last_hour,
+                 (SELECT count(*) FROM recent
+                  WHERE NOT status AND failure IS DISTINCT FROM 'cancelled'
+                    AND id > coalesce((SELECT max(id) FROM recent WHERE status), 0))
+                  AS consecutive_reverts,
+                 (SELECT coalesce(to_char(at, 'YYYY-MM-DD HH24:MI:SS'), '') FROM resumed)
+                  AS resumed_at";
+    let row = db.query_one(sql, &[&(chain_id as i64)])?;
+    // empty, sorting before any time, when never resumed
+    let resumed_at = row.get::<_, String>("resumed_at");
+    let today = Utc::now().date_naive();
+    let pnl_today = report::trades(db, chain_id, today, today)?
+        .iter()
+        .filter(|trade| trade.quote() == quote && trade.executed_at() > resumed_at.as_str())
+        .map(report::TradePnl::net)
+        .sum();
+    Ok(Stats {
+        trades_last_hour: row.get::<_, i64>("trades_last_hour") as u32,
+        consecutive_reverts: row.get::<_, i64>("consecutive_reverts") as u32,
+        pnl_today,
+    })
+}
+
+// the halt in force, if any
+pub fn halt(db: &mut postgres::Client, chain_id: u64) -> Result<Option<Halt>, postgres::Error> {
+    let sql = "SELECT to_char(created_at, 'YYYY-MM-DD HH24:MI:SS') AS created_at, reason
+               FROM halts WHERE chain_id = $1 AND reset_at IS NULL ORDER BY id DESC LIMIT 1";
+    Ok(db.query_opt(sql, &[&(chain_id as i64)])?.map(|row| Halt {
+        created_at: row.get("created_at"),
+        reason: row.get("reason"),
+    }))
+}
+
+pub fn insert_halt(
+    db: &mut postgres::Client,
+    chain_id: u64,
+    reason: &str,
+) -> Result<i64, postgres::Error> {
+    let row = db.query_one(
+        "INSERT INTO halts (reason, chain_id) VALUES ($1, $2) RETURNING id",
+        &[&reason, &(chain_id as i64)],
+    )?;
+    Ok(row.get::<_, i64>("id"))
+}
+
+pub fn reset_halts(db: &mut postgres::Client, chain_id: u64) -> Result<u64, postgres::Error> {
+    db.execute(
+        "UPDATE halts SET reset_at = now() WHERE chain_id = $1 AND reset_at IS NULL",
+        &[&(chain_id as i64)],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, PairBuilder, Schema};
+    use alloy::primitives::I256;
+
+    fn execution(status: bool, coin1_delta: i64) -> Execution {
+        Execution {
+            tx_hash: "0xabc".to_owned(),
+            status,
+            block_number: Some(7),
+            gas_used: 100000,
+            effective_gas_price: 2,
+            eth_delta: I256::try_from(-200000).unwrap(),
+            coin0_delta: I256::ZERO,
+            coin1_delta: I256::try_from(coin1_delta).unwrap(),
+            revert_reason: (!status).then(|| "K".to_owned()),
+            failure: None,
+        }
+    }
+
+    #[test]
+    #[ignore = "needs GOFI_E2E_PG_URL"]
+    fn test_opportunity_and_execution_round_trip() {
+        let mut schema = Schema::create("record_trades");
+        let db = &mut schema.db;
+        db.execute(
+            "INSERT INTO coins (contract_address, symbol, decimals) VALUES ($1, 'USDONC', 18)",
+            &[&address::to_db(fixture::COIN1)],
+        )
+        .unwrap();
+        let r#match = PairBuilder::new().simulate();
+
+        let id = insert_opportunity(db, 5, &r#match, 300000, 600000, U256::from(3)).unwrap();
+        let row = db
+            .query_one("SELECT * FROM opportunities WHERE id = $1", &[&id])
+            .unwrap();
+        assert_eq!(row.get::<_, &str>("pool0"), address::to_db(fixture::POOL0));
+        assert_eq!(
+            row.get::<_, &str>("token_in"),
+            address::to_db(fixture::COIN1)
+        );
+        assert_eq!(row.get::<_, &str>("ay_in"), r#match.pool0_ay_in.to_string());
+        assert_eq!(row.get::<_, &str>("profit"), r#match.profit().to_string());
+        assert_eq!(row.get::<_, Option<i64>>("gas_estimate"), Some(300000));
+        assert_eq!(row.get::<_, &str>("gas_cost_wei"), "600000");
+        assert_eq!(row.get::<_, &str>("gas_cost_coin1"), "3");
+        assert_eq!(row.get::<_, i64>("chain_id"), 5);
+
+        insert_execution(db, 5, id, &execution(true, 1000)).unwrap();
+        let today = Utc::now().date_naive();
+        let trades = report::trades(db, 5, today, today).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quote(), fixture::COIN1);
+        assert_eq!(trades[0].outcome(), "ok");
+        // 100000 gas at 2 wei is 200000 wei, a third of the 3 coin1 600000 wei cost
+        assert_eq!(trades[0].net(), 999);
+        assert!(report::trades(db, 1, today, today).unwrap().is_empty());
+
+        insert_execution(db, 5, id, &execution(false, 0)).unwrap();
+        let stats = risk_stats(db, 5, fixture::COIN1).unwrap();
+        assert_eq!(stats.trades_last_hour, 2);
+        assert_eq!(stats.consecutive_reverts, 1);
+        assert_eq!(stats.pnl_today, 998);
+        assert_eq!(risk_stats(db, 5, fixture::COIN0).unwrap().pnl_today, 0);
+    }
+
+    #[test]
+    #[ignore = "needs GOFI_E2E_PG_URL"]
+    fn test_screening_round_trip() {
+        let mut schema = Schema::create("record_screenings");
+        let db = &mut schema.db;
+        let screening = |verdict, reason: Option<&str>| Screening {
+            pool: fixture::POOL0,
+            token: fixture::COIN0,
+            verdict,
+            buy_tax_bps: Some(0),
+            sell_tax_bps: Some(500),
+            reason: reason.map(str::to_owned),
+        };
+        insert_screening(db, 5, &screening(Verdict::Ok, None)).unwrap();
+        insert_screening(db, 5, &screening(Verdict::Taxed, Some("sell tax"))).unwrap();
+
+        let screenings = screenings(db, 5, 24).unwrap();
+        assert_eq!(screenings.len(), 1);
+        assert_eq!(screenings[0].pool, fixture::POOL0);
+        assert_eq!(screenings[0].token, fixture::COIN0);
+        assert_eq!(screenings[0].verdict, Verdict::Taxed);
+        assert_eq!(screenings[0].sell_tax_bps, Some(500));
+        assert_eq!(screenings[0].reason.as_deref(), Some("sell tax"));
+        assert!(super::screenings(db, 1, 24).unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs GOFI_E2E_PG_URL"]
+    fn test_halt_round_trip() {
+        let mut schema = Schema::create("record_halts");
+        let db = &mut schema.db;
+        assert!(halt(db, 5).unwrap().is_none());
+
+        insert_halt(db, 5, "daily loss").unwrap();
+        assert_eq!(halt(db, 5).unwrap().unwrap().reason, "daily loss");
+        assert!(halt(db, 1).unwrap().is_none());
+
+        assert_eq!(reset_halts(db, 5).unwrap(), 1);
+        assert!(halt(db, 5).unwrap().is_none());
+        assert_eq!(reset_halts(db, 5).unwrap(), 0);
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::BTreeMap;
+
+use alloy::primitives::{Address, I256, utils::format_units};
+use chrono::{DateTime, NaiveDate, Utc};
+use tracing::warn;
+
+use crate::{PoolSnapshot, address, config, decimal::Decimal};
+
+#[derive(clap::Args)]
+pub struct ReportArgs {
+    /// first day to include, YYYY-MM-DD
+    #[arg(long)]
+    from: Option<NaiveDate>,
+    /// last day to include, YYYY-MM-DD. defaults to today
+    #[arg(long)]
+    to: Option<NaiveDate>,
+    /// one row per trade, or totals per pool pair, per day or per failure cause
+    #[arg(long, value_enum, default_value_t = GroupBy::Trade)]
+    by: GroupBy,
+    /// print csv instead of a table
+    #[arg(long)]
+    csv: bool,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum GroupBy {
+    Trade,
+    Pair,
+    Day,
+    Failure,
+}
+
+// realized pnl of one swab, amounts in raw coin1 (quote) units
+pub struct TradePnl {
+    executed_at: String,
+    day: String,
+    tx_hash: String,
+    status: bool,
+    // revert::Failure::as_str of a failed one
+    failure: Option<String>,
+    pool0: String,
+    pool1: String,
+    quote: Address,
+    symbol: String,
+    decimals: i32,
+    gross: i128,
+    gas: i128,
+    // eth (coin0) -> coin1 rate at trade time, from the recorded gas estimate
+    gas_cost_wei: u128,
+    gas_cost_coin1: u128,
+}
+
+impl TradePnl {
+    // ok, or why it failed
+    pub fn outcome(&self) -> String {
+        match (&self.failure, self.status) {
+            (Some(failure), _) => failure.clone(),
+            (None, true) => "ok".to_owned(),
+            (None, false) => "reverted".to_owned(),
+        }
+    }
+
+    // YYYY-MM-DD HH24:MI:SS
+    pub fn executed_at(&self) -> &str {
+        &self.executed_at
+    }
+
+    pub fn quote(&self) -> Address {
+        self.quote
+    }
+
+    pub fn net(&self) -> i128 {
+        self.gross - self.gas
+    }
+
+    pub fn usd(&self, amount: i128, usd_per_eth: f64) -> f64 {
+        if self.gas_cost_coin1 == 0 {
+            return 0.0;
+        }
+        let wei = amount as f64 * self.gas_cost_wei as f64 / self.gas_cost_coin1 as f64;
+        wei / 10_f64.powi(18) * usd_per_eth
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    trades: u32,
+    reverted: u32,
+    gross: i128,
+    gas: i128,
+    net_usd: f64,
+}
+
+pub fn run(db: &mut postgres::Client, args: &ReportArgs) -> Result<(), postgres::Error> {
+    let config = config::CONFIG.get().unwrap();
+    let from = args.from.unwrap_or(DateTime::UNIX_EPOCH.date_naive());
+    let to = args.to.unwrap_or(Utc::now().date_naive());
+    let trades = trades(db, config.chain_id, from, to)?;
+    let usd_per_eth = match config.usd_reference_pool {
+        Some(pool_address) => usd_per_eth(db, pool_address, config.preferred_base_token)?,
+        None => None,
+    };
+
+    let mut headers = vec!["trades", "reverted", "gross", "gas", "net", "symbol"];
+    let mut rows = vec![];
+    match args.by {
+        GroupBy::Trade => {
+            headers = vec![
+                "executed_at",
+                "tx_hash",
+                "pool0",
+                "pool1",
+                "status",
+                "gross",
+                "gas",
+                "net",
+                "symbol",
+            ];
+            for trade in trades.iter() {
+                let mut row = vec![
+                    trade.executed_at.clone(),
+                    trade.tx_hash.clone(),
+                    trade.pool0.clone(),
+                    trade.pool1.clone(),
+                    trade.outcome(),
+                    format_amount(trade.gross, trade.decimals),
+                    format_amount(trade.gas, trade.decimals),
+                    format_amount(trade.net(), trade.decimals),
+                    trade.symbol.clone(),
+                ];
+                if let Some(usd_per_eth) = usd_per_eth {
+                    row.push(format!("{:.2}", trade.usd(trade.net(), usd_per_eth)));
+                }
+                rows.push(row);
+            }
+        }
+        GroupBy::Pair | GroupBy::Day | GroupBy::Failure => {
+            headers.insert(
+                0,
+                match args.by {
+                    GroupBy::Pair => "pair",
+                    GroupBy::Day => "day",
+                    _ => "outcome",
+                },
+            );
+            let mut groups: BTreeMap<(String, String, i32), Totals> = BTreeMap::new();
+            for trade in trades.iter() {
+                let key = match args.by {
+                    GroupBy::Pair => format!("{}:{}", trade.pool0, trade.pool1),
+                    GroupBy::Day => trade.day.clone(),
+                    _ => trade.outcome(),
+                };
+                let totals = groups
+                    .entry((key, trade.symbol.clone(), trade.decimals))
+                    .or_default();
+                totals.trades += 1;
+                totals.reverted += if trade.status { 0 } else { 1 };
+                totals.gross += trade.gross;
+                totals.gas += trade.gas;
+                if let Some(usd_per_eth) = usd_per_eth {
+                    totals.net_usd += trade.usd(trade.net(), usd_per_eth);
+                }
+            }
+            for ((key, symbol, decimals), totals) in groups {
+                let mut row = vec![
+                    key,
+                    totals.trades.to_string(),
+                    totals.reverted.to_string(),
+                    format_amount(totals.gross, decimals),
+                    format_amount(totals.gas, decimals),
+                    format_amount(totals.gross - totals.gas, decimals),
+                    symbol,
+                ];
+                if usd_per_eth.is_some() {
+                    row.push(format!("{:.2}", totals.net_usd));
+                }
+                rows.push(row);
+            }
+        }
+    }
+    if usd_per_eth.is_some() {
+        headers.push("net_usd");
+    }
+
+    if args.csv {
+        println!("{}", headers.join(","));
+        for row in rows {
+            println!("{}", row.join(","));
+        }
+    } else {
+        print_table(&headers, &rows);
+        println!("{} trades from {} to {}", trades.len(), from, to);
+    }
+    Ok(())
+}
+
+pub fn trades(
+    db: &mut postgres::Client,
+    chain_id: u64,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<TradePnl>, postgres::Error> {
+    let sql = "SELECT to_char(e.created_at, 'YYYY-MM-DD HH24:MI:SS') AS executed_at,
+                      e.created_at::date::text AS day,
+                      e.tx_hash, e.status, e.failure, e.gas_used, e.effective_gas_price, e.coin0_delta, e.coin1_delta,
+                      o.pool0, o.pool1, o.token_in, o.gas_cost_wei, o.gas_cost_coin1,
+                      c.symbol, c.decimals
+               FROM executions AS e
+               JOIN opportunities AS o ON o.id = e.opportunity_id
+               JOIN coins AS c ON lower(right(c.contract_address, 40)) = o.token_in
+               WHERE e.chain_id = $1
+                 AND e.created_at >= $2::text::date AND e.created_at < $3::text::date + 1
+               ORDER BY e.created_at";
+    let rows = db.query(
+        sql,
+        &[&(chain_id as i64), &from.to_string(), &to.to_string()],
+    )?;
+    Ok(rows.iter().map(trade_pnl_from_row).collect())
+}
+
+fn trade_pnl_from_row(row: &postgres::Row) -> TradePnl {
+    let digits = |name: &str| row.get::<_, &str>(name).parse::<i128>().unwrap();
+    let gas_cost_wei = digits("gas_cost_wei") as u128;
+    let gas_cost_coin1 = digits("gas_cost_coin1") as u128;
+    let gas_wei = row.get::<_, i64>("gas_used") as i128 * digits("effective_gas_price");
+    let gross =
+        digits("coin1_delta") + wei_to_coin1(digits("coin0_delta"), gas_cost_wei, gas_cost_coin1);
+    TradePnl {
+        executed_at: row.get("executed_at"),
+        day: row.get("day"),
+        tx_hash: row.get("tx_hash"),
+        status: row.get("status"),
+        failure: row.get("failure"),
+        pool0: row.get("pool0"),
+        pool1: row.get("pool1"),
+        quote: address::from_db(row.get("token_in")),
+        symbol: row.get("symbol"),
+        decimals: row.get("decimals"),
+        gross,
+        gas: wei_to_coin1(gas_wei, gas_cost_wei, gas_cost_coin1),
+        gas_cost_wei,
+        gas_cost_coin1,
+    }
+}
+
+// gas_cost_coin1 / gas_cost_wei is the coin0 price in coin1 that the opportunity was sized with
+pub fn wei_to_coin1(wei: i128, gas_cost_wei: u128, gas_cost_coin1: u128) -> i128 {
+    if gas_cost_wei == 0 {
+        return 0;
+    }
+    let coin1 = I256::try_from(wei).unwrap() * I256::try_from(gas_cost_coin1).unwrap()
+        / I256::try_from(gas_cost_wei).unwrap();
+    i128::try_from(coin1).unwrap()
+}
+
+// price of the base token (eth) in usd, from the latest reserves of a base/stablecoin pool.
+// None when the pool is empty or not indexed, the report then has no usd column.
+fn usd_per_eth(
+    db: &mut postgres::Client,
+    pool_address: Address,
+    eth_token: Address,
+) -> Result<Option<f64>, postgres::Error> {
+    let Some(pool) = crate::pool(db, pool_address)? else {
+        warn!(pool = %pool_address, "usd_reference_pool not indexed");
+        return Ok(None);
+    };
+    let Some(reserve) = crate::reserves_latest(db, pool_address)? else {
+        warn!(pool = %pool_address, "usd_reference_pool has no reserves yet");
+        return Ok(None);
+    };
+    let eth_is_coin0 = pool.coin0.contract_address == eth_token;
+    let Some(price) = PoolSnapshot { pool, reserve }.price() else {
+        return Ok(None);
+    };
+    let price = if eth_is_coin0 {
+        Some(price)
+    } else {
+        Decimal::ONE.checked_div(price)
+    };
+    Ok(price.map(|price| price.to_f64()))
+}
+
+fn format_amount(raw: i128, decimals: i32) -> String {
+    format_units(I256::try_from(raw).unwrap(), decimals as u8).unwrap()
+}
+
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths = headers.iter().map(|h| h.len()).collect::<Vec<usize>>();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_owned()
+    };
+    println!("{}", line(headers.to_vec()));
+    for row in rows {
+        println!("{}", line(row.iter().map(|cell| cell.as_str()).collect()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{COIN0, COIN1, POOL0, Schema};
+
+    #[test]
+    fn test_wei_to_coin1() {
+        // 0.001 eth of gas valued at 2500 usdc (6 decimals) per eth
+        let gas_cost_wei = 10_u128.pow(15);
+        let gas_cost_coin1 = 2_500_000;
+        assert_eq!(
+            wei_to_coin1(2 * 10_i128.pow(15), gas_cost_wei, gas_cost_coin1),
+            5_000_000
+        );
+        assert_eq!(
+            wei_to_coin1(-(10_i128.pow(15)), gas_cost_wei, gas_cost_coin1),
+            -2_500_000
+        );
+        assert_eq!(wei_to_coin1(10_i128.pow(15), 0, gas_cost_coin1), 0);
+    }
+
+    #[test]
+    fn test_trade_pnl_usd() {
+        let trade = TradePnl {
+            executed_at: "2025-07-04 19:45:23".to_owned(),
+            day: "2025-07-04".to_owned(),
+            tx_hash: "0x01".to_owned(),
+            status: true,
+            failure: None,
+            pool0: "POOL-A".to_owned(),
+            pool1: "POOL-B".to_owned(),
+            quote: Address::ZERO,
+            symbol: "USDC".to_owned(),
+            decimals: 6,
+            gross: 3_000_000,
+            gas: 500_000,
+            gas_cost_wei: 10_u128.pow(15),
+            gas_cost_coin1: 2_500_000,
+        };
+        assert_eq!(trade.net(), 2_500_000);
+        assert_eq!(trade.outcome(), "ok");
+        // 2.5 usdc is 0.001 eth, worth 3 usd when eth trades at 3000
+        assert!((trade.usd(trade.net(), 3000.0) - 3.0).abs() < 1e-9);
+        assert_eq!(format_amount(trade.net(), trade.decimals), "2.500000");
+        assert_eq!(format_amount(-trade.gas, trade.decimals), "-0.500000");
+    }
+
+    #[test]
+    #[ignore = "needs GOFI_E2E_PG_URL"]
+    fn test_usd_per_eth() {
+        let mut schema = Schema::create("report_usd");
+        let db = &mut schema.db;
+        // a mistyped or not yet indexed pool
+        assert_eq!(usd_per_eth(db, POOL0, COIN0).unwrap(), None);
+
+        db.batch_execute(&format!(
+            "INSERT INTO coins VALUES ('{eth}', 'WETH', 18), ('{usdc}', 'USDC', 6);
+             INSERT INTO pools VALUES ('{pool}', '{eth}', '{usdc}');",
+            eth = address::to_db(COIN0),
+            usdc = address::to_db(COIN1),
+            pool = address::to_db(POOL0),
+        ))
+        .unwrap();
+        assert_eq!(usd_per_eth(db, POOL0, COIN0).unwrap(), None);
+
+        // 2 eth against 5000 usdc
+        db.batch_execute(&format!(
+            "INSERT INTO blocks VALUES (1, 0);
+             INSERT INTO reserves VALUES ('{}', 1, '2000000000000000000', '5000000000');",
+            address::to_db(POOL0)
+        ))
+        .unwrap();
+        assert_eq!(usd_per_eth(db, POOL0, COIN0).unwrap(), Some(2500.0));
+        assert_eq!(usd_per_eth(db, POOL0, COIN1).unwrap(), Some(0.0004));
+    }
+}
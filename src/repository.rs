@@ -0,0 +1,213 @@
+use alloy::primitives::{Address, U256};
+
+use crate::risk::{Halt, Stats};
+use crate::screen::Screening;
+use crate::{Execution, Match, Pair, record};
+
+// the tables scan_cycle reads pairs from and records matches into. ChainDb is the real one,
+// FakeRepository keeps rows in memory for tests.
+pub trait PairRepository {
+    fn pools_count(&mut self) -> Result<i64, postgres::Error>;
+    fn pairs_with(&mut self, base_token: Address) -> Result<Vec<Pair>, postgres::Error>;
+    fn insert_opportunity(
+        &mut self,
+        r#match: &Match,
+        gas_estimate: u64,
+        gas_cost_wei: u128,
+        gas_cost_coin1: U256,
+    ) -> Result<i64, postgres::Error>;
+    fn insert_execution(
+        &mut self,
+        opportunity_id: i64,
+        execution: &Execution,
+    ) -> Result<i64, postgres::Error>;
+    fn screenings(&mut self, max_age_hours: u64) -> Result<Vec<Screening>, postgres::Error>;
+    fn insert_screening(&mut self, screening: &Screening) -> Result<i64, postgres::Error>;
+    // pnl_today is of the swabs of quote
+    fn risk_stats(&mut self, quote: Address) -> Result<Stats, postgres::Error>;
+    fn halt(&mut self) -> Result<Option<Halt>, postgres::Error>;
+    fn insert_halt(&mut self, reason: &str) -> Result<i64, postgres::Error>;
+    fn reset_halts(&mut self) -> Result<u64, postgres::Error>;
+}
+
+// the database of one chain. the indexer's tables hold that chain's market only, gofi's
+// own rows are kept apart by chain_id.
+pub struct ChainDb {
+    pub client: postgres::Client,
+    pub chain_id: u64,
+}
+
+impl PairRepository for ChainDb {
+    fn pools_count(&mut self) -> Result<i64, postgres::Error> {
+        Ok(crate::rows_count(&mut self.client, "pools"))
+    }
+
+    fn pairs_with(&mut self, base_token: Address) -> Result<Vec<Pair>, postgres::Error> {
+        let rows = crate::pairs_with(&mut self.client, base_token)?;
+        Ok(rows.iter().map(Pair::from_pair_row).collect())
+    }
+
+    fn insert_opportunity(
+        &mut self,
+        r#match: &Match,
+        gas_estimate: u64,
+        gas_cost_wei: u128,
+        gas_cost_coin1: U256,
+    ) -> Result<i64, postgres::Error> {
+        record::insert_opportunity(
+            &mut self.client,
+            self.chain_id,
+            r#match,
+            gas_estimate,
+            gas_cost_wei,
+            gas_cost_coin1,
+        )
+    }
+
+    fn insert_execution(
+        &mut self,
+        opportunity_id: i64,
+        execution: &Execution,
+    ) -> Result<i64, postgres::Error> {
+        record::insert_execution(&mut self.client, self.chain_id, opportunity_id, execution)
+    }
+
+    fn screenings(&mut self, max_age_hours: u64) -> Result<Vec<Screening>, postgres::Error> {
+        record::screenings(&mut self.client, self.chain_id, max_age_hours)
+    }
+
+    fn insert_screening(&mut self, screening: &Screening) -> Result<i64, postgres::Error> {
+        record::insert_screening(&mut self.client, self.chain_id, screening)
+    }
+
+    fn risk_stats(&mut self, quote: Address) -> Result<Stats, postgres::Error> {
+        record::risk_stats(&mut self.client, self.chain_id, quote)
+    }
+
+    fn halt(&mut self) -> Result<Option<Halt>, postgres::Error> {
+        record::halt(&mut self.client, self.chain_id)
+    }
+
+    fn insert_halt(&mut self, reason: &str) -> Result<i64, postgres::Error> {
+        record::insert_halt(&mut self.client, self.chain_id, reason)
+    }
+
+    fn reset_halts(&mut self) -> Result<u64, postgres::Error> {
+        record::reset_halts(&mut self.client, self.chain_id)
+    }
+}
+
+#[cfg(test)]
+pub use fake::FakeRepository;
+
+#[cfg(test)]
+mod fake {
+    use super::*;
+    use crate::revert;
+
+    // opportunities are (ay_in, profit) and executions (opportunity id, coin1 delta),
+    // ids are positions in the vecs. screenings never expire, every execution is from
+    // the last hour and today in the quote asked about and its pnl is its coin1 delta, halts are (reason, reset)
+    // and resumed is how many executions there were at the last reset.
+    #[derive(Default)]
+    pub struct FakeRepository {
+        pub pairs: Vec<Pair>,
+        pub opportunities: Vec<(U256, U256)>,
+        pub executions: Vec<(i64, Execution)>,
+        pub screenings: Vec<Screening>,
+        pub halts: Vec<(String, bool)>,
+        pub resumed: usize,
+    }
+
+    impl PairRepository for FakeRepository {
+        fn pools_count(&mut self) -> Result<i64, postgres::Error> {
+            Ok(self.pairs.len() as i64 * 2)
+        }
+
+        fn pairs_with(&mut self, base_token: Address) -> Result<Vec<Pair>, postgres::Error> {
+            Ok(self
+                .pairs
+                .iter()
+                .filter(|pair| pair.pool0.pool.coin0.contract_address == base_token)
+                .cloned()
+                .collect())
+        }
+
+        fn insert_opportunity(
+            &mut self,
+            r#match: &Match,
+            _gas_estimate: u64,
+            _gas_cost_wei: u128,
+            _gas_cost_coin1: U256,
+        ) -> Result<i64, postgres::Error> {
+            self.opportunities
+                .push((r#match.pool0_ay_in, r#match.profit()));
+            Ok(self.opportunities.len() as i64 - 1)
+        }
+
+        fn insert_execution(
+            &mut self,
+            opportunity_id: i64,
+            execution: &Execution,
+        ) -> Result<i64, postgres::Error> {
+            self.executions.push((opportunity_id, execution.clone()));
+            Ok(self.executions.len() as i64 - 1)
+        }
+
+        fn screenings(&mut self, _max_age_hours: u64) -> Result<Vec<Screening>, postgres::Error> {
+            Ok(self.screenings.clone())
+        }
+
+        fn insert_screening(&mut self, screening: &Screening) -> Result<i64, postgres::Error> {
+            self.screenings.push(screening.clone());
+            Ok(self.screenings.len() as i64 - 1)
+        }
+
+        fn risk_stats(&mut self, _quote: Address) -> Result<Stats, postgres::Error> {
+            let executions = &self.executions[self.resumed..];
+            let failed = |execution: &&Execution| {
+                !execution.status && execution.failure != Some(revert::Failure::Cancelled)
+            };
+            Ok(Stats {
+                trades_last_hour: executions.len() as u32,
+                consecutive_reverts: executions
+                    .iter()
+                    .map(|(_, execution)| execution)
+                    .rev()
+                    .take_while(|execution| !execution.status)
+                    .filter(failed)
+                    .count() as u32,
+                pnl_today: executions
+                    .iter()
+                    .map(|(_, execution)| i128::try_from(execution.coin1_delta).unwrap())
+                    .sum(),
+            })
+        }
+
+        fn halt(&mut self) -> Result<Option<Halt>, postgres::Error> {
+            Ok(self
+                .halts
+                .iter()
+                .rfind(|(_, reset)| !reset)
+                .map(|(reason, _)| Halt {
+                    created_at: "2026-01-01 00:00:00".to_owned(),
+                    reason: reason.clone(),
+                }))
+        }
+
+        fn insert_halt(&mut self, reason: &str) -> Result<i64, postgres::Error> {
+            self.halts.push((reason.to_owned(), false));
+            Ok(self.halts.len() as i64 - 1)
+        }
+
+        fn reset_halts(&mut self) -> Result<u64, postgres::Error> {
+            let mut count = 0;
+            for (_, reset) in self.halts.iter_mut().filter(|(_, reset)| !reset) {
+                *reset = true;
+                count += 1;
+            }
+            self.resumed = self.executions.len();
+            Ok(count)
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use alloy::{
+    primitives::Bytes,
+    sol,
+    sol_types::{SolError, decode_revert_reason},
+};
+
+// why a swab failed. the revert data of a replay at the swab's block says what the
+// contracts complained about, whether the pools had already moved before that block
+// tells a slow scan from a transaction that got in ahead of ours.
+
+// openzeppelin 5 custom errors UniSwab's safeTransferFrom can end in. the abi files
+// only carry functions.
+sol! {
+    error SafeERC20FailedOperation(address token);
+    error ERC20InsufficientAllowance(address spender, uint256 allowance, uint256 needed);
+    error ERC20InsufficientBalance(address sender, uint256 balance, uint256 needed);
+}
+
+// what eth_call says the failed transaction does at the state after its block
+#[derive(Clone, Debug, PartialEq)]
+pub enum Replay {
+    Reverted(Bytes),
+    // all of the gas limit was used
+    OutOfGas,
+    // an rpc error without revert data
+    Error(String),
+    // does not fail any more
+    Succeeded,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Failure {
+    // the pools were as we read them the block before and still the price moved
+    FrontRun,
+    // the pools moved in blocks between our read and the swab's block
+    StaleReserves,
+    Allowance,
+    Balance,
+    OutOfGas,
+    // replaced with a 0 value transfer to ourselves by nonce::send
+    Cancelled,
+    // send_swab gave up without a receipt, the swab may still have gone out
+    SendFailed,
+    Unknown,
+}
+
+impl Failure {
+    // as stored in executions.failure
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Failure::FrontRun => "front_run",
+            Failure::StaleReserves => "stale_reserves",
+            Failure::Allowance => "allowance",
+            Failure::Balance => "balance",
+            Failure::OutOfGas => "out_of_gas",
+            Failure::Cancelled => "cancelled",
+            Failure::SendFailed => "send_failed",
+            Failure::Unknown => "unknown",
+        }
+    }
+}
+
+// revert data as text, Error(string) and Panic(uint256) as solidity would say them
+pub fn decode(data: &[u8]) -> String {
+    if let Ok(error) = SafeERC20FailedOperation::abi_decode(data) {
+        return format!("SafeERC20FailedOperation({})", error.token);
+    }
+    if let Ok(error) = ERC20InsufficientAllowance::abi_decode(data) {
+        return format!(
+            "ERC20InsufficientAllowance({}, {}, {})",
+            error.spender, error.allowance, error.needed
+        );
+    }
+    if let Ok(error) = ERC20InsufficientBalance::abi_decode(data) {
+        return format!(
+            "ERC20InsufficientBalance({}, {}, {})",
+            error.sender, error.balance, error.needed
+        );
+    }
+    if data.is_empty() {
+        return "reverted without data".to_owned();
+    }
+    decode_revert_reason(data).unwrap_or_else(|| format!("0x{}", hex::encode(data)))
+}
+
+// the failure class of a replay, reserves_moved when the pools at the block before
+// the swab's were not the ones it was sized on
+pub fn classify(replay: &Replay, reserves_moved: bool) -> Failure {
+    let reason = match replay {
+        Replay::OutOfGas => return Failure::OutOfGas,
+        Replay::Reverted(data) => decode(data),
+        Replay::Error(message) if message.to_lowercase().contains("out of gas") => {
+            return Failure::OutOfGas;
+        }
+        Replay::Error(message) => message.clone(),
+        // whatever made it fail was gone by the end of the block
+        Replay::Succeeded => String::new(),
+    };
+    let price_moved = reason.is_empty()
+        || [
+            "UniSwab: no profit",
+            "UniswapV2: K",
+            "INSUFFICIENT_OUTPUT_AMOUNT",
+            "INSUFFICIENT_INPUT_AMOUNT",
+            "INSUFFICIENT_LIQUIDITY",
+        ]
+        .iter()
+        .any(|needle| reason.contains(needle));
+    let lower = reason.to_lowercase();
+    if price_moved {
+        if reserves_moved {
+            Failure::StaleReserves
+        } else {
+            Failure::FrontRun
+        }
+    } else if lower.contains("allowance") || reason.starts_with("SafeERC20") {
+        Failure::Allowance
+    } else if lower.contains("balance") {
+        Failure::Balance
+    } else {
+        Failure::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{Address, U256},
+        sol_types::{Panic, Revert},
+    };
+
+    use super::*;
+
+    fn reverted(reason: &str) -> Replay {
+        Replay::Reverted(Revert::from(reason).abi_encode().into())
+    }
+
+    #[test]
+    fn test_decode() {
+        let data = Revert::from("UniswapV2: K").abi_encode();
+        assert_eq!(decode(&data), "revert: UniswapV2: K");
+        let data = Panic {
+            code: U256::from(0x11),
+        }
+        .abi_encode();
+        assert!(decode(&data).contains("overflow"), "{}", decode(&data));
+        let token = Address::with_last_byte(0xc1);
+        let data = SafeERC20FailedOperation { token }.abi_encode();
+        assert_eq!(
+            decode(&data),
+            format!("SafeERC20FailedOperation({})", token)
+        );
+        let data = ERC20InsufficientAllowance {
+            spender: Address::with_last_byte(0x5b),
+            allowance: U256::ZERO,
+            needed: U256::from(40371),
+        }
+        .abi_encode();
+        assert!(decode(&data).starts_with("ERC20InsufficientAllowance("));
+        assert!(decode(&data).ends_with(", 0, 40371)"));
+        assert_eq!(decode(&[]), "reverted without data");
+        assert_eq!(decode(&[0xde, 0xad, 0xbe, 0xef, 0x01]), "0xdeadbeef01");
+    }
+
+    #[test]
+    fn test_classify() {
+        for reason in [
+            "UniSwab: no profit",
+            "UniswapV2: K",
+            "UniswapV2: INSUFFICIENT_OUTPUT_AMOUNT",
+        ] {
+            assert_eq!(classify(&reverted(reason), false), Failure::FrontRun);
+            assert_eq!(classify(&reverted(reason), true), Failure::StaleReserves);
+        }
+        assert_eq!(classify(&Replay::Succeeded, false), Failure::FrontRun);
+        let allowance = ERC20InsufficientAllowance {
+            spender: Address::ZERO,
+            allowance: U256::ZERO,
+            needed: U256::from(1),
+        };
+        assert_eq!(
+            classify(&Replay::Reverted(allowance.abi_encode().into()), true),
+            Failure::Allowance
+        );
+        let safe = SafeERC20FailedOperation {
+            token: Address::ZERO,
+        };
+        assert_eq!(
+            classify(&Replay::Reverted(safe.abi_encode().into()), false),
+            Failure::Allowance
+        );
+        assert_eq!(
+            classify(&reverted("ERC20: transfer amount exceeds balance"), false),
+            Failure::Balance
+        );
+        assert_eq!(classify(&Replay::OutOfGas, false), Failure::OutOfGas);
+        assert_eq!(
+            classify(&Replay::Error("out of gas".to_owned()), false),
+            Failure::OutOfGas
+        );
+        assert_eq!(
+            classify(&reverted("only owner can call this"), false),
+            Failure::Unknown
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use alloy::primitives::{Address, U256, utils::format_units};
+use tracing::{error, warn};
+
+use crate::chain::Chain;
+use crate::config::Config;
+use crate::repository::PairRepository;
+use crate::rule::{self, units};
+use crate::{Coin, metrics};
+
+// limits on live trading. scan checks them before it swabs or rebalances, and a breach
+// halts trading until gofi resume. the halt is a row in the halts table so a restart does
+// not lift it. max_trade_notional is a cap on the swab amount rather than a breach.
+
+// what the recorded executions say about recent trading
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub trades_last_hour: u32,
+    // failed swabs since the last one that went through, cancels not counted
+    pub consecutive_reverts: u32,
+    // net pnl of today's swabs of one coin1, in its raw units and gas included
+    pub pnl_today: i128,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Halt {
+    pub created_at: String,
+    pub reason: String,
+}
+
+// whether scan may trade this cycle. coin1 is the coin swabs are sized and valued in.
+pub fn guard(
+    config: &Config,
+    db: &mut impl PairRepository,
+    chain: &impl Chain,
+    coin1: &Coin,
+    owner: Address,
+) -> Result<bool, postgres::Error> {
+    if let Some(halt) = db.halt()? {
+        warn!(
+            since = halt.created_at,
+            reason = halt.reason,
+            "halted, see gofi resume"
+        );
+        metrics::HALTED
+            .with_label_values(&[&config.chain_id.to_string()])
+            .set(1);
+        return Ok(false);
+    }
+    let eth = match config.min_eth_balance {
+        Some(_) => match chain.eth_balance(owner) {
+            Ok(eth) => Some(eth),
+            Err(err) => {
+                warn!(%err, "not trading, eth balance unknown");
+                return Ok(false);
+            }
+        },
+        None => None,
+    };
+    let stats = db.risk_stats(coin1.contract_address)?;
+    match breach(config, &stats, eth, coin1) {
+        Some(reason) => {
+            error!(reason, "halting, see gofi resume");
+            db.insert_halt(&reason)?;
+            metrics::HALTED
+                .with_label_values(&[&config.chain_id.to_string()])
+                .set(1);
+            Ok(false)
+        }
+        None => {
+            metrics::HALTED
+                .with_label_values(&[&config.chain_id.to_string()])
+                .set(0);
+            Ok(true)
+        }
+    }
+}
+
+// the first limit stats and the eth balance are at, as the halt reason
+pub fn breach(config: &Config, stats: &Stats, eth: Option<U256>, coin1: &Coin) -> Option<String> {
+    if stats.consecutive_reverts >= config.max_consecutive_reverts {
+        return Some(format!(
+            "{} swabs failed in a row, max_consecutive_reverts is {}",
+            stats.consecutive_reverts, config.max_consecutive_reverts
+        ));
+    }
+    if let Some(max) = config.max_trades_per_hour
+        && stats.trades_last_hour >= max
+    {
+        return Some(format!(
+            "{} swabs in the last hour, max_trades_per_hour is {}",
+            stats.trades_last_hour, max
+        ));
+    }
+    if let Some(max) = config.max_daily_loss {
+        let loss = U256::from(stats.pnl_today.min(0).unsigned_abs());
+        let decimals = rule::decimals(coin1.decimals);
+        // any loss breaches a limit coin1's decimals can't express
+        let max_loss = decimals.and_then(|decimals| units(max, decimals));
+        if !loss.is_zero() && max_loss.is_none_or(|max_loss| loss >= max_loss) {
+            return Some(format!(
+                "lost {} {} today, max_daily_loss is {}",
+                decimals
+                    .and_then(|decimals| format_units(loss, decimals).ok())
+                    .unwrap_or_else(|| loss.to_string()),
+                coin1.symbol,
+                max
+            ));
+        }
+    }
+    if let (Some(min), Some(eth)) = (config.min_eth_balance, eth)
+        && units(min, 18).is_none_or(|min| eth < min)
+    {
+        return Some(format!(
+            "eth balance {} is below min_eth_balance {}",
+            format_units(eth, 18).unwrap(),
+            min
+        ));
+    }
+    None
+}
+
+// the most coin1 one swab may put in
+pub fn max_amount(config: &Config, coin1: &Coin) -> U256 {
+    rule::limits(config, coin1).map_or(U256::MAX, |limits| limits.max_amount)
+}
+
+// lift the halt, gofi resume
+pub fn resume(config: &Config, db: &mut impl PairRepository) -> Result<(), postgres::Error> {
+    match db.halt()? {
+        Some(halt) => {
+            db.reset_halts()?;
+            metrics::HALTED
+                .with_label_values(&[&config.chain_id.to_string()])
+                .set(0);
+            println!("resumed, halted since {}: {}", halt.created_at, halt.reason);
+        }
+        None => println!("not halted"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::FakeChain;
+    use crate::fixture::{COIN0, COIN1, coin};
+    use crate::repository::FakeRepository;
+
+    const ME: Address = Address::repeat_byte(0xee);
+
+    fn config(extra: &str) -> Config {
+        serde_yaml::from_str(&format!(
+            "geth_url: http://localhost:8545
+pg_url: postgres://localhost/gofi
+eth_priv_key: 0000000000000000000000000000000000000000000000000000000000000001
+uniswab: 000000000000000000000000000000000000005b
+preferred_base_token: {COIN0}
+preferred_coin_token: {COIN1}
+minimum_out: 0
+tx_gas: 300000
+{extra}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_breach() {
+        let usdc = coin(COIN1, "USDC", 6);
+        let stats = Stats::default();
+        // one failed swab halts unless configured otherwise
+        assert_eq!(breach(&config(""), &stats, None, &usdc), None);
+        let failed = Stats {
+            consecutive_reverts: 1,
+            ..Stats::default()
+        };
+        let reason = breach(&config(""), &failed, None, &usdc).unwrap();
+        assert!(reason.contains("1 swabs failed in a row"), "{}", reason);
+        let config = config(
+            "max_consecutive_reverts: 3
+max_trades_per_hour: 10
+max_daily_loss: 50
+min_eth_balance: 0.1",
+        );
+        assert_eq!(breach(&config, &failed, None, &usdc), None);
+        let busy = Stats {
+            trades_last_hour: 10,
+            ..Stats::default()
+        };
+        assert!(
+            breach(&config, &busy, None, &usdc)
+                .unwrap()
+                .contains("last hour")
+        );
+        let losing = Stats {
+            pnl_today: -49_999999,
+            ..Stats::default()
+        };
+        assert_eq!(breach(&config, &losing, None, &usdc), None);
+        let lost = Stats {
+            pnl_today: -50_000000,
+            ..Stats::default()
+        };
+        assert_eq!(
+            breach(&config, &lost, None, &usdc).unwrap(),
+            "lost 50.000000 USDC today, max_daily_loss is 50"
+        );
+        let eth = U256::from(10).pow(U256::from(17));
+        assert_eq!(breach(&config, &stats, Some(eth), &usdc), None);
+        assert!(
+            breach(&config, &stats, Some(eth - U256::from(1)), &usdc)
+                .unwrap()
+                .contains("below min_eth_balance")
+        );
+        assert_eq!(max_amount(&config, &usdc), U256::MAX);
+        let capped = self::config("max_trade_notional: 1000.5");
+        assert_eq!(max_amount(&capped, &usdc), U256::from(1000_500000));
+    }
+
+    #[test]
+    fn test_guard() {
+        let usdc = coin(COIN1, "USDC", 6);
+        let config = config("min_eth_balance: 0.1");
+        let mut chain = FakeChain::default();
+        chain.set_balance(Address::ZERO, ME, U256::from(10).pow(U256::from(18)));
+        let mut db = FakeRepository::default();
+        assert!(guard(&config, &mut db, &chain, &usdc, ME).unwrap());
+        assert!(db.halts.is_empty());
+
+        // the halt outlives the breach until it is reset
+        chain.set_balance(Address::ZERO, ME, U256::ZERO);
+        assert!(!guard(&config, &mut db, &chain, &usdc, ME).unwrap());
+        chain.set_balance(Address::ZERO, ME, U256::from(10).pow(U256::from(18)));
+        assert!(!guard(&config, &mut db, &chain, &usdc, ME).unwrap());
+        assert_eq!(db.halts.len(), 1);
+        resume(&config, &mut db).unwrap();
+        assert!(guard(&config, &mut db, &chain, &usdc, ME).unwrap());
+        assert_eq!(db.halts.len(), 1);
+    }
+}
@@ -0,0 +1,201 @@
+use alloy::primitives::{
+    Address, U256,
+    utils::{ParseUnits, parse_units},
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+
+use crate::config::Config;
+use crate::{Coin, address};
+
+// which pairs of preferred_base_token and a quote token (coin1) scan swabs, and the limits
+// each gets. the first rule whose quote matches applies, quote: any matches every token the
+// base token has pools with. without rules only preferred_coin_token is swabbed.
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PairRule {
+    pub quote: Quote,
+    // profit after gas a swab has to clear, in quote token units. the top level one if unset.
+    #[serde(default)]
+    pub minimum_out: Option<f64>,
+    // the most quote one swab may put in, in token units. the top level one if unset.
+    #[serde(default)]
+    pub max_trade_notional: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quote {
+    Any,
+    Token(Address),
+}
+
+impl Quote {
+    pub fn matches(&self, token: Address) -> bool {
+        match self {
+            Quote::Any => true,
+            Quote::Token(quote) => *quote == token,
+        }
+    }
+}
+
+impl Serialize for Quote {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Quote::Any => serializer.serialize_str("any"),
+            Quote::Token(token) => serializer.serialize_str(&address::to_db(*token)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Quote {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Quote, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if text == "any" {
+            return Ok(Quote::Any);
+        }
+        address::parse(&text)
+            .map(Quote::Token)
+            .map_err(de::Error::custom)
+    }
+}
+
+// a rule's limits with the top level ones filled in, in raw units of the quote token
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    pub minimum_out: U256,
+    // U256::MAX when there is no cap
+    pub max_amount: U256,
+}
+
+// the limits quote is swabbed under. None when no rule takes it or they don't fit its
+// decimals, which any token can claim.
+pub fn limits(config: &Config, quote: &Coin) -> Option<Limits> {
+    let (minimum_out, max_trade_notional) = if config.pairs.is_empty() {
+        (quote.contract_address == config.preferred_coin_token)
+            .then_some((config.minimum_out, config.max_trade_notional))?
+    } else {
+        config
+            .pairs
+            .iter()
+            .find(|rule| rule.quote.matches(quote.contract_address))
+            .map(|rule| {
+                (
+                    rule.minimum_out.unwrap_or(config.minimum_out),
+                    rule.max_trade_notional.or(config.max_trade_notional),
+                )
+            })?
+    };
+    let decimals = decimals(quote.decimals)?;
+    Some(Limits {
+        minimum_out: units(minimum_out, decimals)?,
+        max_amount: match max_trade_notional {
+            Some(max) => units(max, decimals)?,
+            None => U256::MAX,
+        },
+    })
+}
+
+// a token's decimals when parse_units can take them
+pub fn decimals(decimals: i32) -> Option<u8> {
+    u8::try_from(decimals)
+        .ok()
+        .filter(|&decimals| decimals <= 77)
+}
+
+// None when amount is negative or does not fit a U256 with decimals
+pub fn units(amount: f64, decimals: u8) -> Option<U256> {
+    match parse_units(&amount.to_string(), decimals).ok()? {
+        ParseUnits::U256(units) => Some(units),
+        ParseUnits::I256(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{COIN0, COIN1, coin};
+
+    fn config(yaml: &str) -> Config {
+        serde_yaml::from_str(&format!(
+            "geth_url: http://localhost:8545
+pg_url: postgres://localhost/gofi
+uniswab: 000000000000000000000000000000000000005b
+preferred_base_token: {COIN0}
+preferred_coin_token: {COIN1}
+minimum_out: 0.5
+max_trade_notional: 100
+tx_gas: 300000
+{yaml}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_limits() {
+        let other = coin(Address::with_last_byte(0xc2), "OTHER", 18);
+        let usdc = coin(COIN1, "USDC", 6);
+        let config = self::config("");
+        assert_eq!(
+            limits(&config, &usdc),
+            Some(Limits {
+                minimum_out: U256::from(500000),
+                max_amount: U256::from(100_000000)
+            })
+        );
+        assert_eq!(limits(&config, &other), None);
+
+        // preferred_coin_token is only a default
+        let config = self::config(&format!(
+            "pairs:
+- quote: {}
+  minimum_out: 2
+- quote: any
+  max_trade_notional: 10
+",
+            other.contract_address
+        ));
+        assert_eq!(
+            limits(&config, &other),
+            Some(Limits {
+                minimum_out: U256::from(2) * U256::from(10).pow(U256::from(18)),
+                max_amount: U256::from(100) * U256::from(10).pow(U256::from(18))
+            })
+        );
+        let any = limits(&config, &usdc).unwrap();
+        assert_eq!(any.max_amount, U256::from(10_000000));
+        assert_eq!(any.minimum_out, U256::from(500000));
+        let config = self::config(&format!("pairs:\n- quote: {}\n", other.contract_address));
+        assert_eq!(limits(&config, &usdc), None);
+    }
+
+    #[test]
+    fn test_limits_unusable_decimals() {
+        let config = self::config("pairs:\n- quote: any\n");
+        for decimals in [-1, 78, 256, i32::MAX] {
+            assert_eq!(limits(&config, &coin(COIN1, "BAD", decimals)), None);
+        }
+        assert!(limits(&config, &coin(COIN1, "WIDE", 77)).is_none());
+        let config = self::config("pairs:\n- quote: any\n  minimum_out: 0\n");
+        let config = Config {
+            max_trade_notional: None,
+            ..config
+        };
+        assert!(limits(&config, &coin(COIN1, "WIDE", 77)).is_some());
+        assert_eq!(units(-1.0, 6), None);
+        assert_eq!(units(1e60, 18), None);
+    }
+
+    #[test]
+    fn test_quote() {
+        let quote = |text: &str| serde_yaml::from_str::<Quote>(text);
+        assert_eq!(quote("any").unwrap(), Quote::Any);
+        assert_eq!(
+            quote("'0x00000000000000000000000000000000000000c1'").unwrap(),
+            Quote::Token(COIN1)
+        );
+        assert!(quote("anything").is_err());
+        assert_eq!(
+            serde_yaml::to_string(&Quote::Token(COIN1)).unwrap(),
+            "00000000000000000000000000000000000000c1\n"
+        );
+    }
+}
@@ -0,0 +1,427 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::Mutex;
+
+use alloy::{
+    primitives::{Address, B256, Bytes, U256, keccak256},
+    providers::Provider,
+    rpc::types::state::StateOverridesBuilder,
+    sol,
+};
+use tracing::warn;
+
+use crate::config::Config;
+use crate::contracts::ERC20;
+use crate::repository::PairRepository;
+use crate::{Pair, metrics, revert};
+
+// tokens that tax transfers or refuse sells make get_y_out's predictions wrong. a pool is
+// screened by buying its coin0 with coin1 and selling it straight back in one eth_call on
+// a fork: the Screener contract's runtime code goes to an unused address along with a
+// coin1 balance, and what arrived is compared with what the reserves promised. verdicts
+// are kept in token_screenings and scan skips pools, and coins, that did not pass.
+
+sol! {
+    #[sol(rpc)]
+    contract Screener {
+        function screen(address pair_addr, address quote, uint256 amountIn, uint16 feeBps)
+            external
+            returns (uint256[4] memory amounts);
+    }
+}
+
+// nothing lives here, the screener's code is put there for the call
+const SCREENER: Address = Address::repeat_byte(0x5c);
+// mappings to try when looking for a token's balances
+const BALANCE_SLOTS: u64 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    Ok,
+    // taxes transfers beyond max_transfer_tax_bps
+    Taxed,
+    // the round trip reverted or sold for nothing
+    Honeypot,
+}
+
+impl Verdict {
+    // as stored in token_screenings.verdict
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Ok => "ok",
+            Verdict::Taxed => "taxed",
+            Verdict::Honeypot => "honeypot",
+        }
+    }
+
+    pub fn from_db(verdict: &str) -> Verdict {
+        match verdict {
+            "ok" => Verdict::Ok,
+            "taxed" => Verdict::Taxed,
+            _ => Verdict::Honeypot,
+        }
+    }
+}
+
+// what a buy and sell through a pool did
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoundTrip {
+    // (expected, received) of the buy and of the sell
+    Done {
+        buy: (U256, U256),
+        sell: (U256, U256),
+    },
+    // the decoded revert
+    Reverted(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Screening {
+    pub pool: Address,
+    pub token: Address,
+    pub verdict: Verdict,
+    pub buy_tax_bps: Option<u32>,
+    pub sell_tax_bps: Option<u32>,
+    pub reason: Option<String>,
+}
+
+pub trait Screen {
+    // buy the pool's other token with amount_in of quote and sell it back, the pool
+    // charging fee_bps
+    fn round_trip(
+        &self,
+        pool: Address,
+        quote: Address,
+        amount_in: U256,
+        fee_bps: u8,
+    ) -> Result<RoundTrip, String>;
+}
+
+// the pairs whose pools and coin0 passed screening. pools without a verdict younger than
+// screen_ttl_hours are screened first, a pool that could not be screened is skipped.
+pub fn filter(
+    config: &Config,
+    db: &mut impl PairRepository,
+    screener: &dyn Screen,
+    pairs: Vec<Pair>,
+) -> Result<Vec<Pair>, postgres::Error> {
+    let mut screenings = db
+        .screenings(config.screen_ttl_hours)?
+        .into_iter()
+        .map(|screening| (screening.pool, screening))
+        .collect::<HashMap<Address, Screening>>();
+    for snapshot in pairs.iter().flat_map(|pair| [&pair.pool0, &pair.pool1]) {
+        let pool = &snapshot.pool;
+        if screenings.contains_key(&pool.contract_address) {
+            continue;
+        }
+        // small enough not to move the price much
+        let amount_in = (snapshot.reserve.y / U256::from(1000)).max(U256::from(1));
+        let round_trip = match screener.round_trip(
+            pool.contract_address,
+            pool.coin1.contract_address,
+            amount_in,
+            pool.fee_bps,
+        ) {
+            Ok(round_trip) => round_trip,
+            Err(err) => {
+                warn!(pool = %pool.contract_address, %err, "screening failed");
+                continue;
+            }
+        };
+        let screening = judge(
+            pool.contract_address,
+            pool.coin0.contract_address,
+            &round_trip,
+            config.max_transfer_tax_bps,
+        );
+        metrics::SCREENINGS
+            .with_label_values(&[&config.chain_id.to_string(), screening.verdict.as_str()])
+            .inc();
+        if screening.verdict != Verdict::Ok {
+            warn!(
+                pool = %pool.contract_address,
+                token = %pool.coin0.symbol,
+                verdict = screening.verdict.as_str(),
+                buy_tax_bps = screening.buy_tax_bps,
+                sell_tax_bps = screening.sell_tax_bps,
+                reason = screening.reason,
+                "pool flagged"
+            );
+        }
+        db.insert_screening(&screening)?;
+        screenings.insert(pool.contract_address, screening);
+    }
+    // a coin that failed in one pool fails in all of them
+    let flagged = screenings
+        .values()
+        .filter(|screening| screening.verdict != Verdict::Ok)
+        .map(|screening| screening.token)
+        .collect::<HashSet<Address>>();
+    let passed = |pool: &crate::Pool| {
+        screenings
+            .get(&pool.contract_address)
+            .is_some_and(|screening| screening.verdict == Verdict::Ok)
+            && !flagged.contains(&pool.coin0.contract_address)
+    };
+    Ok(pairs
+        .into_iter()
+        .filter(|pair| passed(&pair.pool0.pool) && passed(&pair.pool1.pool))
+        .collect())
+}
+
+pub fn judge(pool: Address, token: Address, round_trip: &RoundTrip, max_tax_bps: u32) -> Screening {
+    match round_trip {
+        RoundTrip::Reverted(reason) => Screening {
+            pool,
+            token,
+            verdict: Verdict::Honeypot,
+            buy_tax_bps: None,
+            sell_tax_bps: None,
+            reason: Some(reason.clone()),
+        },
+        RoundTrip::Done { buy, sell } => {
+            let (buy_tax_bps, sell_tax_bps) = (tax_bps(*buy), tax_bps(*sell));
+            let (verdict, reason) = if sell.1.is_zero() {
+                (Verdict::Honeypot, Some("sold for nothing".to_owned()))
+            } else if buy_tax_bps.max(sell_tax_bps) > max_tax_bps {
+                (Verdict::Taxed, None)
+            } else {
+                (Verdict::Ok, None)
+            };
+            Screening {
+                pool,
+                token,
+                verdict,
+                buy_tax_bps: Some(buy_tax_bps),
+                sell_tax_bps: Some(sell_tax_bps),
+                reason,
+            }
+        }
+    }
+}
+
+// how much of expected did not arrive
+fn tax_bps((expected, received): (U256, U256)) -> u32 {
+    if expected.is_zero() || received >= expected {
+        return 0;
+    }
+    ((expected - received) * U256::from(10000) / expected).to::<u32>()
+}
+
+// screens against a fork, eg anvil --fork-url, so nothing is ever sent to the real chain
+pub struct Fork<P> {
+    provider: P,
+    code: Bytes,
+    // token -> storage slot of the screener's balance
+    slots: Mutex<HashMap<Address, B256>>,
+}
+
+impl<P: Provider> Fork<P> {
+    // code_path is the Screener.bin-runtime make -C ethereum builds
+    pub fn new(provider: P, code_path: &str) -> Result<Fork<P>, String> {
+        let hex = fs::read_to_string(code_path)
+            .map_err(|err| format!("{}: {}. run make -C ethereum", code_path, err))?;
+        let code = hex::decode(hex.trim().trim_start_matches("0x"))
+            .map_err(|err| format!("{}: {}", code_path, err))?;
+        Ok(Fork {
+            provider,
+            code: code.into(),
+            slots: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // the slot holding the screener's balance of token. a marker is written to where
+    // solidity, keccak(holder . n), or vyper, keccak(n . holder), keep mapping n until
+    // balanceOf reads it back.
+    async fn balance_slot(&self, token: Address) -> Result<B256, String> {
+        if let Some(slot) = self.slots.lock().unwrap().get(&token) {
+            return Ok(*slot);
+        }
+        let marker = U256::from(0x5c5c5c5c_u64);
+        let erc20 = ERC20::new(token, &self.provider);
+        for n in 0..BALANCE_SLOTS {
+            let n = B256::from(U256::from(n));
+            for slot in [
+                keccak256([SCREENER.into_word(), n].concat()),
+                keccak256([n, SCREENER.into_word()].concat()),
+            ] {
+                let state = StateOverridesBuilder::default()
+                    .with_state_diff(token, [(slot, B256::from(marker))])
+                    .build();
+                let balance =
+                    metrics::rpc("balanceOf", erc20.balanceOf(SCREENER).state(state).call())
+                        .await
+                        .map_err(|err| format!("{} balanceOf: {}", token, err))?;
+                if balance == marker {
+                    self.slots.lock().unwrap().insert(token, slot);
+                    return Ok(slot);
+                }
+            }
+        }
+        Err(format!(
+            "{}: balances not in any of the first {} mappings",
+            token, BALANCE_SLOTS
+        ))
+    }
+}
+
+impl<P: Provider> Screen for Fork<P> {
+    #[tokio::main]
+    async fn round_trip(
+        &self,
+        pool: Address,
+        quote: Address,
+        amount_in: U256,
+        fee_bps: u8,
+    ) -> Result<RoundTrip, String> {
+        let slot = self.balance_slot(quote).await?;
+        let state = StateOverridesBuilder::default()
+            .with_code(SCREENER, self.code.clone())
+            .with_state_diff(quote, [(slot, B256::from(amount_in))])
+            .build();
+        let screener = Screener::new(SCREENER, &self.provider);
+        let call = screener
+            .screen(pool, quote, amount_in, fee_bps as u16)
+            .state(state);
+        match metrics::rpc("eth_call", call.call()).await {
+            Ok([buy_expected, buy_received, sell_expected, sell_received]) => Ok(RoundTrip::Done {
+                buy: (buy_expected, buy_received),
+                sell: (sell_expected, sell_received),
+            }),
+            Err(err) => match err.as_revert_data() {
+                Some(data) => Ok(RoundTrip::Reverted(revert::decode(&data))),
+                None => Err(err.to_string()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::fixture::{COIN0, COIN1, POOL0, POOL1, PairBuilder, coin};
+    use crate::repository::FakeRepository;
+
+    const POOL2: Address = Address::with_last_byte(0xa2);
+    const POOL3: Address = Address::with_last_byte(0xb2);
+    const TAXED: Address = Address::with_last_byte(0xcf);
+
+    // pools not in round_trips revert, screened has every pool asked about
+    #[derive(Default)]
+    struct FakeScreener {
+        round_trips: HashMap<Address, RoundTrip>,
+        screened: RefCell<Vec<(Address, Address, U256)>>,
+    }
+
+    impl Screen for FakeScreener {
+        fn round_trip(
+            &self,
+            pool: Address,
+            quote: Address,
+            amount_in: U256,
+            _fee_bps: u8,
+        ) -> Result<RoundTrip, String> {
+            self.screened.borrow_mut().push((pool, quote, amount_in));
+            self.round_trips
+                .get(&pool)
+                .cloned()
+                .ok_or_else(|| "fork down".to_owned())
+        }
+    }
+
+    fn done(buy: (u64, u64), sell: (u64, u64)) -> RoundTrip {
+        RoundTrip::Done {
+            buy: (U256::from(buy.0), U256::from(buy.1)),
+            sell: (U256::from(sell.0), U256::from(sell.1)),
+        }
+    }
+
+    #[test]
+    fn test_judge() {
+        let judge = |round_trip: &RoundTrip| judge(POOL0, COIN0, round_trip, 100);
+        let screening = judge(&done((10000, 10000), (9000, 9000)));
+        assert_eq!(screening.verdict, Verdict::Ok);
+        assert_eq!(
+            (screening.buy_tax_bps, screening.sell_tax_bps),
+            (Some(0), Some(0))
+        );
+        // 1% is still fine, 5% on the sell is not
+        assert_eq!(
+            judge(&done((10000, 9900), (9000, 9000))).verdict,
+            Verdict::Ok
+        );
+        let screening = judge(&done((10000, 10000), (9000, 8550)));
+        assert_eq!(screening.verdict, Verdict::Taxed);
+        assert_eq!(screening.sell_tax_bps, Some(500));
+        assert_eq!(
+            judge(&done((10000, 10000), (9000, 0))).verdict,
+            Verdict::Honeypot
+        );
+        let screening = judge(&RoundTrip::Reverted("revert: TRANSFER_FAILED".to_owned()));
+        assert_eq!(screening.verdict, Verdict::Honeypot);
+        assert_eq!(screening.reason.as_deref(), Some("revert: TRANSFER_FAILED"));
+        for verdict in [Verdict::Ok, Verdict::Taxed, Verdict::Honeypot] {
+            assert_eq!(Verdict::from_db(verdict.as_str()), verdict);
+        }
+    }
+
+    fn config() -> Config {
+        serde_yaml::from_str(&format!(
+            "geth_url: http://localhost:8545
+pg_url: postgres://localhost/gofi
+eth_priv_key: 0000000000000000000000000000000000000000000000000000000000000001
+uniswab: 000000000000000000000000000000000000005b
+preferred_base_token: {COIN0}
+preferred_coin_token: {COIN1}
+minimum_out: 0
+tx_gas: 300000
+max_transfer_tax_bps: 100"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_filter() {
+        let taxed = PairBuilder::new()
+            .coins(coin(TAXED, "TAX", 18), coin(COIN1, "USDONC", 18))
+            .addresses(POOL2, POOL3)
+            .build();
+        let pairs = vec![PairBuilder::new().build(), taxed];
+        let mut screener = FakeScreener::default();
+        screener
+            .round_trips
+            .insert(POOL0, done((1000, 1000), (990, 990)));
+        screener
+            .round_trips
+            .insert(POOL1, done((1000, 1000), (990, 990)));
+        screener
+            .round_trips
+            .insert(POOL2, done((1000, 1000), (990, 990)));
+        screener
+            .round_trips
+            .insert(POOL3, done((1000, 900), (890, 890)));
+        let mut db = FakeRepository::default();
+        let kept = filter(&config(), &mut db, &screener, pairs.clone()).unwrap();
+        // POOL2 passed on its own but shares its coin with POOL3
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].pool0.pool.contract_address, POOL0);
+        assert_eq!(db.screenings.len(), 4);
+        // 0.1% of the coin1 reserve
+        assert_eq!(
+            screener.screened.borrow()[0],
+            (POOL0, COIN1, U256::from(210))
+        );
+
+        // verdicts are reused, a pool that cannot be screened is skipped
+        let screener = FakeScreener::default();
+        let kept = filter(&config(), &mut db, &screener, pairs.clone()).unwrap();
+        assert_eq!(kept.len(), 1);
+        assert!(screener.screened.borrow().is_empty());
+        let kept = filter(&config(), &mut FakeRepository::default(), &screener, pairs).unwrap();
+        assert!(kept.is_empty());
+        assert_eq!(screener.screened.borrow().len(), 4);
+    }
+}
@@ -0,0 +1,363 @@
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::Command;
+
+use alloy::{
+    consensus::{SignableTransaction, TxEnvelope},
+    eips::Decodable2718,
+    network::{EthereumWallet, TxSigner},
+    primitives::{Address, Signature},
+    signers::local::PrivateKeySigner,
+};
+use serde_json::{Value, json};
+
+use crate::config::Config;
+
+// the key that signs swabs. a raw eth_priv_key or an eth_keystore file, web3 secret
+// storage v3 as geth, clef and cast wallet write it, decrypt to a local key. eth_signer is the ipc socket of clef or anything else that speaks its
+// account_signTransaction, so the key never has to be in gofi's memory.
+pub enum Signer {
+    Local(PrivateKeySigner),
+    Remote(Clef),
+}
+
+impl Signer {
+    pub fn load(config: &Config) -> Result<Signer, String> {
+        if let Some(socket) = &config.eth_signer {
+            return Ok(Signer::Remote(Clef::connect(
+                socket,
+                config.eth_signer_account,
+            )?));
+        }
+        let secret = match (&config.eth_priv_key, &config.eth_keystore) {
+            (Some(priv_key), _) => hex::decode(priv_key).unwrap(),
+            (None, Some(path)) => return keystore(path, &password(path)?).map(Signer::Local),
+            (None, None) => return Err("no eth_priv_key, eth_keystore or eth_signer".to_owned()),
+        };
+        PrivateKeySigner::from_slice(&secret)
+            .map(Signer::Local)
+            .map_err(|err| err.to_string())
+    }
+
+    pub fn address(&self) -> Address {
+        match self {
+            Signer::Local(signer) => signer.address(),
+            Signer::Remote(clef) => clef.address,
+        }
+    }
+
+    pub fn public_key_bytes(&self) -> [u8; 20] {
+        self.address().into_array()
+    }
+
+    pub fn public_key(&self) -> String {
+        hex::encode(self.public_key_bytes())
+    }
+
+    pub fn wallet(self) -> EthereumWallet {
+        match self {
+            Signer::Local(signer) => EthereumWallet::new(signer),
+            Signer::Remote(clef) => EthereumWallet::new(clef),
+        }
+    }
+}
+
+const PASSWORD_ENV: &str = "GOFI_KEYSTORE_PASSWORD";
+
+fn keystore(path: &str, password: &str) -> Result<PrivateKeySigner, String> {
+    PrivateKeySigner::decrypt_keystore(path, password).map_err(|err| format!("{}: {}", path, err))
+}
+
+// GOFI_KEYSTORE_PASSWORD, otherwise asked for on the terminal with echo off
+fn password(path: &str) -> Result<String, String> {
+    if let Ok(password) = env::var(PASSWORD_ENV) {
+        return Ok(password);
+    }
+    let no_tty = |err: std::io::Error| format!("{} not set and no terminal, {}", PASSWORD_ENV, err);
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(no_tty)?;
+    let stty = |setting: &str| -> Result<(), std::io::Error> {
+        Command::new("stty")
+            .arg(setting)
+            .stdin(File::open("/dev/tty")?)
+            .status()
+            .map(|_| ())
+    };
+    write!(tty, "password for {}: ", path).map_err(no_tty)?;
+    stty("-echo").map_err(no_tty)?;
+    let mut line = String::new();
+    let read = BufReader::new(&tty).read_line(&mut line);
+    stty("echo").map_err(no_tty)?;
+    writeln!(tty).map_err(no_tty)?;
+    read.map_err(no_tty)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+pub struct Clef {
+    socket: PathBuf,
+    address: Address,
+}
+
+impl Clef {
+    // account is which of the signer's accounts to use, the first one if not given
+    pub fn connect(socket: &str, account: Option<Address>) -> Result<Clef, String> {
+        let mut clef = Clef {
+            socket: PathBuf::from(socket),
+            address: Address::ZERO,
+        };
+        let accounts =
+            serde_json::from_value::<Vec<Address>>(clef.call("account_list", json!([]))?)
+                .map_err(|err| format!("account_list: {}", err))?;
+        clef.address = match account {
+            Some(account) if accounts.contains(&account) => account,
+            Some(account) => return Err(format!("{} has no account {}", socket, account)),
+            None => *accounts
+                .first()
+                .ok_or_else(|| format!("{} has no accounts", socket))?,
+        };
+        Ok(clef)
+    }
+
+    // one request per connection, clef answers after the user approves it
+    fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let err =
+            |err: &dyn std::fmt::Display| format!("{} {}: {}", self.socket.display(), method, err);
+        let mut stream = UnixStream::connect(&self.socket).map_err(|e| err(&e))?;
+        let request = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        stream
+            .write_all(format!("{}\n", request).as_bytes())
+            .map_err(|e| err(&e))?;
+        let mut response = serde_json::Deserializer::from_reader(&stream).into_iter::<Value>();
+        let response = response
+            .next()
+            .ok_or_else(|| err(&"no response"))?
+            .map_err(|e| err(&e))?;
+        match response.get("error") {
+            Some(error) => Err(err(&error["message"].as_str().unwrap_or("error"))),
+            None => Ok(response["result"].clone()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TxSigner<Signature> for Clef {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    async fn sign_transaction(
+        &self,
+        tx: &mut dyn SignableTransaction<Signature>,
+    ) -> alloy::signers::Result<Signature> {
+        let mut args = json!({
+            "from": self.address,
+            "to": tx.to(),
+            "gas": format!("{:#x}", tx.gas_limit()),
+            "value": tx.value(),
+            "nonce": format!("{:#x}", tx.nonce()),
+            "data": tx.input(),
+            "chainId": tx.chain_id().map(|chain_id| format!("{:#x}", chain_id)),
+        });
+        if tx.is_dynamic_fee() {
+            args["maxFeePerGas"] = json!(format!("{:#x}", tx.max_fee_per_gas()));
+            args["maxPriorityFeePerGas"] = json!(format!(
+                "{:#x}",
+                tx.max_priority_fee_per_gas().unwrap_or_default()
+            ));
+        } else {
+            args["gasPrice"] = json!(format!("{:#x}", tx.gas_price().unwrap_or_default()));
+        }
+        if let Some(access_list) = tx.access_list() {
+            args["accessList"] = json!(access_list);
+        }
+        // a blocking socket is fine, the tokio::main around the send has nothing else to run
+        let result = self
+            .call("account_signTransaction", json!([args]))
+            .map_err(alloy::signers::Error::other)?;
+        let raw = result["raw"]
+            .as_str()
+            .and_then(|raw| hex::decode(raw.trim_start_matches("0x")).ok())
+            .ok_or_else(|| alloy::signers::Error::other("account_signTransaction: no raw"))?;
+        let envelope = TxEnvelope::decode_2718_exact(&raw).map_err(alloy::signers::Error::other)?;
+        let signature = *envelope.signature();
+        // the user can edit a transaction in clef before approving it
+        if signature.recover_address_from_prehash(&tx.signature_hash())? != self.address {
+            return Err(alloy::signers::Error::other(
+                "clef signed a different transaction",
+            ));
+        }
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use alloy::{
+        consensus::TxEip1559,
+        eips::Encodable2718,
+        network::TxSignerSync,
+        primitives::{U256, address},
+    };
+
+    use super::*;
+
+    // anvil account 1
+    const KEY: &str = "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d";
+    const ACCOUNT: Address = address!("0x70997970C51812dc3A010C7d01b50e0d17dc79C8");
+
+    // answers account_list and signs with KEY, plus tamper to the gas
+    fn fake_clef(socket: PathBuf, requests: usize, tamper: u64) -> thread::JoinHandle<()> {
+        let listener = UnixListener::bind(&socket).unwrap();
+        thread::spawn(move || {
+            let signer: PrivateKeySigner = KEY.parse().unwrap();
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut line = String::new();
+                BufReader::new(&stream).read_line(&mut line).unwrap();
+                let request: Value = serde_json::from_str(&line).unwrap();
+                let result = match request["method"].as_str().unwrap() {
+                    "account_list" => json!([signer.address()]),
+                    "account_signTransaction" => {
+                        let args = &request["params"][0];
+                        let quantity = |name: &str| {
+                            u128::from_str_radix(&args[name].as_str().unwrap()[2..], 16).unwrap()
+                        };
+                        let mut tx = TxEip1559 {
+                            chain_id: quantity("chainId") as u64,
+                            nonce: quantity("nonce") as u64,
+                            gas_limit: quantity("gas") as u64 + tamper,
+                            max_fee_per_gas: quantity("maxFeePerGas"),
+                            max_priority_fee_per_gas: quantity("maxPriorityFeePerGas"),
+                            to: serde_json::from_value(args["to"].clone()).unwrap(),
+                            value: serde_json::from_value(args["value"].clone()).unwrap(),
+                            input: serde_json::from_value(args["data"].clone()).unwrap(),
+                            ..TxEip1559::default()
+                        };
+                        let signature = signer.sign_transaction_sync(&mut tx).unwrap();
+                        let raw = TxEnvelope::from(tx.into_signed(signature)).encoded_2718();
+                        json!({"raw": format!("0x{}", hex::encode(raw))})
+                    }
+                    method => panic!("{}", method),
+                };
+                let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": result});
+                stream
+                    .write_all(format!("{}\n", response).as_bytes())
+                    .unwrap();
+            }
+        })
+    }
+
+    fn tx() -> TxEip1559 {
+        TxEip1559 {
+            chain_id: 31337,
+            nonce: 7,
+            gas_limit: 300000,
+            max_fee_per_gas: 2_000_000_000,
+            max_priority_fee_per_gas: 1_000_000_000,
+            to: Address::with_last_byte(0x5b).into(),
+            value: U256::ZERO,
+            input: vec![0xab, 0xcd].into(),
+            ..TxEip1559::default()
+        }
+    }
+
+    // the web3 secret storage definition's scrypt test vector, under n 1024 rather than
+    // 262144 which is far too slow unoptimized
+    const KEYSTORE: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": {"iv": "83dbcc02d8ccb40e466191a123791e0e"},
+            "ciphertext": "01a05c7f05b697274227d8bd0825a6caa89967e24643426c0fcfa2fb663052d7",
+            "kdf": "scrypt",
+            "kdfparams": {
+                "dklen": 32,
+                "n": 1024,
+                "p": 1,
+                "r": 8,
+                "salt": "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+            },
+            "mac": "d60a6540bbdeaa746e4c7b4359c74e4bb0b679bedce5b4d129ad96150d200274"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+
+    #[test]
+    fn test_keystore() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keystore.json");
+        std::fs::write(&path, KEYSTORE).unwrap();
+        let path = path.to_str().unwrap();
+        let signer = keystore(path, "testpassword").unwrap();
+        assert_eq!(
+            hex::encode(signer.to_bytes()),
+            "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d"
+        );
+        let err = keystore(path, "testpassword2").err().unwrap();
+        assert!(err.starts_with(path), "{}", err);
+        assert!(keystore(&format!("{}.missing", path), "testpassword").is_err());
+    }
+
+    #[tokio::main]
+    async fn sign(clef: &Clef, tx: &mut TxEip1559) -> alloy::signers::Result<Signature> {
+        clef.sign_transaction(tx).await
+    }
+
+    #[test]
+    fn test_clef() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("clef.ipc");
+        let server = fake_clef(socket.clone(), 3, 0);
+        let clef = Clef::connect(socket.to_str().unwrap(), None).unwrap();
+        assert_eq!(clef.address, ACCOUNT);
+        let mut tx = tx();
+        let signature = sign(&clef, &mut tx).unwrap();
+        assert_eq!(
+            signature
+                .recover_address_from_prehash(&tx.signature_hash())
+                .unwrap(),
+            ACCOUNT
+        );
+        let err = Clef::connect(socket.to_str().unwrap(), Some(Address::ZERO)).err();
+        assert!(err.unwrap().contains("has no account"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_clef_tampered() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("clef.ipc");
+        let server = fake_clef(socket.clone(), 2, 1);
+        let clef = Clef::connect(socket.to_str().unwrap(), Some(ACCOUNT)).unwrap();
+        let err = sign(&clef, &mut tx()).unwrap_err();
+        assert!(err.to_string().contains("different transaction"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_local() {
+        let config: Config = serde_yaml::from_str(&format!(
+            "geth_url: http://localhost:8545
+pg_url: postgres://localhost/gofi
+eth_priv_key: {KEY}
+uniswab: 000000000000000000000000000000000000005b
+preferred_base_token: 00000000000000000000000000000000000000c0
+preferred_coin_token: 00000000000000000000000000000000000000c1
+minimum_out: 0
+tx_gas: 300000"
+        ))
+        .unwrap();
+        let signer = Signer::load(&config).unwrap();
+        assert_eq!(signer.address(), ACCOUNT);
+        assert_eq!(signer.public_key(), hex::encode(ACCOUNT));
+    }
+}
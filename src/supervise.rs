@@ -0,0 +1,100 @@
+use std::any::Any;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+// runs jobs that are each meant to go on for as long as gofi does, one thread apiece.
+// the first to fail or panic is returned straight away rather than once the rest are
+// done, which with scan --every is never.
+
+pub type Job<E> = Box<dyn FnOnce() -> Result<(), E> + Send>;
+
+#[derive(Debug, PartialEq)]
+pub enum Stopped<E> {
+    Failed(usize, E),
+    // the job's index and the panic message
+    Panicked(usize, String),
+}
+
+// Ok once every job has returned Ok, otherwise the first one to stop otherwise. the
+// others are left running.
+pub fn run<E: Send + 'static>(jobs: Vec<Job<E>>) -> Result<(), Stopped<E>> {
+    let (finished, done) = mpsc::channel();
+    let mut threads = jobs
+        .into_iter()
+        .enumerate()
+        .map(|(index, job)| {
+            let finished = Finished(index, finished.clone());
+            Some(thread::spawn(move || {
+                let _finished = finished;
+                job()
+            }))
+        })
+        .collect::<Vec<_>>();
+    drop(finished);
+    for index in done {
+        match threads[index].take().unwrap().join() {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => return Err(Stopped::Failed(index, err)),
+            Err(panic) => return Err(Stopped::Panicked(index, panic_message(panic))),
+        }
+    }
+    Ok(())
+}
+
+// tells run a job's thread is ending, dropped both when it returns and when it panics
+struct Finished(usize, Sender<usize>);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        let _ = self.1.send(self.0);
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "panicked".to_owned(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a job that runs until the returned sender is dropped
+    fn forever() -> (Sender<()>, Job<String>) {
+        let (keep, wait) = mpsc::channel::<()>();
+        let job: Job<String> = Box::new(move || {
+            let _ = wait.recv();
+            Ok(())
+        });
+        (keep, job)
+    }
+
+    #[test]
+    fn test_all_done() {
+        let jobs: Vec<Job<String>> = vec![Box::new(|| Ok(())), Box::new(|| Ok(()))];
+        assert_eq!(run(jobs), Ok(()));
+        assert_eq!(run::<String>(vec![]), Ok(()));
+    }
+
+    #[test]
+    fn test_first_failure_while_others_run() {
+        let (_keep, job) = forever();
+        let jobs = vec![job, Box::new(|| Err("db gone".to_owned()))];
+        assert_eq!(run(jobs), Err(Stopped::Failed(1, "db gone".to_owned())));
+    }
+
+    #[test]
+    fn test_panic_while_others_run() {
+        let (_keep, job) = forever();
+        let jobs = vec![Box::new(|| panic!("chain {} down", 8453)), job];
+        assert_eq!(
+            run(jobs),
+            Err(Stopped::Panicked(0, "chain 8453 down".to_owned()))
+        );
+    }
+}
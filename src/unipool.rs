@@ -1,40 +1,129 @@
-use alloy::primitives::{U256, U512};
+use alloy::primitives::{U256, U512, Uint};
+
+// uniswap v2 charges 0.3% on the input amount
+pub const POOL_FEE_BASIS_POINTS: u8 = 30;
 
 // x is the product. y is the money. ay/ax < by/bx means pool a is cheaper than pool b
-pub fn optimal_ay_in(ax: u128, ay: u128, bx: u128, by: u128) -> Result<u128, String> {
-    const POOL_FEE_BASIS_POINTS: u8 = 30;
-    let (a, b, c) = reserves_to_coefficients(ax, ay, bx, by, POOL_FEE_BASIS_POINTS)?;
-    Ok(quadratic_root(a, b, c))
+pub fn optimal_ay_in(ax: U256, ay: U256, bx: U256, by: U256) -> Result<U256, String> {
+    optimal_ay_in_with_fee(ax, ay, bx, by, POOL_FEE_BASIS_POINTS)
+}
+
+pub fn optimal_ay_in_with_fee(
+    ax: U256,
+    ay: U256,
+    bx: U256,
+    by: U256,
+    fee_points: u8,
+) -> Result<U256, String> {
+    optimal_ay_in_with_fees(ax, ay, bx, by, fee_points, fee_points)
+}
+
+// pools of two dexes, a_fee_points charged by pool a and b_fee_points by pool b
+pub fn optimal_ay_in_with_fees(
+    ax: U256,
+    ay: U256,
+    bx: U256,
+    by: U256,
+    a_fee_points: u8,
+    b_fee_points: u8,
+) -> Result<U256, String> {
+    let (a, b, c) = reserves_to_coefficients_with_fees(ax, ay, bx, by, a_fee_points, b_fee_points)?;
+    let root = quadratic_root(a, b, c)?;
+    // the root is the real optimum rounded down and get_y_out rounds down twice more,
+    // so the whole-unit profit is flat and a little noisy around it. start from the
+    // root, or from the least input that still buys one of the ax amounts next to
+    // the root's, then climb to a whole-unit local maximum.
+    let one = U256::from(1);
+    // ay_out - ay_in, offset by U256::MAX to stay unsigned
+    let profit = |ay_in: U256| {
+        swab_out_with_fees(ay_in, ax, ay, bx, by, a_fee_points, b_fee_points)
+            .ok()
+            .map(|(_, ay_out)| U512::from(ay_out) + U512::from(U256::MAX) - U512::from(ay_in))
+    };
+    let (adx, _) = swab_out_with_fees(root, ax, ay, bx, by, a_fee_points, b_fee_points)?;
+    let mut best = (root, profit(root));
+    for dx in [adx.saturating_sub(one), adx, adx.saturating_add(one)] {
+        if let Some(ay_in) = get_x_in_with_fee(dx, ay, ax, a_fee_points) {
+            let candidate = profit(ay_in);
+            if candidate > best.1 || (candidate == best.1 && ay_in < best.0) {
+                best = (ay_in, candidate);
+            }
+        }
+    }
+    // every step gains at least one unit, the cap only guards against bad input
+    for _ in 0..1000 {
+        let (ay_in, best_profit) = best;
+        let up = ay_in.saturating_add(one);
+        let down = ay_in.saturating_sub(one);
+        if profit(up) > best_profit {
+            best = (up, profit(up));
+        } else if profit(down) > best_profit {
+            best = (down, profit(down));
+        } else {
+            break;
+        }
+    }
+    Ok(best.0)
 }
 
+// the coefficients multiply four reserves together, so they are computed in U512.
+// on-chain reserves are u112 and cannot overflow. anything larger is an error.
 pub fn reserves_to_coefficients(
-    ax: u128,
-    ay: u128,
-    bx: u128,
-    by: u128,
+    ax: U256,
+    ay: U256,
+    bx: U256,
+    by: U256,
     fee_points: u8,
-) -> Result<(U256, U256, U256), String> {
-    let fee_points_magnitude = U256::from(10000);
-    let fee = fee_points_magnitude - U256::from(fee_points);
-    // k = (1-f)*xb + (1-f)^2*xa
+) -> Result<(U512, U512, U512), String> {
+    reserves_to_coefficients_with_fees(ax, ay, bx, by, fee_points, fee_points)
+}
+
+pub fn reserves_to_coefficients_with_fees(
+    ax: U256,
+    ay: U256,
+    bx: U256,
+    by: U256,
+    a_fee_points: u8,
+    b_fee_points: u8,
+) -> Result<(U512, U512, U512), String> {
+    let overflow = || format!("(a,b,c) overflow for reserves {} {} {} {}", ax, ay, bx, by);
+    let (ax, ay, bx, by) = (
+        U512::from(ax),
+        U512::from(ay),
+        U512::from(bx),
+        U512::from(by),
+    );
+    let mul = |terms: &[U512]| {
+        terms
+            .iter()
+            .try_fold(U512::from(1), |product, term| product.checked_mul(*term))
+            .ok_or_else(overflow)
+    };
+    // everything is multiplied through by m^4, m = 10000 basis points, so the
+    // fee fractions stay whole numbers. the root of the quadratic is unchanged.
+    let m = U512::from(10000);
+    let m_squared = m * m;
+    let a_fee = m - U512::from(a_fee_points);
+    let b_fee = m - U512::from(b_fee_points);
+    // k = (1-fa)*xb + (1-fa)*(1-fb)*xa, times m^2
     // k is always positive
-    let k1 = U256::from(bx) * fee / (U256::from(fee_points_magnitude));
-    let k2 = fee.pow(U256::from(2)) * U256::from(ax) / (fee_points_magnitude.pow(U256::from(2)));
-    let k = k1 + k2;
+    let k = mul(&[a_fee, m, bx])?
+        .checked_add(mul(&[a_fee, b_fee, ax])?)
+        .ok_or_else(overflow)?;
     // a = k^2
     // a is always positive
-    let a = k.pow(U256::from(2));
+    let a = mul(&[k, k])?;
     // b = 2k*ya*xb
     // b is always positive
-    let b = k * U256::from(2) * U256::from(ay) * U256::from(bx);
-    // c = (ya*xb)^2 - (1-f)^2*xa*ya*xb*yb
+    let b = mul(&[k, U512::from(2), ay, bx, m_squared])?;
+    // c = (ya*xb)^2 - (1-fa)*(1-fb)*xa*ya*xb*yb
     // c1 is always positive
-    let c1 = U256::from(ay) * U256::from(ay) * U256::from(bx) * U256::from(bx);
-    let c21 = U256::from(ax) * U256::from(ay) * U256::from(bx) * U256::from(by);
+    let c1 = mul(&[ay, ay, bx, bx, m_squared, m_squared])?;
+    let c21 = mul(&[ax, ay, bx, by])?;
     // c2 is always positive
-    let c2 = fee.pow(U256::from(2)) * c21 / fee_points_magnitude.pow(U256::from(2));
-    if c1 > c2 {
-        if c1 < c21 {
+    let c2 = mul(&[a_fee, b_fee, m_squared, c21])?;
+    if c1 >= c2 {
+        if c1 < mul(&[c21, m_squared, m_squared])? {
             Err("(a,b,c) no arb after fee".to_owned())
         } else {
             Err("(a,b,c) no arb.".to_owned())
@@ -45,45 +134,112 @@ pub fn reserves_to_coefficients(
     }
 }
 
-pub fn quadratic_root(pos_a: U256, pos_b: U256, neg_c: U256) -> u128 {
-    let a = U512::from(pos_a);
-    let b = U512::from(pos_b);
-    let c = U512::from(neg_c);
-    // delta = b^2 - 4ac
-    let d1 = b.pow(U512::from(2));
+// wide enough for b^2 with b built from u112 reserves
+pub type U1024 = Uint<1024, 16>;
+
+// delta = b^2 - 4ac, with c negative (expressed here as UINT)
+pub fn discriminant(pos_a: U512, pos_b: U512, neg_c: U512) -> U1024 {
+    let a = U1024::from(pos_a);
+    let b = U1024::from(pos_b);
+    let c = U1024::from(neg_c);
     // d1 is always positive
-    let d2 = U512::from(4) * a * c;
+    let d1 = b * b;
     // d2 is always negative because c is always negative (expressed here as UINT)
     // delta is always postiive because c is always negative
     // -neg + pos = pos + "neg":  b^2 + 4ac
-    let delta = d1 + d2;
+    let d2 = U1024::from(4) * a * c;
+    d1 + d2
+}
+
+pub fn quadratic_root(pos_a: U512, pos_b: U512, neg_c: U512) -> Result<U256, String> {
+    if pos_a.is_zero() {
+        return Err("(a,b,c) a is zero".to_owned());
+    }
+    let delta = discriminant(pos_a, pos_b, neg_c);
+    let a = U1024::from(pos_a);
+    let b = U1024::from(pos_b);
     // -b +- sqrt(delta) / 2a
     // sqrt(delta) is always larger than b because delta is b^2 plus a value
     //
-    let root = (delta.root(2).saturating_sub(b)) / (U512::from(2) * a);
-    println!(
-        "+a {} ({}) +b {} ({}) -c {} ({}) -> {} ",
-        a,
-        a.log2(),
-        b,
-        b.log2(),
-        c,
-        c.log2(),
-        root,
+    let root = (delta.root(2).saturating_sub(b)) / (U1024::from(2) * a);
+    tracing::debug!(
+        %pos_a,
+        a_log2 = pos_a.log2(),
+        %pos_b,
+        b_log2 = pos_b.log2(),
+        %neg_c,
+        c_log2 = neg_c.log2(),
+        %root,
+        "quadratic root"
     );
-    root.saturating_to::<u128>()
+    U256::checked_from_limbs_slice(root.as_limbs())
+        .ok_or_else(|| format!("quadratic root {} does not fit in 256 bits", root))
 }
 
-pub fn get_y_out(dx: u128, x: u128, y: u128) -> u128 {
-    // uniswap v1 paper: (997 * dx * y) / (1000 * x + 997 * dx)
-    let big = (U256::from(997) * U256::from(dx) * U256::from(y))
-        / (U256::from(1000) * U256::from(x) + U256::from(997) * U256::from(dx));
-    big.saturating_to::<u128>()
+pub fn get_y_out(dx: U256, x: U256, y: U256) -> Result<U256, String> {
+    get_y_out_with_fee(dx, x, y, POOL_FEE_BASIS_POINTS)
+}
+
+pub fn get_y_out_with_fee(dx: U256, x: U256, y: U256, fee_points: u8) -> Result<U256, String> {
+    // uniswap v1 paper: (997 * dx * y) / (1000 * x + 997 * dx), here in basis points
+    let overflow = || format!("get_y_out overflow for dx {} x {} y {}", dx, x, y);
+    let fee = U256::from(10000 - fee_points as u64);
+    let dx_with_fee = fee.checked_mul(dx).ok_or_else(overflow)?;
+    let numerator = dx_with_fee.checked_mul(y).ok_or_else(overflow)?;
+    let denominator = U256::from(10000)
+        .checked_mul(x)
+        .and_then(|x| x.checked_add(dx_with_fee))
+        .ok_or_else(overflow)?;
+    if denominator.is_zero() {
+        return Err("get_y_out from an empty pool".to_owned());
+    }
+    Ok(numerator / denominator)
+}
+
+// least dx that makes get_y_out return at least dy. None when the pool does not hold dy.
+pub fn get_x_in_with_fee(dy: U256, x: U256, y: U256, fee_points: u8) -> Option<U256> {
+    if dy >= y {
+        return None;
+    }
+    // dx*f*(y - dy) >= dy*m*x
+    let fee = U512::from(10000 - fee_points as u64);
+    let need = U512::from(dy) * U512::from(10000) * U512::from(x);
+    let per_dx = fee * U512::from(y - dy);
+    let dx = need.div_ceil(per_dx);
+    U256::checked_from_limbs_slice(dx.as_limbs())
+}
+
+// buy ax in pool a with ay_in, sell it in pool b. returns (ax out of a, ay out of b)
+pub fn swab_out(
+    ay_in: U256,
+    ax: U256,
+    ay: U256,
+    bx: U256,
+    by: U256,
+    fee_points: u8,
+) -> Result<(U256, U256), String> {
+    swab_out_with_fees(ay_in, ax, ay, bx, by, fee_points, fee_points)
+}
+
+pub fn swab_out_with_fees(
+    ay_in: U256,
+    ax: U256,
+    ay: U256,
+    bx: U256,
+    by: U256,
+    a_fee_points: u8,
+    b_fee_points: u8,
+) -> Result<(U256, U256), String> {
+    let adx = get_y_out_with_fee(ay_in, ay, ax, a_fee_points)?;
+    let ady = get_y_out_with_fee(adx, bx, by, b_fee_points)?;
+    Ok((adx, ady))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloy::primitives::I256;
+    use proptest::prelude::*;
 
     #[test]
     fn test_quadratic_root() {
@@ -93,27 +249,55 @@ mod tests {
         // a 278237260324 b 48739336800000000 -c 2421143007360000000000
         // wolfram alpha: x≈-215543 x≈40371
 
-        let a = U256::from_str_radix("278237260324", 10).unwrap();
-        let b = U256::from_str_radix("48739336800000000", 10).unwrap();
-        let c = U256::from_str_radix("2421143007360000000000", 10).unwrap();
-        let root = quadratic_root(a, b, c);
-        assert_eq!(root, 40371);
+        let a = U512::from_str_radix("278237260324", 10).unwrap();
+        let b = U512::from_str_radix("48739336800000000", 10).unwrap();
+        let c = U512::from_str_radix("2421143007360000000000", 10).unwrap();
+        let root = quadratic_root(a, b, c).unwrap();
+        assert_eq!(root, U256::from(40371));
 
         // a 7010956849340041661775550609684450681 b 2719085318207604654461411506480024329673136 -c 1695220225124043972868953930979927881452999219
         // wolfram alpha: x≈-388456 x≈622.45
-        let a = U256::from_str_radix("7010956849340041661775550609684450681", 10).unwrap();
-        let b = U256::from_str_radix("2719085318207604654461411506480024329673136", 10).unwrap();
-        let c = U256::from_str_radix("1695220225124043972868953930979927881452999219", 10).unwrap();
-        let root = quadratic_root(a, b, c);
-        assert_eq!(root, 622);
+        let a = U512::from_str_radix("7010956849340041661775550609684450681", 10).unwrap();
+        let b = U512::from_str_radix("2719085318207604654461411506480024329673136", 10).unwrap();
+        let c = U512::from_str_radix("1695220225124043972868953930979927881452999219", 10).unwrap();
+        let root = quadratic_root(a, b, c).unwrap();
+        assert_eq!(root, U256::from(622));
+
+        assert!(quadratic_root(U512::ZERO, b, c).is_err());
     }
 
     #[test]
     fn test_get_y_out() {
-        let dx = 10;
-        let x = 100;
-        let y = 50;
-        assert_eq!(get_y_out(dx, x, y), 4)
+        let dx = U256::from(10);
+        let x = U256::from(100);
+        let y = U256::from(50);
+        assert_eq!(get_y_out(dx, x, y), Ok(U256::from(4)));
+        assert!(get_y_out(U256::ZERO, U256::ZERO, y).is_err());
+        assert!(get_y_out(U256::MAX, x, y).is_err());
+    }
+
+    #[test]
+    fn test_get_y_out_with_fee() {
+        let n = U256::from;
+        assert_eq!(
+            get_y_out_with_fee(n(10), n(100), n(50), 30),
+            get_y_out(n(10), n(100), n(50))
+        );
+        assert_eq!(get_y_out_with_fee(n(10), n(100), n(50), 0), Ok(n(4))); // 500/110
+        assert_eq!(get_y_out_with_fee(n(1000), n(1000), n(1000), 0), Ok(n(500)));
+        assert_eq!(
+            get_y_out_with_fee(n(1000), n(1000), n(1000), 100),
+            Ok(n(497))
+        );
+    }
+
+    #[test]
+    fn test_swab_out() {
+        let n = U256::from;
+        let (adx, ady) =
+            swab_out(n(40371), n(310000), n(210000), n(220000), n(320000), 30).unwrap();
+        assert_eq!(Ok(adx), get_y_out(n(40371), n(210000), n(310000)));
+        assert_eq!(ady - n(40371), n(18608));
     }
 
     #[test]
@@ -138,10 +322,13 @@ mod tests {
             ((ay * 100 / ax) + ((by * 100 / bx) - (ay * 100 / ax)) / 2) as f64 / 100.0,
         );
 
-        let ay_in = optimal_ay_in(ax, ay, bx, by).unwrap();
+        let n = U256::from;
+        let ay_in = optimal_ay_in(n(ax), n(ay), n(bx), n(by))
+            .unwrap()
+            .to::<u128>();
         assert_eq!(ay_in, 40371, "ay_in");
 
-        let s1_adx = get_y_out(ay_in, ay, ax);
+        let s1_adx = get_y_out(n(ay_in), n(ay), n(ax)).unwrap().to::<u128>();
         println!(
             "p1 sale {} s1_adx {} / ay_in {}",
             (ay_in * 100 / s1_adx) as f64 / 100.0,
@@ -157,7 +344,7 @@ mod tests {
             s1_ay,
             s1_ax * s1_ay
         );
-        let s2_ady = get_y_out(s1_adx, bx, by);
+        let s2_ady = get_y_out(n(s1_adx), n(bx), n(by)).unwrap().to::<u128>();
         println!(
             "p2 sale {} s1_adx {} / s2_ady {}",
             (s2_ady * 100 / s1_adx) as f64 / 100.0,
@@ -181,26 +368,185 @@ mod tests {
 
     #[test]
     fn test_reserves_to_coefficients() {
-        //winner: 1.5432USDT profit:0.0286USDT p0:cbc5bde09fb89220e961415d2098b40860fd352a #2025-07-04 19:45:23 UTC p1:5b8fbba724afc16bee3eb0a4af9953fd023dcb09 #2025-07-03 06:01:23
-        //winner p0: cbc5bde09fb89220e961415d2098b40860fd352a r0: 98203032335537373 r1: 242910566 block: 22848029 2025-07-04 19:45:23 UTC
-        //winner p1: 5b8fbba724afc16bee3eb0a4af9953fd023dcb09 r0: 50774084797862325 r1: 131079784 block: 22836777 2025-07-03 06:01:23 UTC
+        // the winner behind these used to be noted here, it is now
+        // tests/fixtures/weth_usdt_2025-07-04.yaml
 
         let fee_points = 30;
-        let ax = 310000;
-        let ay = 210000;
-        let bx = 220000;
-        let by = 320000;
+        let ax = U256::from(310000);
+        let ay = U256::from(210000);
+        let bx = U256::from(220000);
+        let by = U256::from(320000);
         let (a, b, c) = reserves_to_coefficients(ax, ay, bx, by, fee_points).unwrap();
-        assert_eq!(a, U256::from_str_radix("278237260324", 10).unwrap(), "a");
+        // scaled by 10000^4 so the fee does not round k down
+        assert_eq!(
+            a,
+            U512::from_str_radix("2782380937461841000000000000", 10).unwrap(),
+            "a"
+        );
         assert_eq!(
             b,
-            U256::from_str_radix("48739336800000000", 10).unwrap(),
+            U512::from_str_radix("487394097960000000000000000000000", 10).unwrap(),
             "b"
         );
         assert_eq!(
             c,
-            U256::from_str_radix("2421143007360000000000", 10).unwrap(),
+            U512::from_str_radix("24211430073600000000000000000000000000", 10).unwrap(),
             "c"
         );
+        assert_eq!(quadratic_root(a, b, c), Ok(U256::from(40371)));
+
+        // reserves past 128 bits used to overflow U256
+        let big = U256::from(2).pow(U256::from(111));
+        let (_, _, c) = reserves_to_coefficients(big, big, big, big * U256::from(2), 30).unwrap();
+        assert!(c > U512::ZERO);
+        assert!(reserves_to_coefficients(U256::MAX, U256::MAX, U256::MAX, U256::MAX, 30).is_err());
+    }
+
+    #[test]
+    fn test_tiny_reserves() {
+        // k = (1-f)*bx + (1-f)^2*ax with the fee fractions rounded first came out as
+        // 1 here rather than 1.99, and the root as 13999 with 485249 profit
+        let n = U256::from;
+        let (ax, ay, bx, by) = (n(2), n(100), n(1), n(1000000));
+        let ay_in = optimal_ay_in(ax, ay, bx, by).unwrap();
+        let (_, ay_out) = swab_out(ay_in, ax, ay, bx, by, POOL_FEE_BASIS_POINTS).unwrap();
+        // buying whole units of x, 101 in is worth more than the root's 4689
+        assert_eq!((ay_in, ay_out - ay_in), (n(101), n(499147)));
+        // and as zero here, which made a zero and the root fail
+        let (a, b, c) = reserves_to_coefficients(n(1), n(1), n(1), n(2), 1).unwrap();
+        assert!(quadratic_root(a, b, c).is_ok());
+    }
+
+    #[test]
+    fn test_whole_unit_optimum() {
+        // the rounded root made less than 3 units under it
+        let n = |digits: &str| U256::from_str_radix(digits, 10).unwrap();
+        let ax = n("1432330274910032384973092832514644");
+        let ay = n("2363219701409569361942051805872693");
+        let bx = n("22548164");
+        let by = n("5192296858534827628530496329220095");
+        let ay_in = optimal_ay_in(ax, ay, bx, by).unwrap();
+        let profit = |ay_in: U256| {
+            let (_, ay_out) = swab_out(ay_in, ax, ay, bx, by, POOL_FEE_BASIS_POINTS).unwrap();
+            ay_out - ay_in
+        };
+        assert_eq!(ay_in, n("440829505380645943178"));
+        assert!(profit(n("440829505380645943181")) < profit(ay_in));
+        assert!(profit(ay_in) >= profit(ay_in + U256::from(1)));
+        assert!(profit(ay_in) >= profit(ay_in - U256::from(1)));
+    }
+
+    #[test]
+    fn test_optimal_ay_in_with_fees() {
+        let n = U256::from;
+        let (ax, ay, bx, by) = (n(310000), n(210000), n(220000), n(320000));
+        assert_eq!(
+            optimal_ay_in_with_fees(ax, ay, bx, by, 30, 30),
+            optimal_ay_in(ax, ay, bx, by)
+        );
+        // a cheaper second pool is worth putting more through
+        let ay_in = optimal_ay_in_with_fees(ax, ay, bx, by, 30, 5).unwrap();
+        assert!(ay_in > n(40371), "{}", ay_in);
+        let profit = |ay_in: U256| {
+            let (_, ady) = swab_out_with_fees(ay_in, ax, ay, bx, by, 30, 5).unwrap();
+            ady - ay_in
+        };
+        assert!(profit(ay_in) > n(18608));
+        assert!(profit(ay_in) >= profit(ay_in + n(1)));
+        assert!(profit(ay_in) >= profit(ay_in - n(1)));
+    }
+
+    // uniswap v2 reserves are uint112
+    const U112_MAX: u128 = (1 << 112) - 1;
+
+    fn u112() -> impl Strategy<Value = U256> {
+        prop_oneof![
+            1..=U112_MAX,
+            1..=1_000_000_000_u128,
+            Just(1),
+            Just(U112_MAX),
+        ]
+        .prop_map(U256::from)
+    }
+
+    // UniSwab.sol getAmountOut, with solidity's checked uint256 arithmetic
+    fn get_amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256) -> Option<U256> {
+        let amount_in_with_fee = amount_in.checked_mul(U256::from(997))?;
+        let numerator = amount_in_with_fee.checked_mul(reserve_out)?;
+        let denominator = reserve_in
+            .checked_mul(U256::from(1000))?
+            .checked_add(amount_in_with_fee)?;
+        if denominator > U256::ZERO {
+            Some(numerator / denominator)
+        } else {
+            Some(U256::ZERO)
+        }
+    }
+
+    fn profit(ay_in: U256, ax: U256, ay: U256, bx: U256, by: U256) -> I256 {
+        let (_, ady) = swab_out(ay_in, ax, ay, bx, by, POOL_FEE_BASIS_POINTS).unwrap();
+        I256::try_from(ady).unwrap() - I256::try_from(ay_in).unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn prop_optimal_beats_neighbours(ax in u112(), ay in u112(), bx in u112(), by in u112()) {
+            // list the cheaper pool first so most cases have an arb
+            let (ax, ay, bx, by) = if U512::from(ay) * U512::from(bx) > U512::from(by) * U512::from(ax) {
+                (bx, by, ax, ay)
+            } else {
+                (ax, ay, bx, by)
+            };
+            let ay_in = optimal_ay_in(ax, ay, bx, by);
+            prop_assume!(ay_in.is_ok());
+            let ay_in = ay_in.unwrap();
+            let best = profit(ay_in, ax, ay, bx, by);
+            prop_assert!(best >= profit(ay_in + U256::from(1), ax, ay, bx, by));
+            if ay_in > U256::ZERO {
+                prop_assert!(best >= profit(ay_in - U256::from(1), ax, ay, bx, by));
+            }
+        }
+
+        #[test]
+        fn prop_get_y_out_matches_uniswab(dx in u112(), x in u112(), y in u112()) {
+            prop_assert_eq!(Some(get_y_out(dx, x, y).unwrap()), get_amount_out(dx, x, y));
+        }
+
+        #[test]
+        fn prop_k_never_decreases(dx in u112(), x in u112(), y in u112()) {
+            let dy = get_y_out(dx, x, y).unwrap();
+            prop_assert!(dy < y);
+            let k = U512::from(x) * U512::from(y);
+            let k_after = U512::from(x + dx) * U512::from(y - dy);
+            prop_assert!(k_after >= k);
+        }
+
+        #[test]
+        fn prop_no_coefficients_without_profit(
+            ax in u112(), ay in u112(), bx in u112(), by in u112(), ay_in in u112()
+        ) {
+            // an arb exists when the first unit in comes back as more than one unit:
+            // (1-f)^2 * (ax/ay) * (by/bx) > 1
+            let fee = U512::from(10000 - POOL_FEE_BASIS_POINTS as u64);
+            let profitable = fee * fee * U512::from(ax) * U512::from(by)
+                > U512::from(10000_u64.pow(2)) * U512::from(ay) * U512::from(bx);
+            let coefficients = reserves_to_coefficients(ax, ay, bx, by, POOL_FEE_BASIS_POINTS);
+            prop_assert_eq!(coefficients.is_ok(), profitable);
+            if !profitable {
+                prop_assert!(profit(ay_in, ax, ay, bx, by) <= I256::ZERO);
+            }
+        }
+
+        #[test]
+        fn prop_extreme_reserves_do_not_panic(
+            ax in u112(), ay in u112(), bx in u112(), by in u112(), fee_points in any::<u8>()
+        ) {
+            if let Ok((a, b, c)) = reserves_to_coefficients(ax, ay, bx, by, fee_points) {
+                let ay_in = quadratic_root(a, b, c).unwrap();
+                prop_assert!(swab_out(ay_in, ax, ay, bx, by, fee_points).is_ok());
+            }
+            let _ = optimal_ay_in_with_fee(ax, ay, bx, by, fee_points);
+            let _ = swab_out(U256::from(U112_MAX), ax, ay, bx, by, fee_points);
+        }
     }
 }
@@ -0,0 +1,387 @@
+// the deploy_uniswap.sh + deploy_swab.sh + swab.sh scenario against a throwaway anvil.
+//
+// needs anvil on the PATH, the compiled contracts (make -C ethereum) and a postgres url
+// in GOFI_E2E_PG_URL. tables are created in a fresh schema that is dropped afterwards.
+//
+//   GOFI_E2E_PG_URL=postgres://localhost/gofi cargo test --test e2e -- --ignored
+
+#![allow(clippy::too_many_arguments)] // sol! generated bindings
+
+use std::fs;
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use alloy::{
+    network::TransactionBuilder,
+    primitives::{Address, Bytes, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::TransactionRequest,
+    signers::local::PrivateKeySigner,
+    sol,
+    sol_types::SolValue,
+};
+use postgres::{Client, NoTls};
+
+sol!(
+    #[sol(rpc)]
+    UniswapV2Pair,
+    "sol-abi/UniswapV2Pair.json"
+);
+sol!(
+    #[sol(rpc)]
+    ERC20,
+    "sol-abi/ERC20.json"
+);
+sol!(
+    #[sol(rpc)]
+    UniswapV2Factory,
+    "sol-abi/uniswap-v2-factory.json"
+);
+sol! {
+    #[sol(rpc)]
+    interface UsDon {
+        function mint(address to, uint256 amount) external;
+    }
+}
+
+// anvil's first two dev accounts, hat1 and hat2 in the shell scripts
+const HAT1_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+const HAT2_KEY: &str = "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d";
+const MINT: u128 = 1105340744425943157500;
+// (token0, token1) reserves, pool0 is the cheaper one
+const POOL0_RESERVES: (u64, u64) = (310000, 210000);
+const POOL1_RESERVES: (u64, u64) = (220000, 320000);
+// unipool::test_optimal_ay_in
+const AY_IN: u64 = 40371;
+const PROFIT: u64 = 18608;
+
+struct Anvil {
+    child: Child,
+    url: String,
+}
+
+impl Anvil {
+    fn spawn() -> Anvil {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        // the test tokens stand in for weth, so gas priced in coin1 would swamp the
+        // profit. a zero base fee with no minimum tip makes eth_gasPrice zero.
+        let child = Command::new("anvil")
+            .args(["--port", &port.to_string()])
+            .args([
+                "--block-base-fee-per-gas",
+                "0",
+                "--disable-min-priority-fee",
+            ])
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|err| panic!("anvil: {}. install foundry to run this test", err));
+        Anvil {
+            child,
+            url: format!("http://127.0.0.1:{}", port),
+        }
+    }
+}
+
+impl Drop for Anvil {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+struct Schema {
+    db: Client,
+    name: String,
+    url: String,
+}
+
+impl Schema {
+    fn create(pg_url: &str) -> Schema {
+        let mut db = Client::connect(pg_url, NoTls).unwrap();
+        let name = format!("gofi_e2e_{}", std::process::id());
+        db.batch_execute(&format!(
+            "DROP SCHEMA IF EXISTS {name} CASCADE;
+             CREATE SCHEMA {name};
+             SET search_path TO {name};
+             CREATE TABLE coins (contract_address VARCHAR PRIMARY KEY, symbol VARCHAR NOT NULL, decimals INTEGER NOT NULL);
+             CREATE TABLE pools (contract_address VARCHAR PRIMARY KEY, token0 VARCHAR NOT NULL, token1 VARCHAR NOT NULL);
+             CREATE TABLE blocks (number INTEGER PRIMARY KEY, timestamp INTEGER NOT NULL);
+             CREATE TABLE reserves (contract_address VARCHAR NOT NULL, block_number INTEGER NOT NULL, x VARCHAR NOT NULL, y VARCHAR NOT NULL);"
+        ))
+        .unwrap();
+        let separator = if pg_url.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}options=-csearch_path%3D{}", pg_url, separator, name);
+        Schema { db, name, url }
+    }
+}
+
+impl Drop for Schema {
+    fn drop(&mut self) {
+        let _ = self
+            .db
+            .batch_execute(&format!("DROP SCHEMA IF EXISTS {} CASCADE", self.name));
+    }
+}
+
+// addresses are stored the way the indexer writes them, lowercase hex without 0x
+fn db_address(address: Address) -> String {
+    hex::encode(address)
+}
+
+fn bytecode(name: &str) -> Bytes {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("ethereum/artifacts")
+        .join(format!("{}.bin", name));
+    let hex = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("{}: {}. run make -C ethereum", path.display(), err));
+    hex::decode(hex.trim()).unwrap().into()
+}
+
+async fn deploy<P: Provider>(provider: &P, code: Bytes) -> Address {
+    let tx = TransactionRequest::default().with_deploy_code(code);
+    provider
+        .send_transaction(tx)
+        .await
+        .unwrap()
+        .get_receipt()
+        .await
+        .unwrap()
+        .contract_address
+        .unwrap()
+}
+
+async fn wait_for_rpc<P: Provider>(provider: &P) {
+    let start = Instant::now();
+    while provider.get_block_number().await.is_err() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "anvil did not start"
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+// createPair, fund it with token0/token1 and mint the liquidity to hat1, as in deploy_uniswap.sh
+async fn pool<P: Provider>(
+    provider: &P,
+    factory: &UniswapV2Factory::UniswapV2FactoryInstance<&P>,
+    tokens: (Address, Address),
+    reserves: (u64, u64),
+    hat1: Address,
+) -> Address {
+    factory
+        .createPair(tokens.0, tokens.1)
+        .send()
+        .await
+        .unwrap()
+        .get_receipt()
+        .await
+        .unwrap();
+    let address = factory.getPair(tokens.0, tokens.1).call().await.unwrap();
+    for (token, amount) in [(tokens.0, reserves.0), (tokens.1, reserves.1)] {
+        ERC20::new(token, provider)
+            .transfer(address, U256::from(amount))
+            .send()
+            .await
+            .unwrap()
+            .get_receipt()
+            .await
+            .unwrap();
+    }
+    let pair = UniswapV2Pair::new(address, provider);
+    pair.mint(hat1)
+        .send()
+        .await
+        .unwrap()
+        .get_receipt()
+        .await
+        .unwrap();
+    address
+}
+
+// what the indexer would have written for the current block
+async fn seed<P: Provider>(provider: &P, db: &mut Client, pools: [Address; 2]) {
+    let block = provider
+        .get_block_by_number(Default::default())
+        .await
+        .unwrap()
+        .unwrap();
+    let number = block.header.number as i32;
+    db.execute(
+        "INSERT INTO blocks (number, timestamp) VALUES ($1, $2)",
+        &[&number, &(block.header.timestamp as i32)],
+    )
+    .unwrap();
+    for address in pools {
+        let pair = UniswapV2Pair::new(address, provider);
+        let token0 = pair.token0().call().await.unwrap();
+        let token1 = pair.token1().call().await.unwrap();
+        for token in [token0, token1] {
+            let erc20 = ERC20::new(token, provider);
+            let symbol = erc20.symbol().call().await.unwrap();
+            let decimals = erc20.decimals().call().await.unwrap();
+            db.execute(
+                "INSERT INTO coins (contract_address, symbol, decimals) VALUES ($1, $2, $3)
+                 ON CONFLICT DO NOTHING",
+                &[&db_address(token), &symbol, &(decimals as i32)],
+            )
+            .unwrap();
+        }
+        db.execute(
+            "INSERT INTO pools (contract_address, token0, token1) VALUES ($1, $2, $3)",
+            &[
+                &db_address(address),
+                &db_address(token0),
+                &db_address(token1),
+            ],
+        )
+        .unwrap();
+        let reserves = pair.getReserves().call().await.unwrap();
+        db.execute(
+            "INSERT INTO reserves (contract_address, block_number, x, y) VALUES ($1, $2, $3, $4)",
+            &[
+                &db_address(address),
+                &number,
+                &reserves._reserve0.to_string(),
+                &reserves._reserve1.to_string(),
+            ],
+        )
+        .unwrap();
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs anvil, compiled contracts and GOFI_E2E_PG_URL"]
+async fn test_swab_realizes_predicted_profit() {
+    let pg_url = std::env::var("GOFI_E2E_PG_URL").expect("GOFI_E2E_PG_URL");
+    let mut schema = Schema::create(&pg_url);
+    let anvil = Anvil::spawn();
+
+    let hat1_signer: PrivateKeySigner = HAT1_KEY.parse().unwrap();
+    let hat2_signer: PrivateKeySigner = HAT2_KEY.parse().unwrap();
+    let hat1 = hat1_signer.address();
+    let hat2 = hat2_signer.address();
+    let url = anvil.url.parse().unwrap();
+    let hat1_provider = ProviderBuilder::new()
+        .wallet(hat1_signer)
+        .connect_http(anvil.url.parse().unwrap());
+    let hat2_provider = ProviderBuilder::new().wallet(hat2_signer).connect_http(url);
+    wait_for_rpc(&hat1_provider).await;
+
+    // deploy_uniswap.sh
+    deploy(&hat1_provider, bytecode("WETH9")).await;
+    let usdona = deploy(&hat1_provider, bytecode("UsDonA")).await;
+    let usdonc = deploy(&hat1_provider, bytecode("UsDonC")).await;
+    for token in [usdona, usdonc] {
+        for to in [hat1, hat2] {
+            UsDon::new(token, &hat1_provider)
+                .mint(to, U256::from(MINT))
+                .send()
+                .await
+                .unwrap()
+                .get_receipt()
+                .await
+                .unwrap();
+        }
+    }
+    let mut factory_code = bytecode("UniswapV2Factory").to_vec();
+    factory_code.extend(hat1.abi_encode());
+    let factory = deploy(&hat1_provider, factory_code.into()).await;
+    let factory = UniswapV2Factory::new(factory, &hat1_provider);
+    // the pair sorts its tokens by address. fund by that order so reserve0 is always 310000.
+    let tokens = if usdona < usdonc {
+        (usdona, usdonc)
+    } else {
+        (usdonc, usdona)
+    };
+    let pool0 = pool(&hat1_provider, &factory, tokens, POOL0_RESERVES, hat1).await;
+    let pool1 = pool(&hat1_provider, &factory, tokens, POOL1_RESERVES, hat1).await;
+
+    // deploy_swab.sh
+    let uniswab = deploy(&hat2_provider, bytecode("UniSwab")).await;
+    for token in [tokens.0, tokens.1] {
+        ERC20::new(token, &hat2_provider)
+            .approve(uniswab, U256::from(u128::MAX))
+            .send()
+            .await
+            .unwrap()
+            .get_receipt()
+            .await
+            .unwrap();
+    }
+
+    seed(&hat1_provider, &mut schema.db, [pool0, pool1]).await;
+
+    let coin1 = ERC20::new(tokens.1, &hat2_provider);
+    let coin1_start = coin1.balanceOf(hat2).call().await.unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("config.yaml"),
+        format!(
+            "geth_url: {}
+pg_url: {}
+eth_priv_key: {}
+uniswab: {}
+chain_id: 31337
+preferred_base_token: {}
+preferred_coin_token: {}
+minimum_out: 0
+tx_gas: 300000
+exclude_addresses: []
+",
+            anvil.url,
+            schema.url,
+            HAT2_KEY,
+            db_address(uniswab),
+            db_address(tokens.0),
+            db_address(tokens.1),
+        ),
+    )
+    .unwrap();
+    // only the config.yaml above, not whatever GOFI_ overrides the shell has
+    let mut gofi = Command::new(env!("CARGO_BIN_EXE_gofi"));
+    for (name, _) in std::env::vars().filter(|(name, _)| name.starts_with("GOFI_")) {
+        gofi.env_remove(name);
+    }
+    let output = gofi.arg("scan").current_dir(dir.path()).output().unwrap();
+    assert!(
+        output.status.success(),
+        "gofi scan failed\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let coin1_end = coin1.balanceOf(hat2).call().await.unwrap();
+    assert_eq!(
+        coin1_end - coin1_start,
+        U256::from(PROFIT),
+        "on-chain profit"
+    );
+
+    let opportunity = schema
+        .db
+        .query_one(
+            "SELECT ay_in, profit, chain_id FROM opportunities WHERE pool0 = $1",
+            &[&db_address(pool0)],
+        )
+        .unwrap();
+    assert_eq!(opportunity.get::<_, String>("ay_in"), AY_IN.to_string());
+    assert_eq!(opportunity.get::<_, String>("profit"), PROFIT.to_string());
+    assert_eq!(opportunity.get::<_, i64>("chain_id"), 31337);
+    let execution = schema
+        .db
+        .query_one("SELECT status, coin1_delta FROM executions", &[])
+        .unwrap();
+    assert!(execution.get::<_, bool>("status"));
+    assert_eq!(
+        execution.get::<_, String>("coin1_delta"),
+        PROFIT.to_string()
+    );
+}